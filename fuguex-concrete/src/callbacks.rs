@@ -0,0 +1,460 @@
+use std::marker::PhantomData;
+
+use fugue::bytes::Order;
+use fugue::ir::Address;
+
+use fuguex_hooks::types::{Error, HookAction, HookCallAction, HookOutcome, HookStepAction};
+use fuguex_machine::StepState;
+use fuguex_state::pcode;
+
+use crate::hooks::{ClonableHookConcrete, HookConcrete};
+use crate::ConcreteState;
+
+/// Values that callbacks may return: `()` to pass, an action, or a result
+/// of either.
+pub trait CallbackAction<A> {
+    fn into_action(self) -> Result<A, Error<pcode::Error>>;
+}
+
+impl<R> CallbackAction<HookAction<R>> for () {
+    fn into_action(self) -> Result<HookAction<R>, Error<pcode::Error>> {
+        Ok(HookAction::Pass)
+    }
+}
+
+impl<R> CallbackAction<HookCallAction<R>> for () {
+    fn into_action(self) -> Result<HookCallAction<R>, Error<pcode::Error>> {
+        Ok(HookCallAction::Pass)
+    }
+}
+
+impl<R> CallbackAction<HookStepAction<R>> for () {
+    fn into_action(self) -> Result<HookStepAction<R>, Error<pcode::Error>> {
+        Ok(HookStepAction::Pass)
+    }
+}
+
+impl<R> CallbackAction<HookAction<R>> for HookAction<R> {
+    fn into_action(self) -> Result<HookAction<R>, Error<pcode::Error>> {
+        Ok(self)
+    }
+}
+
+impl<R> CallbackAction<HookCallAction<R>> for HookCallAction<R> {
+    fn into_action(self) -> Result<HookCallAction<R>, Error<pcode::Error>> {
+        Ok(self)
+    }
+}
+
+impl<R> CallbackAction<HookStepAction<R>> for HookStepAction<R> {
+    fn into_action(self) -> Result<HookStepAction<R>, Error<pcode::Error>> {
+        Ok(self)
+    }
+}
+
+impl<A, T> CallbackAction<A> for Result<T, Error<pcode::Error>>
+where T: CallbackAction<A> {
+    fn into_action(self) -> Result<A, Error<pcode::Error>> {
+        self.and_then(T::into_action)
+    }
+}
+
+macro_rules! callback_hook {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        pub struct $name<F, O, R> {
+            callback: F,
+            marker: PhantomData<fn() -> (O, R)>,
+        }
+
+        impl<F, O, R> $name<F, O, R> {
+            pub fn new(callback: F) -> Self {
+                Self {
+                    callback,
+                    marker: PhantomData,
+                }
+            }
+        }
+
+        impl<F: Clone, O, R> Clone for $name<F, O, R> {
+            fn clone(&self) -> Self {
+                Self::new(self.callback.clone())
+            }
+        }
+    };
+}
+
+callback_hook! {
+    /// Invokes a callback with the destination of each call.
+    CallCallback
+}
+
+callback_hook! {
+    /// Invokes a callback with the address of each instruction.
+    InstructionCallback
+}
+
+callback_hook! {
+    /// Invokes a callback with the address and size of each memory read.
+    MemoryReadCallback
+}
+
+callback_hook! {
+    /// Invokes a callback with the address and value of each memory write.
+    MemoryWriteCallback
+}
+
+impl<F, T, O, R> HookConcrete for CallCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address) -> T + 'static,
+    T: CallbackAction<HookCallAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = ConcreteState<O>;
+    type Error = pcode::Error;
+    type Outcome = R;
+
+    fn hook_call(
+        &mut self,
+        state: &mut Self::State,
+        destination: &Address,
+    ) -> Result<HookOutcome<HookCallAction<Self::Outcome>>, Error<Self::Error>> {
+        Ok((self.callback)(state, destination).into_action()?.into())
+    }
+}
+
+impl<F, T, O, R> HookConcrete for InstructionCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address) -> T + 'static,
+    T: CallbackAction<HookStepAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = ConcreteState<O>;
+    type Error = pcode::Error;
+    type Outcome = R;
+
+    fn hook_architectural_step(
+        &mut self,
+        state: &mut Self::State,
+        address: &Address,
+        _operation: &StepState,
+    ) -> Result<HookOutcome<HookStepAction<Self::Outcome>>, Error<Self::Error>> {
+        Ok((self.callback)(state, address).into_action()?.into())
+    }
+}
+
+impl<F, T, O, R> HookConcrete for MemoryReadCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address, usize) -> T + 'static,
+    T: CallbackAction<HookAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = ConcreteState<O>;
+    type Error = pcode::Error;
+    type Outcome = R;
+
+    fn hook_memory_read(
+        &mut self,
+        state: &mut Self::State,
+        address: &Address,
+        size: usize,
+    ) -> Result<HookOutcome<HookAction<Self::Outcome>>, Error<Self::Error>> {
+        Ok((self.callback)(state, address, size).into_action()?.into())
+    }
+}
+
+impl<F, T, O, R> HookConcrete for MemoryWriteCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address, &[u8]) -> T + 'static,
+    T: CallbackAction<HookAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = ConcreteState<O>;
+    type Error = pcode::Error;
+    type Outcome = R;
+
+    fn hook_memory_write(
+        &mut self,
+        state: &mut Self::State,
+        address: &Address,
+        _size: usize,
+        value: &[u8],
+    ) -> Result<HookOutcome<HookAction<Self::Outcome>>, Error<Self::Error>> {
+        Ok((self.callback)(state, address, value).into_action()?.into())
+    }
+}
+
+impl<F, T, O, R> ClonableHookConcrete for CallCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address) -> T + Clone + 'static,
+    T: CallbackAction<HookCallAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+}
+
+impl<F, T, O, R> ClonableHookConcrete for InstructionCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address) -> T + Clone + 'static,
+    T: CallbackAction<HookStepAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+}
+
+impl<F, T, O, R> ClonableHookConcrete for MemoryReadCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address, usize) -> T + Clone + 'static,
+    T: CallbackAction<HookAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+}
+
+impl<F, T, O, R> ClonableHookConcrete for MemoryWriteCallback<F, O, R>
+where
+    F: FnMut(&mut ConcreteState<O>, &Address, &[u8]) -> T + Clone + 'static,
+    T: CallbackAction<HookAction<R>>,
+    O: Order + 'static,
+    R: 'static,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use fugue::bytes::LE;
+    use fugue::ir::il::Location;
+    use fugue::ir::AddressValue;
+
+    use fuguex_hooks::types::HookCallSkip;
+    use fuguex_machine::{Machine, StepOutcome};
+
+    use crate::testing;
+
+    const CODE: u64 = 0x1000;
+    const STACK: u64 = 0x8000;
+    const CALLEE: u64 = 0x2000;
+    // within the page of zeros mapped after the code
+    const DATA: u64 = 0x1800;
+
+    type Log<T> = Arc<Mutex<Vec<T>>>;
+
+    // `mov eax, imm32`
+    fn mov_eax(value: u32) -> Vec<u8> {
+        let mut bytes = vec![0xb8];
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    // `mov eax, dword [address]`
+    fn load_eax(address: u64) -> Vec<u8> {
+        let mut bytes = vec![0x8b, 0x04, 0x25];
+        bytes.extend_from_slice(&(address as u32).to_le_bytes());
+        bytes
+    }
+
+    // `mov dword [address], imm32`
+    fn store(address: u64, value: u32) -> Vec<u8> {
+        let mut bytes = vec![0xc7, 0x04, 0x25];
+        bytes.extend_from_slice(&(address as u32).to_le_bytes());
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    fn context(code: &[u8]) -> testing::Context<LE> {
+        testing::context::<LE>("x86:LE:64:default", CODE, code, STACK)
+    }
+
+    fn eax(machine: &Machine<testing::Context<LE>>) -> u64 {
+        machine.interpreter().state().return_value().unwrap()
+    }
+
+    // Calls `CALLEE`, which is skipped, then `LOCAL`, which returns, then
+    // sets eax to 3; returns the context, the destinations of the skipped
+    // calls, and where the code ends
+    fn calls() -> (testing::Context<LE>, Log<u64>, u64, u64) {
+        let mut code = testing::call_rel32(CODE, CALLEE);
+        let local = CODE + 0x10;
+        code.extend(testing::call_rel32(CODE + 5, local));
+        code.extend(mov_eax(3));
+        let end = CODE + code.len() as u64;
+        code.resize(0x10, 0x90);
+        code.push(0xc3);
+
+        let log = Log::default();
+        let mut context = context(&code);
+
+        let skipped = log.clone();
+        context.on_call("skip", CALLEE, move |_: &mut ConcreteState<LE>, destination: &Address| {
+            skipped.lock().unwrap().push(u64::from(*destination));
+            HookCallAction::SkipWith(HookCallSkip::default().with_return_value(7))
+        }).unwrap();
+
+        (context, log, local, end)
+    }
+
+    #[test]
+    fn on_call() {
+        let (context, log, _, end) = calls();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+
+        // only the call to `CALLEE` is reported, and it is skipped
+        assert_eq!(*log.lock().unwrap(), [CALLEE]);
+        assert_eq!(eax(&machine), 3);
+    }
+
+    #[test]
+    fn on_call_halt() {
+        let (mut context, log, local, end) = calls();
+        context.on_call("halt", local, |_: &mut ConcreteState<LE>, _: &Address| HookCallAction::Halt(())).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Halt(())));
+
+        assert_eq!(*log.lock().unwrap(), [CALLEE]);
+        assert_eq!(eax(&machine), 7);
+    }
+
+    // Sets eax to 1, then 2, then does nothing
+    fn instructions() -> testing::Context<LE> {
+        let mut code = mov_eax(1);
+        code.extend(mov_eax(2));
+        code.push(0x90);
+        context(&code)
+    }
+
+    #[test]
+    fn on_instruction() {
+        let mut context = instructions();
+
+        let log = Log::default();
+        let executed = log.clone();
+        context.on_instruction("log", CODE + 5..CODE + 10, move |_: &mut ConcreteState<LE>, address: &Address| {
+            executed.lock().unwrap().push(u64::from(*address));
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, CODE + 11), StepOutcome::Reached));
+
+        assert_eq!(*log.lock().unwrap(), [CODE + 5]);
+        assert_eq!(eax(&machine), 2);
+    }
+
+    #[test]
+    fn on_instruction_branch() {
+        let mut context = instructions();
+        context.on_instruction("skip", CODE + 5..CODE + 6, |state: &mut ConcreteState<LE>, _: &Address| {
+            HookStepAction::Branch(Location::from(AddressValue::new(state.memory_space(), CODE + 10)))
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, CODE + 11), StepOutcome::Reached));
+
+        assert_eq!(eax(&machine), 1);
+    }
+
+    #[test]
+    fn on_instruction_halt() {
+        let mut context = instructions();
+        context.on_instruction("halt", CODE + 5..CODE + 6, |_: &mut ConcreteState<LE>, _: &Address| {
+            HookStepAction::Halt(())
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, CODE + 11), StepOutcome::Halt(())));
+
+        assert_eq!(eax(&machine), 1);
+        let state = machine.interpreter().state();
+        assert_eq!(u64::from(state.program_counter_value().unwrap()), CODE + 5);
+    }
+
+    // Reads `DATA`, then `DATA + 0x100`, then sets eax to 3
+    fn reads() -> (testing::Context<LE>, u64) {
+        let mut code = load_eax(DATA);
+        code.extend(load_eax(DATA + 0x100));
+        code.extend(mov_eax(3));
+        (context(&code), CODE + code.len() as u64)
+    }
+
+    #[test]
+    fn on_memory_read() {
+        let (mut context, end) = reads();
+
+        let log = Log::default();
+        let read = log.clone();
+        context.on_memory_read("log", DATA..DATA + 4, move |_: &mut ConcreteState<LE>, address: &Address, size: usize| {
+            read.lock().unwrap().push((u64::from(*address), size));
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+
+        assert_eq!(*log.lock().unwrap(), [(DATA, 4)]);
+        assert_eq!(eax(&machine), 3);
+    }
+
+    #[test]
+    fn on_memory_read_halt() {
+        let (mut context, end) = reads();
+        context.on_memory_read("halt", DATA + 0x100..DATA + 0x104, |_: &mut ConcreteState<LE>, _: &Address, _: usize| {
+            HookAction::Halt(())
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Halt(())));
+
+        // the read completes, but nothing after it executes
+        assert_eq!(eax(&machine), 0);
+    }
+
+    // Writes 1 to `DATA`, then 2 to `DATA + 0x100`, then sets eax to 3
+    fn writes() -> (testing::Context<LE>, u64) {
+        let mut code = store(DATA, 1);
+        code.extend(store(DATA + 0x100, 2));
+        code.extend(mov_eax(3));
+        (context(&code), CODE + code.len() as u64)
+    }
+
+    #[test]
+    fn on_memory_write() {
+        let (mut context, end) = writes();
+
+        let log = Log::default();
+        let written = log.clone();
+        context.on_memory_write("log", DATA..DATA + 4, move |_: &mut ConcreteState<LE>, address: &Address, value: &[u8]| {
+            written.lock().unwrap().push((u64::from(*address), value.to_vec()));
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+
+        assert_eq!(*log.lock().unwrap(), [(DATA, vec![1, 0, 0, 0])]);
+        assert_eq!(eax(&machine), 3);
+    }
+
+    #[test]
+    fn on_memory_write_halt() {
+        let (mut context, end) = writes();
+        context.on_memory_write("halt", DATA + 0x100..DATA + 0x104, |_: &mut ConcreteState<LE>, _: &Address, _: &[u8]| {
+            HookAction::Halt(())
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Halt(())));
+
+        // the write completes, but nothing after it executes
+        let state = machine.interpreter().state();
+        assert_eq!(&state.view_values_from(DATA + 0x100).unwrap()[..4], [2, 0, 0, 0]);
+        assert_eq!(eax(&machine), 0);
+    }
+}
@@ -0,0 +1,239 @@
+use fugue::ir::Address;
+
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Frame {
+    call_site: Address,
+    callee: Address,
+    return_address: Address,
+    stack_pointer: Address,
+}
+
+impl Frame {
+    pub fn new(call_site: Address, callee: Address, return_address: Address, stack_pointer: Address) -> Self {
+        Self {
+            call_site,
+            callee,
+            return_address,
+            stack_pointer,
+        }
+    }
+
+    /// The address of the calling instruction.
+    pub fn call_site(&self) -> Address {
+        self.call_site
+    }
+
+    /// The function called; after a tail call, the function returning may
+    /// differ.
+    pub fn callee(&self) -> Address {
+        self.callee
+    }
+
+    /// The return address stored by the call.
+    pub fn return_address(&self) -> Address {
+        self.return_address
+    }
+
+    /// The stack pointer on entry to the callee.
+    pub fn stack_pointer(&self) -> Address {
+        self.stack_pointer
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} called from {}", self.callee, self.call_site)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReturnKind {
+    /// Returned to the innermost frame's return address.
+    Matched,
+    /// Returned to an outer frame's return address, unwinding `n` frames,
+    /// e.g., from a function entered by `longjmp`.
+    Unwound(usize),
+    /// Returned to an address no frame expects; the innermost frame is
+    /// popped regardless.
+    Mismatch { expected: Address, actual: Address },
+    /// Returned with no frames, e.g., from the function emulation started
+    /// in.
+    Unmatched,
+}
+
+impl ReturnKind {
+    /// The expected and actual return addresses of a mismatched return.
+    pub fn mismatch(&self) -> Option<(Address, Address)> {
+        if let Self::Mismatch { expected, actual } = self {
+            Some((*expected, *actual))
+        } else {
+            None
+        }
+    }
+}
+
+/// A shadow call stack, maintained from calls and returns.
+///
+/// Tail calls reuse the caller's frame, so are seen as returns from the
+/// original callee. Assumes a descending stack: on each call, frames
+/// entered below the current stack pointer are considered unwound without
+/// returning, e.g., by `longjmp`, and are dropped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The frames, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// The frames, innermost first.
+    pub fn backtrace(&self) -> impl Iterator<Item = &Frame> {
+        self.frames.iter().rev()
+    }
+
+    /// The call sites of each frame, outermost first.
+    pub fn call_sites(&self) -> impl Iterator<Item = Address> + '_ {
+        self.frames.iter().map(Frame::call_site)
+    }
+
+    pub fn current(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        let live = self.frames
+            .iter()
+            .rposition(|f| f.stack_pointer >= frame.stack_pointer)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        self.frames.truncate(live);
+        self.frames.push(frame);
+    }
+
+    /// Record a return to `address`.
+    pub fn pop(&mut self, address: Address) -> ReturnKind {
+        let expected = if let Some(frame) = self.frames.last() {
+            frame.return_address
+        } else {
+            return ReturnKind::Unmatched
+        };
+
+        if expected == address {
+            self.frames.pop();
+            return ReturnKind::Matched
+        }
+
+        if let Some(position) = self.frames.iter().rposition(|frame| frame.return_address == address) {
+            let unwound = self.frames.len() - position;
+            self.frames.truncate(position);
+            return ReturnKind::Unwound(unwound)
+        }
+
+        self.frames.pop();
+        ReturnKind::Mismatch { expected, actual: address }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(callee: u64, return_address: u64, stack_pointer: u64) -> Frame {
+        Frame::new(
+            Address::from(return_address - 4),
+            Address::from(callee),
+            Address::from(return_address),
+            Address::from(stack_pointer),
+        )
+    }
+
+    fn callees(stack: &CallStack) -> Vec<Address> {
+        stack.frames().iter().map(Frame::callee).collect()
+    }
+
+    #[test]
+    fn matched_returns() {
+        let mut stack = CallStack::new();
+        stack.push(frame(0x1000, 0x104, 0x8000));
+        stack.push(frame(0x2000, 0x1010, 0x7ff0));
+
+        assert_eq!(stack.pop(Address::from(0x1010u64)), ReturnKind::Matched);
+        assert_eq!(stack.pop(Address::from(0x104u64)), ReturnKind::Matched);
+        assert_eq!(stack.pop(Address::from(0x104u64)), ReturnKind::Unmatched);
+    }
+
+    #[test]
+    fn tail_call() {
+        let mut stack = CallStack::new();
+
+        // 0x1000 calls 0x2000, which tail calls 0x3000 without a frame;
+        // 0x3000 then calls 0x4000 from within the reused frame
+        stack.push(frame(0x2000, 0x1010, 0x7ff0));
+        stack.push(frame(0x4000, 0x3010, 0x7fe0));
+        assert_eq!(callees(&stack), vec![Address::from(0x2000u64), Address::from(0x4000u64)]);
+
+        assert_eq!(stack.pop(Address::from(0x3010u64)), ReturnKind::Matched);
+
+        // the return from 0x3000 is seen as one from 0x2000
+        assert_eq!(stack.current().map(Frame::callee), Some(Address::from(0x2000u64)));
+        assert_eq!(stack.pop(Address::from(0x1010u64)), ReturnKind::Matched);
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn longjmp() {
+        let mut stack = CallStack::new();
+        stack.push(frame(0x1000, 0x104, 0x8000));
+        stack.push(frame(0x2000, 0x1010, 0x7f00));
+        stack.push(frame(0x3000, 0x2010, 0x7e00));
+
+        // a longjmp back into 0x1000 restores its stack pointer, so the
+        // next call drops the frames entered below it
+        stack.push(frame(0x4000, 0x1020, 0x7ff0));
+        assert_eq!(callees(&stack), vec![Address::from(0x1000u64), Address::from(0x4000u64)]);
+
+        // returning to an outer frame unwinds the inner ones
+        stack.push(frame(0x5000, 0x4010, 0x7fe0));
+        assert_eq!(stack.pop(Address::from(0x104u64)), ReturnKind::Unwound(3));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn mismatch() {
+        let mut stack = CallStack::new();
+        stack.push(frame(0x1000, 0x104, 0x8000));
+        stack.push(frame(0x2000, 0x1010, 0x7f00));
+
+        let kind = stack.pop(Address::from(0x4141_4141u64));
+        assert_eq!(
+            kind.mismatch(),
+            Some((Address::from(0x1010u64), Address::from(0x4141_4141u64))),
+        );
+
+        // the innermost frame is popped regardless
+        assert_eq!(stack.depth(), 1);
+        assert_eq!(ReturnKind::Matched.mismatch(), None);
+        assert_eq!(ReturnKind::Unwound(1).mismatch(), None);
+    }
+}
@@ -0,0 +1,101 @@
+use fnv::FnvHashMap as Map;
+
+use fugue::bytes::Order;
+use fugue::ir::Address;
+
+use fuguex_machine::types::StepState;
+
+/// Lifted instructions, by address and then by the values of the tracked
+/// context variables, i.e., those switched or set explicitly, they were
+/// decoded with.
+pub type LiftCache = Map<Address, Map<Vec<u32>, StepState>>;
+
+/// A rule updating a decoding context variable from execution.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContextSwitch {
+    /// Set `variable` to the value of `register` before decoding each
+    /// instruction, e.g., ARM's `TMode` from `ISAModeSwitch`, as set by
+    /// interworking branches. Hooks switch modes by writing `register`.
+    Register { variable: String, register: String },
+    /// Set `variable` to bit 0 of the target of each indirect branch, call
+    /// and return, and of direct calls to odd addresses, and clear it in
+    /// the target, e.g., for MIPS16's `ISA_MODE`.
+    TargetBit { variable: String },
+}
+
+impl ContextSwitch {
+    pub fn register<V: Into<String>, S: Into<String>>(variable: V, register: S) -> Self {
+        Self::Register { variable: variable.into(), register: register.into() }
+    }
+
+    pub fn target_bit<V: Into<String>>(variable: V) -> Self {
+        Self::TargetBit { variable: variable.into() }
+    }
+
+    /// ARM/Thumb interworking.
+    pub fn arm_thumb() -> Self {
+        Self::register("TMode", "ISAModeSwitch")
+    }
+
+    pub fn variable(&self) -> &str {
+        match self {
+            Self::Register { variable, .. } | Self::TargetBit { variable } => variable,
+        }
+    }
+}
+
+// The value of a variable switched by a register, from the register's
+// bytes; registers wider than 32 bits are truncated
+pub(crate) fn register_value<O: Order>(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    let size = bytes.len().min(buf.len());
+
+    if O::ENDIAN.is_big() {
+        buf[4 - size..].copy_from_slice(&bytes[bytes.len() - size..]);
+        u32::from_be_bytes(buf)
+    } else {
+        buf[..size].copy_from_slice(&bytes[..size]);
+        u32::from_le_bytes(buf)
+    }
+}
+
+// Split a branch target into the address to branch to and the value of a
+// target bit switch
+pub(crate) fn split_target(target: u64) -> (u64, u32) {
+    (target & !1, (target & 1) as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fugue::bytes::{BE, LE};
+
+    #[test]
+    fn arm_bx_to_odd_address() {
+        // `bx r0` with r0 = 0x8001 sets ISAModeSwitch to 1 and branches
+        // to 0x8000 in Thumb mode
+        assert_eq!(split_target(0x8001), (0x8000, 1));
+        assert_eq!(split_target(0x8000), (0x8000, 0));
+        assert_eq!(register_value::<LE>(&[1]), 1);
+        assert_eq!(register_value::<BE>(&[1]), 1);
+    }
+
+    #[test]
+    fn register_values() {
+        assert_eq!(register_value::<LE>(&[0x01, 0x02]), 0x0201);
+        assert_eq!(register_value::<BE>(&[0x01, 0x02]), 0x0102);
+
+        // wider registers are truncated to their low 32 bits
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(register_value::<LE>(&bytes), 0x0403_0201);
+        assert_eq!(register_value::<BE>(&bytes), 0x0506_0708);
+    }
+
+    #[test]
+    fn arm_thumb_switch() {
+        let switch = ContextSwitch::arm_thumb();
+        assert_eq!(switch.variable(), "TMode");
+        assert_eq!(switch, ContextSwitch::register("TMode", "ISAModeSwitch"));
+    }
+}
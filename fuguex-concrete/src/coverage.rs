@@ -0,0 +1,264 @@
+use fugue::bytes::Order;
+use fugue::ir::il::pcode::Operand;
+use fugue::ir::Address;
+use fuguex_hooks::types::{Error, HookCBranchAction, HookOutcome, HookStepAction};
+use fuguex_machine::StepState;
+use fuguex_state::pcode::{Error as PCodeError, PCodeState};
+use fuguex_state::{AsState, StateOps};
+
+use crate::hooks::{ClonableHookConcrete, HookConcrete};
+
+use std::marker::PhantomData;
+
+pub const DEFAULT_MAP_SIZE: usize = 1 << 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CoverageMode {
+    /// Count transitions between blocks, as `map[block ^ (previous >> 1)]`.
+    Edge,
+    /// Count block entries, as `map[block]`.
+    Block,
+}
+
+/// An AFL-style, hashed coverage bitmap with 8-bit wrapping hit counters.
+///
+/// A block is entered after each control transfer: a taken or not-taken
+/// conditional branch, or any instruction not followed by its fallthrough.
+/// As the map is cloned into forks and replaced on restore, restoring a
+/// context forked before a test case resets the map.
+pub struct CoverageMap<S, O, R> {
+    map: Vec<u8>,
+    mode: CoverageMode,
+    previous: usize,
+    fallthrough: Option<Address>,
+    delay_slot: Option<Address>,
+    branched: bool,
+    marker: PhantomData<(S, O, R)>,
+}
+
+impl<S, O, R> Default for CoverageMap<S, O, R> {
+    fn default() -> Self {
+        Self::new(CoverageMode::Edge, DEFAULT_MAP_SIZE)
+    }
+}
+
+impl<S, O, R> Clone for CoverageMap<S, O, R> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            mode: self.mode,
+            previous: self.previous,
+            fallthrough: self.fallthrough,
+            delay_slot: self.delay_slot,
+            branched: self.branched,
+            marker: PhantomData,
+        }
+    }
+}
+
+// Mix the bits of an address so that nearby blocks are spread over the map
+fn hash(address: Address) -> usize {
+    let mut value = u64::from(address);
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (value ^ (value >> 31)) as usize
+}
+
+impl<S, O, R> CoverageMap<S, O, R> {
+    /// Create a map of `size` counters, rounded up to a power of two.
+    pub fn new(mode: CoverageMode, size: usize) -> Self {
+        Self {
+            map: vec![0u8; size.max(1).next_power_of_two()],
+            mode,
+            previous: 0,
+            fallthrough: None,
+            delay_slot: None,
+            branched: false,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn edges() -> Self {
+        Self::new(CoverageMode::Edge, DEFAULT_MAP_SIZE)
+    }
+
+    pub fn blocks() -> Self {
+        Self::new(CoverageMode::Block, DEFAULT_MAP_SIZE)
+    }
+
+    pub fn mode(&self) -> CoverageMode {
+        self.mode
+    }
+
+    pub fn map(&self) -> &[u8] {
+        &self.map
+    }
+
+    pub fn map_mut(&mut self) -> &mut [u8] {
+        &mut self.map
+    }
+
+    /// The number of non-zero counters.
+    pub fn covered(&self) -> usize {
+        self.map.iter().filter(|c| **c != 0).count()
+    }
+
+    /// Clear the counters and the previous block, e.g., between test cases
+    /// run without restoring the context.
+    pub fn reset(&mut self) {
+        self.map.iter_mut().for_each(|c| *c = 0);
+        self.previous = 0;
+        self.fallthrough = None;
+        self.delay_slot = None;
+        self.branched = false;
+    }
+
+    fn enter(&mut self, address: Address) {
+        let mask = self.map.len() - 1;
+        let current = hash(address) & mask;
+
+        let index = match self.mode {
+            CoverageMode::Edge => {
+                let index = current ^ self.previous;
+                self.previous = current >> 1;
+                index
+            }
+            CoverageMode::Block => current,
+        };
+
+        self.map[index] = self.map[index].wrapping_add(1);
+    }
+}
+
+impl<S, O, R> HookConcrete for CoverageMap<S, O, R>
+where
+    S: AsState<PCodeState<u8, O>> + StateOps<Value = u8> + 'static,
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = S;
+    type Error = PCodeError;
+    type Outcome = R;
+
+    fn hook_architectural_step(
+        &mut self,
+        _state: &mut Self::State,
+        address: &Address,
+        operation: &StepState,
+    ) -> Result<HookOutcome<HookStepAction<Self::Outcome>>, Error<Self::Error>> {
+        // delay slots execute as part of the preceding instruction
+        if self.delay_slot == Some(*address) {
+            return Ok(HookStepAction::Pass.into())
+        }
+
+        if self.branched || self.fallthrough != Some(*address) {
+            self.enter(*address);
+        }
+
+        self.fallthrough = Some(operation.fallthrough().into());
+        self.delay_slot = operation.delay_slot().map(Address::from);
+        self.branched = false;
+
+        Ok(HookStepAction::Pass.into())
+    }
+
+    fn hook_cbranch(
+        &mut self,
+        _state: &mut Self::State,
+        _destination: &Operand,
+        _condition: &Operand,
+    ) -> Result<HookOutcome<HookCBranchAction<Self::Outcome>>, Error<Self::Error>> {
+        self.branched = true;
+        Ok(HookCBranchAction::Pass.into())
+    }
+}
+
+impl<S, O, R> ClonableHookConcrete for CoverageMap<S, O, R>
+where
+    S: AsState<PCodeState<u8, O>> + StateOps<Value = u8> + 'static,
+    O: Order + 'static,
+    R: 'static,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Map = CoverageMap<(), (), ()>;
+
+    fn index(map: &Map, address: u64) -> usize {
+        hash(Address::from(address)) & (map.map().len() - 1)
+    }
+
+    #[test]
+    fn map_size() {
+        assert_eq!(Map::new(CoverageMode::Edge, 1000).map().len(), 1024);
+        assert_eq!(Map::new(CoverageMode::Edge, 0).map().len(), 1);
+        assert_eq!(Map::default().map().len(), DEFAULT_MAP_SIZE);
+    }
+
+    #[test]
+    fn hash_spreads_nearby_blocks() {
+        let map = Map::default();
+        assert_eq!(hash(Address::from(0x1000u64)), hash(Address::from(0x1000u64)));
+        assert_ne!(index(&map, 0x1000), index(&map, 0x1004));
+        assert_ne!(index(&map, 0x1000), index(&map, 0x2000));
+    }
+
+    #[test]
+    fn block_counts() {
+        let mut map = Map::blocks();
+
+        map.enter(Address::from(0x1000u64));
+        map.enter(Address::from(0x1000u64));
+        map.enter(Address::from(0x2000u64));
+
+        assert_eq!(map.map()[index(&map, 0x1000)], 2);
+        assert_eq!(map.map()[index(&map, 0x2000)], 1);
+        assert_eq!(map.covered(), 2);
+    }
+
+    #[test]
+    fn edge_counts() {
+        let mut map = Map::edges();
+
+        let a = index(&map, 0x1000);
+        let b = index(&map, 0x2000);
+
+        map.enter(Address::from(0x1000u64));
+        map.enter(Address::from(0x2000u64));
+
+        assert_eq!(map.map()[a], 1);
+        assert_eq!(map.map()[b ^ (a >> 1)], 1);
+        assert_eq!(map.covered(), 2);
+
+        // the reverse edge is distinct
+        map.enter(Address::from(0x1000u64));
+        assert_eq!(map.map()[a ^ (b >> 1)], 1);
+        assert_eq!(map.covered(), 3);
+    }
+
+    #[test]
+    fn counters_wrap() {
+        let mut map = Map::blocks();
+        for _ in 0..256 {
+            map.enter(Address::from(0x1000u64));
+        }
+        assert_eq!(map.covered(), 0);
+    }
+
+    #[test]
+    fn reset() {
+        let mut map = Map::edges();
+
+        map.enter(Address::from(0x1000u64));
+        map.enter(Address::from(0x2000u64));
+        map.reset();
+
+        assert_eq!(map.covered(), 0);
+
+        map.enter(Address::from(0x2000u64));
+        assert_eq!(map.map()[index(&map, 0x2000)], 1);
+    }
+}
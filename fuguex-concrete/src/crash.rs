@@ -0,0 +1,415 @@
+use fugue::bytes::Order;
+use fugue::ir::Address;
+use fuguex_hooks::types::{Error, HookOutcome, HookStepAction};
+use fuguex_machine::StepState;
+use fuguex_state::pcode::{self, Error as PCodeError, PCodeState};
+use fuguex_state::{chunked, flat, paged};
+use fuguex_state::{AsState, StateOps};
+
+use fnv::FnvHasher;
+
+use crate::fuzz::{Execution, ExecutionOutcome};
+use crate::hooks::{ClonableHookConcrete, HookConcrete};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::hash::Hasher;
+use std::marker::PhantomData;
+
+pub const DEFAULT_BRANCH_HISTORY: usize = 16;
+pub const DEFAULT_STACK_DEPTH: usize = 5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FaultKind {
+    /// An access to unmapped memory, or out of the bounds of a mapping.
+    InvalidAccess,
+    /// An access denied by the permissions of a mapping.
+    AccessViolation,
+    /// An access spanning heap allocations, or of unallocated heap memory.
+    HeapOverflow,
+    /// A free or reallocation of memory not allocated on the heap.
+    InvalidFree,
+    DivisionByZero,
+    /// An instruction that could not be lifted.
+    InvalidInstruction,
+    /// A halt requested by a hook, e.g., a sanitiser.
+    Halt,
+    Other,
+}
+
+impl FaultKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::InvalidAccess => "invalid-access",
+            Self::AccessViolation => "access-violation",
+            Self::HeapOverflow => "heap-overflow",
+            Self::InvalidFree => "invalid-free",
+            Self::DivisionByZero => "division-by-zero",
+            Self::InvalidInstruction => "invalid-instruction",
+            Self::Halt => "halt",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// The state error underlying an error raised directly, by a hook or by
+// an intrinsic
+fn state_error(error: &crate::Error) -> Option<&PCodeError> {
+    match error {
+        crate::Error::State(e)
+        | crate::Error::Hook(fuguex_hooks::types::Error::State(e))
+        | crate::Error::Intrinsic(fuguex_intrinsics::Error::State(e)) => Some(e),
+        _ => None,
+    }
+}
+
+fn memory_fault(error: &paged::Error) -> (FaultKind, Option<(Address, usize)>) {
+    match error {
+        paged::Error::Chunked(chunked::Error::FreeUnmanaged(address))
+        | paged::Error::Chunked(chunked::Error::ReallocateUnmanaged(address)) => {
+            (FaultKind::InvalidFree, Some((*address, 0)))
+        },
+        paged::Error::Chunked(chunked::Error::NotEnoughFreeSpace(_)) => (FaultKind::Other, None),
+        paged::Error::Chunked(chunked::Error::AccessUnmanaged { .. })
+        | paged::Error::Chunked(chunked::Error::HeapOverflow { .. }) => {
+            (FaultKind::HeapOverflow, Some(error.access()))
+        },
+        paged::Error::Backing(flat::Error::AccessViolation { .. })
+        | paged::Error::Chunked(chunked::Error::Backing(flat::Error::AccessViolation { .. })) => {
+            (FaultKind::AccessViolation, Some(error.access()))
+        },
+        _ => (FaultKind::InvalidAccess, Some(error.access())),
+    }
+}
+
+/// A normalised record of a crashing run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    kind: FaultKind,
+    pc: Address,
+    access: Option<(Address, usize)>,
+    call_stack: Vec<Address>,
+    branches: Vec<(Address, Address)>,
+    message: String,
+}
+
+impl CrashReport {
+    pub fn new(kind: FaultKind, pc: impl Into<Address>) -> Self {
+        Self {
+            kind,
+            pc: pc.into(),
+            access: None,
+            call_stack: Vec::new(),
+            branches: Vec::new(),
+            message: String::new(),
+        }
+    }
+
+    /// Classify `error`, raised at `pc`.
+    pub fn from_error(error: &crate::Error, pc: impl Into<Address>) -> Self {
+        let pc = pc.into();
+
+        let (kind, access) = match error {
+            crate::Error::DivisionByZero => (FaultKind::DivisionByZero, None),
+            crate::Error::Lift(address, _) => (FaultKind::InvalidInstruction, Some((*address, 0))),
+            _ => match state_error(error) {
+                Some(PCodeError::Memory(e)) => memory_fault(e),
+                _ => (FaultKind::Other, None),
+            },
+        };
+
+        Self {
+            access,
+            message: error.to_string(),
+            ..Self::new(kind, pc)
+        }
+    }
+
+    /// Classify the outcome of `execution`, if it crashed or was halted.
+    pub fn from_execution<R>(execution: &Execution<R>) -> Option<Self> {
+        match execution.outcome {
+            ExecutionOutcome::Crashed(ref error) => Some(Self::from_error(error, execution.address)),
+            ExecutionOutcome::Halted(_) => {
+                Some(Self::new(FaultKind::Halt, execution.address).with_message("halted"))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn with_access(self, address: impl Into<Address>, size: usize) -> Self {
+        Self { access: Some((address.into(), size)), ..self }
+    }
+
+    /// The call sites leading to the fault, outermost first.
+    pub fn with_call_stack<I: IntoIterator<Item = Address>>(self, frames: I) -> Self {
+        Self { call_stack: frames.into_iter().collect(), ..self }
+    }
+
+    /// The control transfers preceding the fault, as `(from, to)`, oldest
+    /// first.
+    pub fn with_branches<I: IntoIterator<Item = (Address, Address)>>(self, branches: I) -> Self {
+        Self { branches: branches.into_iter().collect(), ..self }
+    }
+
+    pub fn with_message<M: Into<String>>(self, message: M) -> Self {
+        Self { message: message.into(), ..self }
+    }
+
+    pub fn kind(&self) -> FaultKind {
+        self.kind
+    }
+
+    pub fn pc(&self) -> Address {
+        self.pc
+    }
+
+    /// The address and size of the faulting access, if any.
+    pub fn access(&self) -> Option<(Address, usize)> {
+        self.access
+    }
+
+    pub fn call_stack(&self) -> &[Address] {
+        &self.call_stack
+    }
+
+    pub fn branches(&self) -> &[(Address, Address)] {
+        &self.branches
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// A hash of the innermost `depth` frames of the call stack.
+    pub fn stack_hash(&self, depth: usize) -> u64 {
+        let mut hasher = FnvHasher::default();
+        for frame in self.call_stack.iter().rev().take(depth) {
+            hasher.write_u64(u64::from(*frame));
+        }
+        hasher.finish()
+    }
+
+    pub fn signature(&self, depth: usize) -> CrashSignature {
+        CrashSignature {
+            kind: self.kind,
+            pc: self.pc,
+            stack_hash: self.stack_hash(depth),
+        }
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.kind, self.pc)?;
+        if let Some((address, size)) = self.access {
+            write!(f, " accessing {} bytes at {}", size, address)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        for frame in self.call_stack.iter().rev() {
+            write!(f, "\n  called from {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// Identifies crashes considered the same: the fault kind, faulting PC
+/// and a hash of the innermost call stack frames.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CrashSignature {
+    pub kind: FaultKind,
+    pub pc: Address,
+    pub stack_hash: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CrashBucket {
+    report: CrashReport,
+    input: Option<Vec<u8>>,
+    hits: u64,
+}
+
+impl CrashBucket {
+    /// The first report of the bucket.
+    pub fn report(&self) -> &CrashReport {
+        &self.report
+    }
+
+    /// The input that produced the first report, if given.
+    pub fn input(&self) -> Option<&[u8]> {
+        self.input.as_deref()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
+
+/// Buckets crash reports by signature across a campaign.
+#[derive(Debug, Clone)]
+pub struct CrashDeduplicator {
+    buckets: BTreeMap<CrashSignature, CrashBucket>,
+    depth: usize,
+}
+
+impl Default for CrashDeduplicator {
+    fn default() -> Self {
+        Self::new(DEFAULT_STACK_DEPTH)
+    }
+}
+
+impl CrashDeduplicator {
+    /// Bucket by the innermost `depth` call stack frames.
+    pub fn new(depth: usize) -> Self {
+        Self {
+            buckets: BTreeMap::new(),
+            depth,
+        }
+    }
+
+    /// Add `report`, produced by `input`; returns true if it starts a new
+    /// bucket.
+    pub fn insert(&mut self, report: CrashReport, input: Option<&[u8]>) -> bool {
+        let signature = report.signature(self.depth);
+
+        if let Some(bucket) = self.buckets.get_mut(&signature) {
+            bucket.hits += 1;
+            false
+        } else {
+            self.buckets.insert(signature, CrashBucket {
+                report,
+                input: input.map(|input| input.to_vec()),
+                hits: 1,
+            });
+            true
+        }
+    }
+
+    pub fn bucket(&self, signature: &CrashSignature) -> Option<&CrashBucket> {
+        self.buckets.get(signature)
+    }
+
+    pub fn buckets(&self) -> impl Iterator<Item = (&CrashSignature, &CrashBucket)> {
+        self.buckets.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+    }
+}
+
+/// Records the most recent control transfers, as `(from, to)`: steps to
+/// an instruction other than the fallthrough of the previous one.
+pub struct BranchHistory<S, O, R> {
+    history: VecDeque<(Address, Address)>,
+    capacity: usize,
+    last: Option<Address>,
+    fallthrough: Option<Address>,
+    delay_slot: Option<Address>,
+    marker: PhantomData<(S, O, R)>,
+}
+
+impl<S, O, R> Default for BranchHistory<S, O, R> {
+    fn default() -> Self {
+        Self::new(DEFAULT_BRANCH_HISTORY)
+    }
+}
+
+impl<S, O, R> Clone for BranchHistory<S, O, R> {
+    fn clone(&self) -> Self {
+        Self {
+            history: self.history.clone(),
+            capacity: self.capacity,
+            last: self.last,
+            fallthrough: self.fallthrough,
+            delay_slot: self.delay_slot,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S, O, R> BranchHistory<S, O, R> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            history: VecDeque::with_capacity(capacity),
+            capacity,
+            last: None,
+            fallthrough: None,
+            delay_slot: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// The recorded transfers, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = (Address, Address)> + '_ {
+        self.history.iter().copied()
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.last = None;
+        self.fallthrough = None;
+        self.delay_slot = None;
+    }
+}
+
+impl<S, O, R> HookConcrete for BranchHistory<S, O, R>
+where
+    S: AsState<PCodeState<u8, O>> + StateOps<Value = u8> + 'static,
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = S;
+    type Error = pcode::Error;
+    type Outcome = R;
+
+    fn hook_architectural_step(
+        &mut self,
+        _state: &mut Self::State,
+        address: &Address,
+        operation: &StepState,
+    ) -> Result<HookOutcome<HookStepAction<Self::Outcome>>, Error<Self::Error>> {
+        // delay slots execute as part of the preceding instruction
+        if self.delay_slot == Some(*address) {
+            return Ok(HookStepAction::Pass.into())
+        }
+
+        if let Some(last) = self.last {
+            if self.fallthrough != Some(*address) && self.capacity > 0 {
+                if self.history.len() == self.capacity {
+                    self.history.pop_front();
+                }
+                self.history.push_back((last, *address));
+            }
+        }
+
+        self.last = Some(*address);
+        self.fallthrough = Some(operation.fallthrough().into());
+        self.delay_slot = operation.delay_slot().map(Address::from);
+
+        Ok(HookStepAction::Pass.into())
+    }
+}
+
+impl<S, O, R> ClonableHookConcrete for BranchHistory<S, O, R>
+where
+    S: AsState<PCodeState<u8, O>> + StateOps<Value = u8> + 'static,
+    O: Order + 'static,
+    R: 'static,
+{
+}
@@ -0,0 +1,299 @@
+use std::cmp::Reverse;
+use std::ops::Range;
+
+use fnv::FnvHashMap as Map;
+
+use fugue::ir::il::pcode::{Operand, Register};
+use fugue::ir::Address;
+
+use iset::IntervalMap;
+
+use smallvec::SmallVec;
+
+use crate::hooks::{HookEvents, HookInterest};
+
+#[derive(Clone)]
+pub(crate) struct HookEntry {
+    pub name: String,
+    pub interest: HookInterest,
+    pub symbol: Option<String>,
+    pub priority: i32,
+    pub sequence: u64,
+    pub enabled: bool,
+}
+
+impl HookEntry {
+    pub fn new(name: String, interest: HookInterest) -> Self {
+        Self {
+            name,
+            interest,
+            symbol: None,
+            priority: 0,
+            sequence: 0,
+            enabled: true,
+        }
+    }
+}
+
+/// Indexes hooks by the events they are interested in, so that each event
+/// is only dispatched to the relevant, enabled hooks. Hooks are kept in
+/// dispatch order: by descending priority, then registration order.
+#[derive(Clone)]
+pub(crate) struct HookIndex {
+    entries: Vec<HookEntry>,
+    names: Map<String, usize>,
+    unfiltered: Vec<Vec<usize>>,
+    filtered: Vec<IntervalMap<Address, usize>>,
+    register_filtered: bool,
+    sequence: u64,
+}
+
+impl Default for HookIndex {
+    fn default() -> Self {
+        Self {
+            entries: Vec::default(),
+            names: Map::default(),
+            unfiltered: vec![Vec::default(); HookEvents::COUNT],
+            filtered: (0..HookEvents::COUNT).map(|_| IntervalMap::new()).collect(),
+            register_filtered: false,
+            sequence: 0,
+        }
+    }
+}
+
+impl HookIndex {
+    /// Insert `entry` after all entries of the same or higher priority;
+    /// returns its position.
+    pub fn insert(&mut self, mut entry: HookEntry) -> usize {
+        entry.sequence = self.sequence;
+        self.sequence += 1;
+        self.place(entry)
+    }
+
+    // Insert `entry` in dispatch order: by descending priority, then by
+    // when it was first inserted
+    fn place(&mut self, entry: HookEntry) -> usize {
+        let order = |e: &HookEntry| (Reverse(e.priority), e.sequence);
+        let position = self.entries.partition_point(|e| order(e) < order(&entry));
+        self.entries.insert(position, entry);
+        self.rebuild();
+        position
+    }
+
+    /// Change the priority of the entry at `position`; it keeps its place
+    /// among entries of the same priority. Returns its new position.
+    pub fn set_priority(&mut self, position: usize, priority: i32) -> usize {
+        let mut entry = self.entries.remove(position);
+        entry.priority = priority;
+        self.place(entry)
+    }
+
+    pub fn remove(&mut self, position: usize) -> HookEntry {
+        let entry = self.entries.remove(position);
+        self.rebuild();
+        entry
+    }
+
+    pub fn position<S: AsRef<str>>(&self, name: S) -> Option<usize> {
+        self.names.get(name.as_ref()).copied()
+    }
+
+    pub fn entry(&self, position: usize) -> &HookEntry {
+        &self.entries[position]
+    }
+
+    pub fn entries(&self) -> &[HookEntry] {
+        &self.entries
+    }
+
+    pub fn set_interest(&mut self, position: usize, interest: HookInterest) {
+        self.entries[position].interest = interest;
+        self.rebuild();
+    }
+
+    pub fn set_enabled(&mut self, position: usize, enabled: bool) {
+        self.entries[position].enabled = enabled;
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        let entries = std::mem::take(&mut self.entries);
+        let sequence = self.sequence;
+        *self = Self { sequence, ..Self::default() };
+
+        let register_events = [HookEvents::REGISTER_READ.index(), HookEvents::REGISTER_WRITE.index()];
+
+        for (index, entry) in entries.iter().enumerate() {
+            self.names.insert(entry.name.clone(), index);
+
+            if !entry.enabled {
+                continue
+            }
+
+            let interest = &entry.interest;
+            for event in interest.events().iter() {
+                if register_events.contains(&event) || interest.address_ranges().is_empty() {
+                    self.unfiltered[event].push(index);
+                } else {
+                    for range in interest.address_ranges().iter().filter(|r| r.start < r.end) {
+                        self.filtered[event].force_insert(range.clone(), index);
+                    }
+                }
+            }
+
+            self.register_filtered |= !interest.registers().is_empty();
+        }
+
+        self.entries = entries;
+    }
+
+    /// The hooks interested in `event` at `range`; hooks with address
+    /// ranges are skipped if `range` is not known.
+    pub fn hooks(&self, event: HookEvents, range: Option<Range<Address>>) -> Hooks {
+        let index = event.index();
+        let filtered = &self.filtered[index];
+
+        let mut matched = SmallVec::new();
+        match range {
+            Some(range) if !filtered.is_empty() && range.start < range.end => {
+                matched.extend(filtered.values(range).copied());
+                matched.sort_unstable();
+                matched.dedup();
+            }
+            _ => (),
+        }
+
+        Hooks {
+            entries: &self.entries,
+            unfiltered: &self.unfiltered[index],
+            register: None,
+            filtered: matched,
+        }
+    }
+
+    pub fn hooks_at(&self, event: HookEvents, address: Address) -> Hooks {
+        self.hooks(event, Some(address..address + 1usize))
+    }
+
+    pub fn has_filtered(&self, event: HookEvents) -> bool {
+        !self.filtered[event.index()].is_empty()
+    }
+
+    pub fn register_hooks(&self, event: HookEvents, register: Register) -> Hooks {
+        Hooks {
+            entries: &self.entries,
+            unfiltered: &self.unfiltered[event.index()],
+            register: if self.register_filtered { Some(register) } else { None },
+            filtered: SmallVec::new(),
+        }
+    }
+
+    pub fn operand_hooks(&self, operand: &Operand, write: bool) -> Hooks {
+        let (memory, register) = if write {
+            (HookEvents::MEMORY_WRITE, HookEvents::REGISTER_WRITE)
+        } else {
+            (HookEvents::MEMORY_READ, HookEvents::REGISTER_READ)
+        };
+
+        match operand {
+            Operand::Address { value, size } => self.hooks(memory, Some(*value..*value + *size)),
+            Operand::Register { .. } => self.register_hooks(register, operand.register().unwrap()),
+            _ => self.hooks(memory, None),
+        }
+    }
+}
+
+/// The hooks dispatched an event: those without address ranges, less
+/// those not interested in `register` if given, and those whose ranges
+/// match, each in dispatch order. Only the latter are collected, and then
+/// without allocating unless many match.
+pub(crate) struct Hooks<'a> {
+    entries: &'a [HookEntry],
+    unfiltered: &'a [usize],
+    register: Option<Register>,
+    filtered: SmallVec<[usize; 8]>,
+}
+
+impl<'a> Hooks<'a> {
+    /// The hooks in dispatch order.
+    pub fn iter(&self) -> impl Iterator<Item = &usize> + '_ {
+        let entries = self.entries;
+        let register = self.register.as_ref();
+
+        let mut unfiltered = self.unfiltered.iter()
+            .filter(move |i| register.map_or(true, |r| entries[**i].interest.is_interested_in_register(r)))
+            .peekable();
+        let mut filtered = self.filtered.iter().peekable();
+
+        std::iter::from_fn(move || match (unfiltered.peek().copied(), filtered.peek().copied()) {
+            (Some(u), Some(f)) if f < u => filtered.next(),
+            (Some(_), _) => unfiltered.next(),
+            (None, _) => filtered.next(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hooks(index: &HookIndex, address: u64) -> Vec<usize> {
+        index.hooks_at(HookEvents::CALL, Address::from(address)).iter().copied().collect()
+    }
+
+    #[test]
+    fn dispatch_order() {
+        let mut index = HookIndex::default();
+
+        let at = |address: u64| HookInterest::new(HookEvents::CALL).with_address(address);
+        index.insert(HookEntry::new("a".to_owned(), at(0x1000)));
+        index.insert(HookEntry::new("b".to_owned(), HookInterest::new(HookEvents::CALL)));
+        index.insert(HookEntry::new("c".to_owned(), at(0x1000).with_address_range(0x1000u64..0x2000u64)));
+        index.insert(HookEntry::new("d".to_owned(), HookInterest::new(HookEvents::CALL)));
+
+        assert_eq!(hooks(&index, 0x1000), vec![0, 1, 2, 3]);
+        assert_eq!(hooks(&index, 0x1800), vec![1, 2, 3]);
+        assert_eq!(hooks(&index, 0x2000), vec![1, 3]);
+    }
+
+    #[test]
+    fn priority_order() {
+        let mut index = HookIndex::default();
+
+        for name in ["a", "b", "c"].iter() {
+            index.insert(HookEntry::new(name.to_string(), HookInterest::new(HookEvents::CALL)));
+        }
+
+        let names = |index: &HookIndex| index.entries().iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(index.set_priority(2, 1), 0);
+        assert_eq!(names(&index), ["c", "a", "b"]);
+
+        // restoring the priority restores the registration order, as does
+        // setting an unchanged priority
+        assert_eq!(index.set_priority(0, 0), 2);
+        assert_eq!(names(&index), ["a", "b", "c"]);
+        assert_eq!(index.set_priority(0, 0), 0);
+        assert_eq!(names(&index), ["a", "b", "c"]);
+
+        assert_eq!(index.set_priority(1, -1), 2);
+        assert_eq!(names(&index), ["a", "c", "b"]);
+        assert_eq!(index.position("b"), Some(2));
+
+        index.remove(0);
+        index.insert(HookEntry::new("d".to_owned(), HookInterest::new(HookEvents::CALL)));
+        assert_eq!(names(&index), ["c", "d", "b"]);
+        assert_eq!(hooks(&index, 0), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn empty_ranges_match_nothing() {
+        let mut index = HookIndex::default();
+
+        let empty = HookInterest::new(HookEvents::CALL).with_address_range(0x1000u64..0x1000u64);
+        index.insert(HookEntry::new("empty".to_owned(), empty));
+
+        assert!(hooks(&index, 0x1000).is_empty());
+        assert!(index.hooks(HookEvents::CALL, None).iter().next().is_none());
+    }
+}
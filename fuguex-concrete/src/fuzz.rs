@@ -0,0 +1,638 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use fugue::bytes::Order;
+use fugue::ir::il::Location;
+use fugue::ir::{Address, AddressValue};
+
+use fuguex_hooks::types::{Error as HookError, HookCallAction, HookCallSkip, HookOutcome};
+use fuguex_machine::{Interpreter, Machine, StepOutcome};
+use fuguex_state::pcode;
+use fuguex_state::traits::StateOps;
+
+use crate::crash::{BranchHistory, CrashReport};
+use crate::hooks::{ClonableHookConcrete, HookConcrete, HookEvents, HookInterest};
+use crate::{ConcreteContext, ConcreteState};
+
+use thiserror::Error;
+
+pub const DEFAULT_STEP_BUDGET: usize = 1_000_000;
+
+/// The name of the hook serving the input to `read` calls.
+pub const INPUT_HOOK: &str = "fuguex-fuzz-input";
+
+/// The name of the hook recording the branch history for crash reports.
+pub const BRANCH_HISTORY_HOOK: &str = "fuguex-fuzz-branch-history";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Context(#[from] crate::Error),
+    #[error("input hook `fuguex-fuzz-input` is missing from the context")]
+    MissingInputHook,
+    #[error(transparent)]
+    State(#[from] pcode::Error),
+    #[error("unknown register `{0}`")]
+    UnknownRegister(String),
+}
+
+/// Where each input is placed before a run.
+#[derive(Debug, Clone)]
+pub enum InputPlacement {
+    /// Write the input to a buffer at `address`, truncated to `max_size`
+    /// bytes; its length is written to `size_register`, if any.
+    Buffer {
+        address: Address,
+        max_size: usize,
+        size_register: Option<String>,
+    },
+    /// Write the input to the buffer pointed to by `register`, truncated
+    /// to `max_size` bytes; its length is written to `size_register`, if
+    /// any.
+    RegisterPointer {
+        register: String,
+        max_size: usize,
+        size_register: Option<String>,
+    },
+    /// Serve the input to calls of the `read` function at `address`, as
+    /// `read(fd, buf, count)`; only reads from `fd` are served, if given.
+    ReadCall {
+        address: Address,
+        fd: Option<u64>,
+    },
+}
+
+impl InputPlacement {
+    pub fn buffer(address: impl Into<Address>, max_size: usize) -> Self {
+        Self::Buffer { address: address.into(), max_size, size_register: None }
+    }
+
+    pub fn register_pointer<S: Into<String>>(register: S, max_size: usize) -> Self {
+        Self::RegisterPointer { register: register.into(), max_size, size_register: None }
+    }
+
+    pub fn read_call(address: impl Into<Address>) -> Self {
+        Self::ReadCall { address: address.into(), fd: None }
+    }
+
+    /// Write the input length to `register`; has no effect for
+    /// `ReadCall`.
+    pub fn with_size_register<S: Into<String>>(self, register: S) -> Self {
+        match self {
+            Self::Buffer { address, max_size, .. } => {
+                Self::Buffer { address, max_size, size_register: Some(register.into()) }
+            },
+            Self::RegisterPointer { register: pointer, max_size, .. } => {
+                Self::RegisterPointer { register: pointer, max_size, size_register: Some(register.into()) }
+            },
+            placement => placement,
+        }
+    }
+
+    /// Only serve reads from `fd`; has no effect unless `ReadCall`.
+    pub fn with_fd(self, fd: u64) -> Self {
+        match self {
+            Self::ReadCall { address, .. } => Self::ReadCall { address, fd: Some(fd) },
+            placement => placement,
+        }
+    }
+}
+
+/// Serves an input to `read(fd, buf, count)` calls, consuming it across
+/// calls; reads past its end return zero.
+pub struct InputReader<O, R> {
+    input: Arc<[u8]>,
+    offset: usize,
+    fd: Option<u64>,
+    marker: PhantomData<(O, R)>,
+}
+
+impl<O, R> Clone for InputReader<O, R> {
+    fn clone(&self) -> Self {
+        Self {
+            input: self.input.clone(),
+            offset: self.offset,
+            fd: self.fd,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<O, R> InputReader<O, R> {
+    pub fn new(fd: Option<u64>) -> Self {
+        Self {
+            input: Arc::from(Vec::new()),
+            offset: 0,
+            fd,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn set_input(&mut self, input: &[u8]) {
+        self.input = Arc::from(input);
+        self.offset = 0;
+    }
+
+    /// The number of input bytes read so far.
+    pub fn consumed(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<O, R> HookConcrete for InputReader<O, R>
+where
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = ConcreteState<O>;
+    type Error = pcode::Error;
+    type Outcome = R;
+
+    fn hook_call(
+        &mut self,
+        state: &mut Self::State,
+        _destination: &Address,
+    ) -> Result<HookOutcome<HookCallAction<Self::Outcome>>, HookError<Self::Error>> {
+        let fd = state.argument(0).map_err(HookError::state)?;
+        if matches!(self.fd, Some(expected) if expected != fd) {
+            return Ok(HookCallAction::Pass.into())
+        }
+
+        let buffer = state.argument(1).map_err(HookError::state)?;
+        let count = state.argument(2).map_err(HookError::state)? as usize;
+
+        let remaining = &self.input[self.offset..];
+        let bytes = &remaining[..count.min(remaining.len())];
+
+        if !bytes.is_empty() {
+            state.set_values(Address::from(buffer), bytes).map_err(HookError::state)?;
+        }
+        self.offset += bytes.len();
+
+        Ok(HookOutcome::from(HookCallAction::SkipWith(
+            HookCallSkip::new().with_return_value(bytes.len() as u64)
+        )).state_changed(true))
+    }
+}
+
+impl<O, R> ClonableHookConcrete for InputReader<O, R>
+where
+    O: Order + 'static,
+    R: 'static,
+{
+}
+
+#[derive(Debug)]
+pub enum ExecutionOutcome<R> {
+    /// Reached an end address.
+    Completed,
+    /// Halted by a hook or intrinsic, e.g., a modelled `exit`.
+    Halted(R),
+    /// Exhausted the step budget.
+    Timeout,
+    /// Stopped by an error, e.g., an invalid memory access.
+    Crashed(crate::Error),
+}
+
+impl<R> ExecutionOutcome<R> {
+    pub fn is_crash(&self) -> bool {
+        matches!(self, Self::Crashed(_))
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Timeout)
+    }
+}
+
+#[derive(Debug)]
+pub struct Execution<R> {
+    pub outcome: ExecutionOutcome<R>,
+    /// The number of instructions executed.
+    pub steps: usize,
+    /// The address of the last instruction stepped.
+    pub address: Address,
+}
+
+/// A persistent-mode executor: each input is run from a snapshot of the
+/// context at `start` until an end address is reached, the program halts
+/// or crashes, or the step budget is exhausted.
+///
+/// Hooks in the snapshot, e.g., a `CoverageMap`, are inspected after a run
+/// through `context`; they are restored before the next.
+pub struct Executor<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> {
+    machine: Machine<ConcreteContext<O, R, { OPERAND_SIZE }>>,
+    snapshot: ConcreteContext<O, R, { OPERAND_SIZE }>,
+    start: Address,
+    ends: Vec<Address>,
+    budget: Option<usize>,
+    placement: InputPlacement,
+    executions: usize,
+}
+
+impl<O, R, const OPERAND_SIZE: usize> Executor<O, R, { OPERAND_SIZE }>
+where
+    O: Order + 'static,
+    R: Clone + Default + 'static,
+{
+    /// Snapshot `context`, e.g., after running it up to `start`.
+    pub fn new(
+        mut context: ConcreteContext<O, R, { OPERAND_SIZE }>,
+        start: impl Into<Address>,
+        placement: InputPlacement,
+    ) -> Result<Self, Error> {
+        if let InputPlacement::ReadCall { address, fd } = placement {
+            context.add_hook_with_interest(
+                INPUT_HOOK,
+                InputReader::<O, R>::new(fd),
+                HookInterest::new(HookEvents::CALL).with_address(address),
+            )?;
+        }
+
+        Ok(Self {
+            machine: Machine::new(context.fork()),
+            snapshot: context,
+            start: start.into(),
+            ends: Vec::new(),
+            budget: Some(DEFAULT_STEP_BUDGET),
+            placement,
+            executions: 0,
+        })
+    }
+
+    pub fn with_end(mut self, end: impl Into<Address>) -> Self {
+        self.ends.push(end.into());
+        self
+    }
+
+    /// Limit each run to `steps` instructions; `None` is unbounded.
+    pub fn with_budget(self, steps: Option<usize>) -> Self {
+        Self { budget: steps, ..self }
+    }
+
+    /// Record the last `capacity` control transfers of each run, for
+    /// inclusion in crash reports.
+    pub fn with_branch_history(mut self, capacity: usize) -> Result<Self, Error> {
+        self.snapshot.add_hook(
+            BRANCH_HISTORY_HOOK,
+            BranchHistory::<ConcreteState<O>, O, R>::new(capacity),
+        )?;
+        Ok(self)
+    }
+
+    pub fn context(&self) -> &ConcreteContext<O, R, { OPERAND_SIZE }> {
+        self.machine.interpreter()
+    }
+
+    pub fn context_mut(&mut self) -> &mut ConcreteContext<O, R, { OPERAND_SIZE }> {
+        self.machine.interpreter_mut()
+    }
+
+    /// The context restored before each run; changes apply from the next.
+    pub fn snapshot_mut(&mut self) -> &mut ConcreteContext<O, R, { OPERAND_SIZE }> {
+        &mut self.snapshot
+    }
+
+    pub fn executions(&self) -> usize {
+        self.executions
+    }
+
+    /// Restore the snapshot, place `input` and run it.
+    pub fn run(&mut self, input: &[u8]) -> Result<Execution<R>, Error> {
+        self.machine.interpreter_mut().restore(&self.snapshot);
+        self.place(input)?;
+        self.executions += 1;
+
+        let space = self.machine.interpreter().state().memory_space();
+        let mut location = Location::from(AddressValue::new(space, u64::from(self.start)));
+        let mut address = self.start;
+        let mut steps = 0;
+
+        let outcome = loop {
+            let current = Address::from(&*location.address());
+
+            if self.ends.contains(&current) {
+                break ExecutionOutcome::Completed
+            }
+
+            if matches!(self.budget, Some(budget) if steps >= budget) {
+                break ExecutionOutcome::Timeout
+            }
+
+            address = current;
+            steps += 1;
+
+            match self.machine.step(location) {
+                Ok(StepOutcome::Branch(next)) => location = Location::from(next),
+                Ok(StepOutcome::Halt(outcome)) => break ExecutionOutcome::Halted(outcome),
+                Ok(StepOutcome::Reached) => break ExecutionOutcome::Completed,
+                Err(error) => break ExecutionOutcome::Crashed(error),
+            }
+        };
+
+        Ok(Execution { outcome, steps, address })
+    }
+
+    /// Report the crash of the last run, `execution`, if it crashed or was
+    /// halted, e.g., by a sanitiser; its call stack is included if the
+    /// snapshot tracks calls.
+    pub fn crash_report(&self, execution: &Execution<R>) -> Option<CrashReport> {
+        let mut report = CrashReport::from_execution(execution)?;
+
+        if let Some(call_stack) = self.context().call_stack() {
+            report = report.with_call_stack(call_stack.call_sites());
+        }
+
+        let history = self.context()
+            .find_hook::<_, BranchHistory<ConcreteState<O>, O, R>>(BRANCH_HISTORY_HOOK);
+
+        Some(match history {
+            Some(history) => report.with_branches(history.history()),
+            None => report,
+        })
+    }
+
+    fn place(&mut self, input: &[u8]) -> Result<(), Error> {
+        let context = self.machine.interpreter_mut();
+
+        match self.placement {
+            InputPlacement::Buffer { address, max_size, ref size_register } => {
+                let input = &input[..input.len().min(max_size)];
+                write_input(context.state_mut(), address, input, size_register.as_deref())
+            },
+            InputPlacement::RegisterPointer { ref register, max_size, ref size_register } => {
+                let input = &input[..input.len().min(max_size)];
+                let address = read_register(context.state(), register)?;
+                write_input(context.state_mut(), address.into(), input, size_register.as_deref())
+            },
+            InputPlacement::ReadCall { .. } => {
+                context.find_hook_mut::<_, InputReader<O, R>>(INPUT_HOOK)
+                    .ok_or(Error::MissingInputHook)?
+                    .set_input(input);
+                Ok(())
+            },
+        }
+    }
+}
+
+fn write_input<O: Order>(
+    state: &mut ConcreteState<O>,
+    address: Address,
+    input: &[u8],
+    size_register: Option<&str>,
+) -> Result<(), Error> {
+    if !input.is_empty() {
+        state.set_values(address, input)?;
+    }
+
+    if let Some(register) = size_register {
+        write_register(state, register, input.len() as u64)?;
+    }
+
+    Ok(())
+}
+
+fn read_register<O: Order>(state: &ConcreteState<O>, name: &str) -> Result<u64, Error> {
+    let register = state.registers()
+        .register_by_name(name)
+        .ok_or_else(|| Error::UnknownRegister(name.to_owned()))?;
+
+    let size = register.size().min(8);
+    let mut buf = vec![0u8; register.size()];
+    state.registers()
+        .get_register_values(&register, &mut buf)
+        .map_err(pcode::Error::Register)?;
+
+    let mut bytes = [0u8; 8];
+    let value = if O::ENDIAN.is_big() {
+        bytes[8 - size..].copy_from_slice(&buf[buf.len() - size..]);
+        u64::from_be_bytes(bytes)
+    } else {
+        bytes[..size].copy_from_slice(&buf[..size]);
+        u64::from_le_bytes(bytes)
+    };
+
+    Ok(value)
+}
+
+fn write_register<O: Order>(state: &mut ConcreteState<O>, name: &str, value: u64) -> Result<(), Error> {
+    let register = state.registers()
+        .register_by_name(name)
+        .ok_or_else(|| Error::UnknownRegister(name.to_owned()))?;
+
+    let size = register.size();
+    let mut buf = vec![0u8; size];
+    let width = size.min(8);
+
+    if O::ENDIAN.is_big() {
+        buf[size - width..].copy_from_slice(&value.to_be_bytes()[8 - width..]);
+    } else {
+        buf[..width].copy_from_slice(&value.to_le_bytes()[..width]);
+    }
+
+    state.registers_mut()
+        .set_register_values(&register, &buf)
+        .map_err(pcode::Error::Register)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fugue::bytes::LE;
+
+    use fuguex_hooks::types::HookStepAction;
+
+    use crate::testing;
+
+    const CODE: u64 = 0x1000;
+    const STACK: u64 = 0x8000;
+    const READ: u64 = 0x2000;
+    // within the page of zeros mapped after the code
+    const DATA: u64 = 0x1800;
+
+    type Context = testing::Context<LE>;
+
+    // `mov r32, imm32`, for the register numbered `register`
+    fn mov(register: u8, value: u64) -> Vec<u8> {
+        let mut bytes = vec![0xb8 + register];
+        bytes.extend_from_slice(&(value as u32).to_le_bytes());
+        bytes
+    }
+
+    const EAX: u8 = 0;
+    const EDX: u8 = 2;
+    const ESI: u8 = 6;
+    const EDI: u8 = 7;
+
+    // `mov eax, dword [address]`
+    fn load_eax(address: u64) -> Vec<u8> {
+        let mut bytes = vec![0x8b, 0x04, 0x25];
+        bytes.extend_from_slice(&(address as u32).to_le_bytes());
+        bytes
+    }
+
+    fn context(code: &[u8]) -> (Context, u64) {
+        let context = testing::context::<LE>("x86:LE:64:default", CODE, code, STACK);
+        (context, CODE + code.len() as u64)
+    }
+
+    fn executor(context: Context, end: u64, placement: InputPlacement) -> Executor<LE, (), 64> {
+        Executor::new(context, CODE, placement).unwrap().with_end(end)
+    }
+
+    fn register(executor: &Executor<LE, (), 64>, name: &str) -> u64 {
+        read_register(executor.context().state(), name).unwrap()
+    }
+
+    fn memory(executor: &Executor<LE, (), 64>, address: u64, size: usize) -> Vec<u8> {
+        executor.context().state().view_values_from(address).unwrap()[..size].to_vec()
+    }
+
+    #[test]
+    fn buffer() {
+        let (context, end) = context(&load_eax(DATA));
+        let placement = InputPlacement::buffer(DATA, 4).with_size_register("RSI");
+        let mut executor = executor(context, end, placement);
+
+        let execution = executor.run(&[1, 2, 3, 4, 5]).unwrap();
+        assert!(matches!(execution.outcome, ExecutionOutcome::Completed));
+        assert_eq!(execution.steps, 1);
+
+        // the input is truncated to the buffer
+        assert_eq!(register(&executor, "RAX"), 0x04030201);
+        assert_eq!(register(&executor, "RSI"), 4);
+        assert_eq!(memory(&executor, DATA, 5), [1, 2, 3, 4, 0]);
+    }
+
+    #[test]
+    fn register_pointer() {
+        // mov eax, dword [rdi]
+        let (mut context, end) = context(&[0x8b, 0x07]);
+        write_register(context.state_mut(), "RDI", DATA).unwrap();
+
+        let placement = InputPlacement::register_pointer("RDI", 8).with_size_register("RSI");
+        let mut executor = executor(context, end, placement);
+
+        let execution = executor.run(&[1, 2, 3]).unwrap();
+        assert!(matches!(execution.outcome, ExecutionOutcome::Completed));
+
+        assert_eq!(register(&executor, "RAX"), 0x030201);
+        assert_eq!(register(&executor, "RSI"), 3);
+
+        // a missing register is reported before the run
+        let (context, end) = self::context(&[0x90]);
+        let mut executor = self::executor(context, end, InputPlacement::register_pointer("R99", 8));
+        assert!(matches!(executor.run(&[1]), Err(Error::UnknownRegister(name)) if name == "R99"));
+    }
+
+    #[test]
+    fn read_call() {
+        // read(0, DATA, 2); read(0, DATA + 2, 4)
+        let mut code = Vec::new();
+        code.extend(mov(EDI, 0));
+        code.extend(mov(ESI, DATA));
+        code.extend(mov(EDX, 2));
+        code.extend(testing::call_rel32(CODE + code.len() as u64, READ));
+        code.extend(mov(ESI, DATA + 2));
+        code.extend(mov(EDX, 4));
+        code.extend(testing::call_rel32(CODE + code.len() as u64, READ));
+
+        let (context, end) = context(&code);
+        let mut executor = executor(context, end, InputPlacement::read_call(READ).with_fd(0));
+
+        let execution = executor.run(&[1, 2, 3]).unwrap();
+        assert!(matches!(execution.outcome, ExecutionOutcome::Completed));
+
+        // the second read returns what remains of the input
+        assert_eq!(register(&executor, "RAX"), 1);
+        assert_eq!(memory(&executor, DATA, 4), [1, 2, 3, 0]);
+
+        let reader = executor.context()
+            .find_hook::<_, InputReader<LE, ()>>(INPUT_HOOK)
+            .unwrap();
+        assert_eq!(reader.consumed(), 3);
+
+        // and reads past its end return zero
+        executor.run(&[]).unwrap();
+        assert_eq!(register(&executor, "RAX"), 0);
+    }
+
+    #[test]
+    fn read_call_other_fd() {
+        // read(1, DATA, 2), which is not served and so is skipped by a
+        // hook returning -1
+        let mut code = Vec::new();
+        code.extend(mov(EDI, 1));
+        code.extend(mov(ESI, DATA));
+        code.extend(mov(EDX, 2));
+        code.extend(testing::call_rel32(CODE + code.len() as u64, READ));
+
+        let (mut context, end) = context(&code);
+        context.on_call("unserved", READ, |_: &mut ConcreteState<LE>, _: &Address| {
+            HookCallAction::SkipWith(HookCallSkip::new().with_return_value(u64::MAX))
+        }).unwrap();
+
+        let mut executor = executor(context, end, InputPlacement::read_call(READ).with_fd(0));
+
+        let execution = executor.run(&[1, 2]).unwrap();
+        assert!(matches!(execution.outcome, ExecutionOutcome::Completed));
+        assert_eq!(register(&executor, "RAX"), u64::MAX);
+        assert_eq!(memory(&executor, DATA, 2), [0, 0]);
+    }
+
+    #[test]
+    fn timeout() {
+        // jmp $
+        let (context, _) = context(&[0xeb, 0xfe]);
+        let mut executor = Executor::<LE, (), 64>::new(context, CODE, InputPlacement::buffer(DATA, 1))
+            .unwrap()
+            .with_budget(Some(10));
+
+        let execution = executor.run(&[]).unwrap();
+        assert!(execution.outcome.is_timeout());
+        assert!(!execution.outcome.is_crash());
+        assert_eq!(execution.steps, 10);
+        assert_eq!(u64::from(execution.address), CODE);
+
+        // reaching an end within the budget completes
+        let (context, end) = self::context(&mov(EAX, 1));
+        let mut executor = self::executor(context, end, InputPlacement::buffer(DATA, 1))
+            .with_budget(Some(1));
+
+        let execution = executor.run(&[]).unwrap();
+        assert!(matches!(execution.outcome, ExecutionOutcome::Completed));
+    }
+
+    #[test]
+    fn restore() {
+        // mov eax, dword [DATA + 0x100]; mov dword [DATA + 0x100], 1
+        let mut code = load_eax(DATA + 0x100);
+        code.extend([0xc7, 0x04, 0x25]);
+        code.extend(((DATA + 0x100) as u32).to_le_bytes());
+        code.extend(1u32.to_le_bytes());
+
+        let (mut context, end) = context(&code);
+
+        // halts when executed more than once since it was snapshotted
+        let mut executed = 0;
+        context.on_instruction("once", CODE..CODE + 1, move |_: &mut ConcreteState<LE>, _: &Address| {
+            executed += 1;
+            if executed > 1 { HookStepAction::Halt(()) } else { HookStepAction::Pass }
+        }).unwrap();
+
+        let mut executor = executor(context, end, InputPlacement::buffer(DATA, 1));
+
+        for _ in 0..2 {
+            let execution = executor.run(&[]).unwrap();
+            assert!(matches!(execution.outcome, ExecutionOutcome::Completed));
+
+            // the write of the previous run is not seen
+            assert_eq!(register(&executor, "RAX"), 0);
+            assert_eq!(memory(&executor, DATA + 0x100, 4), [1, 0, 0, 0]);
+        }
+
+        assert_eq!(executor.executions(), 2);
+    }
+}
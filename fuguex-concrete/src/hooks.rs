@@ -1,5 +1,9 @@
+use std::any::Any;
+use std::ops::{BitOr, BitOrAssign, Range};
+
 use fugue::ir::il::ecode::Location;
 use fugue::ir::il::pcode::{Operand, PCodeOp, Register};
+use fugue::ir::error::Error as LiftError;
 use fugue::ir::Address;
 use fuguex_machine::StepState;
 use fuguex_microx::types::HookInvalidAccessAction;
@@ -9,10 +13,137 @@ use downcast_rs::{impl_downcast, Downcast};
 use dyn_clone::{clone_trait_object, DynClone};
 
 use fuguex_hooks::{
-    Error, HookAction, HookCBranchAction, HookCallAction, HookOutcome, HookStepAction,
+    Error, HookAction, HookBranchAction, HookCBranchAction, HookCallAction, HookLiftAction,
+    HookOutcome, HookStepAction,
 };
 use fuguex_microx::ViolationSource;
 
+/// A set of hook event kinds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HookEvents(u16);
+
+impl HookEvents {
+    pub const NONE: Self = Self(0);
+    pub const MEMORY_READ: Self = Self(1 << 0);
+    pub const MEMORY_WRITE: Self = Self(1 << 1);
+    pub const INVALID_MEMORY_ACCESS: Self = Self(1 << 2);
+    pub const REGISTER_READ: Self = Self(1 << 3);
+    pub const REGISTER_WRITE: Self = Self(1 << 4);
+    pub const CALL: Self = Self(1 << 5);
+    pub const CBRANCH: Self = Self(1 << 6);
+    pub const OPERATION_STEP: Self = Self(1 << 7);
+    pub const ARCHITECTURAL_STEP: Self = Self(1 << 8);
+    pub const IBRANCH: Self = Self(1 << 9);
+    pub const RETURN: Self = Self(1 << 10);
+    pub const LIFT_ERROR: Self = Self(1 << 11);
+    pub const ALL: Self = Self((1 << 12) - 1);
+
+    pub(crate) const COUNT: usize = 12;
+
+    pub fn contains(&self, events: Self) -> bool {
+        self.0 & events.0 == events.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..Self::COUNT).filter(move |i| self.0 & (1 << i) != 0)
+    }
+
+    pub(crate) fn index(&self) -> usize {
+        self.0.trailing_zeros() as usize
+    }
+}
+
+impl BitOr for HookEvents {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for HookEvents {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// The events a hook is dispatched, declared when it is registered.
+///
+/// Address ranges restrict memory events to accesses overlapping them,
+/// calls to destinations within them, and step, conditional branch,
+/// indirect branch, return and lift error events to instructions within
+/// them. Registers restrict register events to accesses overlapping them.
+/// Operands that are neither memory nor registers are only dispatched to
+/// memory hooks without address ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookInterest {
+    events: HookEvents,
+    addresses: Vec<Range<Address>>,
+    registers: Vec<Range<u64>>,
+}
+
+impl Default for HookInterest {
+    fn default() -> Self {
+        Self::new(HookEvents::ALL)
+    }
+}
+
+impl HookInterest {
+    pub fn new(events: HookEvents) -> Self {
+        Self {
+            events,
+            addresses: Vec::default(),
+            registers: Vec::default(),
+        }
+    }
+
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Restrict the hook to `range`; an empty range matches nothing, so a
+    /// hook restricted only to empty ranges is never dispatched the events
+    /// they filter.
+    pub fn with_address_range<A: Into<Address>>(mut self, range: Range<A>) -> Self {
+        self.addresses.push(range.start.into()..range.end.into());
+        self
+    }
+
+    pub fn with_address<A: Into<Address>>(self, address: A) -> Self {
+        let address = address.into();
+        self.with_address_range(address..address + 1usize)
+    }
+
+    pub fn with_register(mut self, register: &Register) -> Self {
+        let offset = u64::from(register.offset());
+        self.registers.push(offset..offset + register.size() as u64);
+        self
+    }
+
+    pub fn events(&self) -> HookEvents {
+        self.events
+    }
+
+    pub fn address_ranges(&self) -> &[Range<Address>] {
+        &self.addresses
+    }
+
+    pub fn registers(&self) -> &[Range<u64>] {
+        &self.registers
+    }
+
+    pub fn is_interested_in_register(&self, register: &Register) -> bool {
+        let offset = u64::from(register.offset());
+        let range = offset..offset + register.size() as u64;
+        self.registers.is_empty()
+            || self.registers.iter().any(|r| r.start < range.end && range.start < r.end)
+    }
+}
+
 #[allow(unused)]
 pub trait HookConcrete: Downcast {
     type State: StateOps;
@@ -120,6 +251,35 @@ pub trait HookConcrete: Downcast {
         Ok(HookCBranchAction::Pass.into())
     }
 
+    /// Called with the computed destination of an indirect branch.
+    fn hook_ibranch(
+        &mut self,
+        state: &mut Self::State,
+        destination: &Address,
+    ) -> Result<HookOutcome<HookBranchAction<Self::Outcome>>, Error<Self::Error>> {
+        Ok(HookBranchAction::Pass.into())
+    }
+
+    /// Called with the computed destination of a return.
+    fn hook_return(
+        &mut self,
+        state: &mut Self::State,
+        destination: &Address,
+    ) -> Result<HookOutcome<HookBranchAction<Self::Outcome>>, Error<Self::Error>> {
+        Ok(HookBranchAction::Pass.into())
+    }
+
+    /// Called when the instruction at `address` cannot be lifted, e.g.,
+    /// as it is from an unsupported extension.
+    fn hook_lift_error(
+        &mut self,
+        state: &mut Self::State,
+        address: &Address,
+        error: &LiftError,
+    ) -> Result<HookOutcome<HookLiftAction<Self::Outcome>>, Error<Self::Error>> {
+        Ok(HookLiftAction::Pass.into())
+    }
+
     fn hook_operation_step(
         &mut self,
         state: &mut Self::State,
@@ -137,6 +297,19 @@ pub trait HookConcrete: Downcast {
     ) -> Result<HookOutcome<HookStepAction<Self::Outcome>>, Error<Self::Error>> {
         Ok(HookStepAction::Pass.into())
     }
+
+    /// Called on the hook's clone in a forked context.
+    fn hook_fork(&mut self) {}
+
+    /// Called when the hook's context is restored to a fork holding
+    /// `other`, the hook registered under the same name there. Returns
+    /// `false` to be replaced by a clone of `other` (the default); hooks
+    /// that restore incrementally, or that keep global data such as
+    /// coverage across paths, should update themselves and return `true`.
+    /// Hooks not registered in the fork are removed.
+    fn hook_restore(&mut self, other: &dyn Any) -> bool {
+        false
+    }
 }
 
 pub trait ClonableHookConcrete: DynClone + HookConcrete {}
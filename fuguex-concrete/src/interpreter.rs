@@ -1,9 +1,12 @@
 use std::marker::PhantomData;
+use std::ops::Range;
 use std::sync::Arc;
 
 use fnv::FnvHashMap as Map;
 use parking_lot::{RwLock, RwLockReadGuard};
 
+use downcast_rs::Downcast;
+
 use fugue::bytes::traits::ByteCast;
 use fugue::bytes::Order;
 
@@ -12,16 +15,28 @@ use fugue::db::Database;
 use fugue::fp::{self, float_format_from_size, Float, FloatFormat, FloatFormatOps};
 
 use fugue::ir::disassembly::ContextDatabase;
-use fugue::ir::il::pcode::{Operand, PCodeOp};
+use fugue::ir::il::pcode::{Operand, PCode, PCodeOp};
 use fugue::ir::il::Location;
 use fugue::ir::{
     self, Address, AddressSpace, AddressSpaceId, AddressValue, IntoAddress, Translator,
 };
 
-use crate::hooks::ClonableHookConcrete;
-use fuguex_hooks::types::{HookCBranchAction, HookCallAction};
+use crate::callbacks::{
+    CallCallback, CallbackAction, InstructionCallback, MemoryReadCallback, MemoryWriteCallback,
+};
+use crate::callstack::{CallStack, Frame};
+use crate::context::{self, ContextSwitch, LiftCache};
+use crate::dispatch::{HookEntry, HookIndex};
+use crate::hooks::{ClonableHookConcrete, HookEvents, HookInterest};
+use crate::overrides::{InstructionOverride, SemanticFn};
+use crate::state::ConcreteState;
+use crate::symbols;
+use fuguex_hooks::types::{
+    HookAction, HookBranchAction, HookCBranchAction, HookCallAction, HookCallSkip, HookLiftAction,
+    HookStepAction,
+};
 
-use fuguex_intrinsics::{IntrinsicAction, IntrinsicHandler};
+use fuguex_intrinsics::{IntrinsicAction, IntrinsicBehaviour, IntrinsicHandler};
 
 use fuguex_loader::LoaderMapping;
 
@@ -45,33 +60,58 @@ pub enum Error {
     DivisionByZero,
     #[error(transparent)]
     Hook(fuguex_hooks::types::Error<pcode::Error>),
+    #[error("hook `{0}` already exists")]
+    HookExists(String),
+    #[error("unknown context variable `{0}`")]
+    UnknownContextVariable(String),
     #[error(transparent)]
     Intrinsic(fuguex_intrinsics::Error<pcode::Error>),
     #[error("error lifting instruction at {0}: {1}")]
     Lift(Address, #[source] ir::error::Error),
+    #[error("hook skipped the instruction at {0} with a length of zero")]
+    ZeroLengthSkip(Address),
     #[error(transparent)]
     State(#[from] pcode::Error),
+    #[error(transparent)]
+    Symbol(#[from] symbols::Error),
     #[error("incompatible operand sizes of {0} bytes and {1} bytes")]
     IncompatibleOperands(usize, usize),
     #[error("unsupported address size of {} bits", .0 * 8)]
     UnsupportedAddressSize(usize),
     #[error("unsupported branch destination in space `{}`", .0.index())]
     UnsupportedBranchDestination(AddressSpaceId),
+    #[error("unknown hook `{0}`")]
+    UnknownHook(String),
+    #[error("unknown register `{0}`")]
+    UnknownRegister(String),
     #[error(transparent)]
     UnsupportedFloatFormat(#[from] fp::Error),
     #[error("unsupported operand size of {0} bytes; maximum supported is {1} bytes")]
     UnsupportedOperandSize(usize, usize),
 }
 
-pub type ConcreteState<O> = PCodeState<u8, O>;
-
 #[derive(Clone)]
 pub struct ConcreteContext<O: Order, R, const OPERAND_SIZE: usize> {
     database: Option<Arc<Database>>,
     translator: Arc<Translator>,
     translator_context: ContextDatabase,
-    translator_cache: Arc<RwLock<Map<Address, StepState>>>,
-    hook_names: Map<String, usize>,
+    translator_cache: Arc<RwLock<LiftCache>>,
+    context_switches: Vec<ContextSwitch>,
+    // the context variables instructions are cached by: those switched,
+    // and those ever set explicitly
+    context_variables: Vec<String>,
+    // the values of those variables, and the bytes of the registers they
+    // are switched by, as of the last lift
+    context_values: Vec<u32>,
+    context_bytes: Vec<u8>,
+    hook_index: HookIndex,
+    // the outcome a hook on an operand halted with during the current
+    // operation
+    halt: Option<R>,
+    symbol_offset: u64,
+    return_mismatch: Option<fn(Address, Address) -> R>,
+    overrides: Map<Address, InstructionOverride<O, R>>,
+    call_arguments: Map<Address, usize>,
     hooks: Vec<
         Box<dyn ClonableHookConcrete<State = ConcreteState<O>, Error = pcode::Error, Outcome = R>>,
     >,
@@ -137,31 +177,56 @@ impl ToSignedBytes for BitVec {
 impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
     ConcreteContext<O, R, { OPERAND_SIZE }>
 {
-    pub fn new(translator: Translator, state: ConcreteState<O>) -> Self {
+    pub fn new<S: Into<ConcreteState<O>>>(translator: Translator, state: S) -> Self {
         Self {
             database: None,
             translator_context: translator.context_database(),
             translator_cache: Arc::new(RwLock::new(Map::default())),
+            context_switches: Vec::default(),
+            context_variables: Vec::default(),
+            context_values: Vec::default(),
+            context_bytes: Vec::default(),
             translator: Arc::new(translator),
-            hook_names: Map::default(),
+            hook_index: HookIndex::default(),
+            halt: None,
+            symbol_offset: 0,
+            return_mismatch: None,
+            overrides: Map::default(),
+            call_arguments: Map::default(),
             hooks: Vec::default(),
             intrinsics: IntrinsicHandler::default(),
-            state,
+            state: state.into(),
             marker: PhantomData,
         }
     }
 
-    pub fn from_loader(loader: impl LoaderMapping<ConcreteState<O>>) -> Self {
+    pub fn from_loader(loader: impl LoaderMapping<PCodeState<u8, O>>) -> Self {
         let database = loader.database();
+        let symbol_offset = loader.symbol_offset();
+        let context_variables = loader.context_variables();
         let translator = loader.translator();
-        let state = loader.into_state();
+        let state = ConcreteState::new(loader.into_state());
+
+        let mut translator_context = translator.context_database();
+        for (variable, value) in context_variables.iter() {
+            translator_context.set_variable_default(variable, *value);
+        }
 
         Self {
             database,
-            translator_context: translator.context_database(),
+            translator_context,
             translator_cache: Arc::new(RwLock::new(Map::default())),
+            context_switches: Vec::default(),
+            context_variables: Vec::default(),
+            context_values: Vec::default(),
+            context_bytes: Vec::default(),
             translator,
-            hook_names: Map::default(),
+            hook_index: HookIndex::default(),
+            halt: None,
+            symbol_offset,
+            return_mismatch: None,
+            overrides: Map::default(),
+            call_arguments: Map::default(),
             hooks: Vec::default(),
             intrinsics: IntrinsicHandler::default(),
             state,
@@ -169,15 +234,141 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
         }
     }
 
-    pub fn add_hook<S, H>(&mut self, name: S, hook: H)
+    pub fn add_hook<S, H>(&mut self, name: S, hook: H) -> Result<(), Error>
+    where
+        S: AsRef<str>,
+        H: ClonableHookConcrete<State = ConcreteState<O>, Error = pcode::Error, Outcome = R>
+            + 'static,
+    {
+        self.add_hook_with_interest(name, hook, HookInterest::all())
+    }
+
+    /// Add a hook that is only dispatched the events declared by
+    /// `interest`.
+    pub fn add_hook_with_interest<S, H>(&mut self, name: S, hook: H, interest: HookInterest) -> Result<(), Error>
     where
         S: AsRef<str>,
         H: ClonableHookConcrete<State = ConcreteState<O>, Error = pcode::Error, Outcome = R>
             + 'static,
     {
-        let index = self.hooks.len();
-        self.hook_names.insert(name.as_ref().into(), index);
-        self.hooks.push(Box::new(hook));
+        self.insert_hook(HookEntry::new(name.as_ref().to_owned(), interest), Box::new(hook))
+    }
+
+    /// Invoke `callback` with the state and destination of each call to
+    /// `address`.
+    pub fn on_call<S, A, F, T>(&mut self, name: S, address: A, callback: F) -> Result<(), Error>
+    where
+        S: AsRef<str>,
+        A: Into<Address>,
+        F: FnMut(&mut ConcreteState<O>, &Address) -> T + Clone + 'static,
+        T: CallbackAction<HookCallAction<R>>,
+    {
+        let interest = HookInterest::new(HookEvents::CALL).with_address(address);
+        self.add_hook_with_interest(name, CallCallback::new(callback), interest)
+    }
+
+    /// Invoke `callback` with the state and address of each instruction
+    /// executed within `range`.
+    pub fn on_instruction<S, A, F, T>(&mut self, name: S, range: Range<A>, callback: F) -> Result<(), Error>
+    where
+        S: AsRef<str>,
+        A: Into<Address>,
+        F: FnMut(&mut ConcreteState<O>, &Address) -> T + Clone + 'static,
+        T: CallbackAction<HookStepAction<R>>,
+    {
+        let interest = HookInterest::new(HookEvents::ARCHITECTURAL_STEP).with_address_range(range);
+        self.add_hook_with_interest(name, InstructionCallback::new(callback), interest)
+    }
+
+    /// Invoke `callback` with the state, address and size of each memory
+    /// read overlapping `range`.
+    pub fn on_memory_read<S, A, F, T>(&mut self, name: S, range: Range<A>, callback: F) -> Result<(), Error>
+    where
+        S: AsRef<str>,
+        A: Into<Address>,
+        F: FnMut(&mut ConcreteState<O>, &Address, usize) -> T + Clone + 'static,
+        T: CallbackAction<HookAction<R>>,
+    {
+        let interest = HookInterest::new(HookEvents::MEMORY_READ).with_address_range(range);
+        self.add_hook_with_interest(name, MemoryReadCallback::new(callback), interest)
+    }
+
+    /// Invoke `callback` with the state, address and written bytes of each
+    /// memory write overlapping `range`.
+    pub fn on_memory_write<S, A, F, T>(&mut self, name: S, range: Range<A>, callback: F) -> Result<(), Error>
+    where
+        S: AsRef<str>,
+        A: Into<Address>,
+        F: FnMut(&mut ConcreteState<O>, &Address, &[u8]) -> T + Clone + 'static,
+        T: CallbackAction<HookAction<R>>,
+    {
+        let interest = HookInterest::new(HookEvents::MEMORY_WRITE).with_address_range(range);
+        self.add_hook_with_interest(name, MemoryWriteCallback::new(callback), interest)
+    }
+
+    fn insert_hook(
+        &mut self,
+        entry: HookEntry,
+        hook: Box<dyn ClonableHookConcrete<State = ConcreteState<O>, Error = pcode::Error, Outcome = R>>,
+    ) -> Result<(), Error> {
+        if self.hook_index.position(&entry.name).is_some() {
+            return Err(Error::HookExists(entry.name));
+        }
+
+        let position = self.hook_index.insert(entry);
+        self.hooks.insert(position, hook);
+
+        Ok(())
+    }
+
+    fn hook_position<S: AsRef<str>>(&self, name: S) -> Result<usize, Error> {
+        self.hook_index
+            .position(name.as_ref())
+            .ok_or_else(|| Error::UnknownHook(name.as_ref().to_owned()))
+    }
+
+    pub fn remove_hook<S: AsRef<str>>(&mut self, name: S) -> Result<(), Error> {
+        let position = self.hook_position(name)?;
+        self.hook_index.remove(position);
+        self.hooks.remove(position);
+        Ok(())
+    }
+
+    /// Disabled hooks are not dispatched any events, but keep their state
+    /// and position.
+    pub fn set_hook_enabled<S: AsRef<str>>(&mut self, name: S, enabled: bool) -> Result<(), Error> {
+        let position = self.hook_position(name)?;
+        self.hook_index.set_enabled(position, enabled);
+        Ok(())
+    }
+
+    pub fn is_hook_enabled<S: AsRef<str>>(&self, name: S) -> Option<bool> {
+        self.hook_index
+            .position(name)
+            .map(|i| self.hook_index.entry(i).enabled)
+    }
+
+    /// Hooks are dispatched events in descending order of priority, then
+    /// in the order they were added; the default priority is zero.
+    pub fn set_hook_priority<S: AsRef<str>>(&mut self, name: S, priority: i32) -> Result<(), Error> {
+        let position = self.hook_position(name)?;
+        let hook = self.hooks.remove(position);
+
+        let position = self.hook_index.set_priority(position, priority);
+        self.hooks.insert(position, hook);
+
+        Ok(())
+    }
+
+    /// The names of the hooks, in dispatch order.
+    pub fn hook_names(&self) -> impl Iterator<Item = &str> {
+        self.hook_index.entries().iter().map(|e| &*e.name)
+    }
+
+    pub fn hook_interest<S: AsRef<str>>(&self, name: S) -> Option<&HookInterest> {
+        self.hook_index
+            .position(name)
+            .map(|i| &self.hook_index.entry(i).interest)
     }
 
     pub fn find_hook<S, H>(&self, name: S) -> Option<&H>
@@ -186,9 +377,8 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
         H: ClonableHookConcrete<State = ConcreteState<O>, Error = pcode::Error, Outcome = R>
             + 'static,
     {
-        self.hook_names
-            .get(name.as_ref())
-            .copied()
+        self.hook_index
+            .position(name)
             .and_then(|i| self.hooks[i].downcast_ref::<H>())
     }
 
@@ -198,12 +388,203 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
         H: ClonableHookConcrete<State = ConcreteState<O>, Error = pcode::Error, Outcome = R>
             + 'static,
     {
-        self.hook_names
-            .get(name.as_ref())
-            .copied()
+        self.hook_index
+            .position(name)
             .and_then(move |i| self.hooks[i].downcast_mut::<H>())
     }
 
+    /// Add a hook that is only dispatched calls to the function named
+    /// `symbol`; returns the address it resolves to.
+    pub fn add_symbol_hook<S, Y, H>(&mut self, name: S, symbol: Y, hook: H) -> Result<Address, Error>
+    where
+        S: AsRef<str>,
+        Y: AsRef<str>,
+        H: ClonableHookConcrete<State = ConcreteState<O>, Error = pcode::Error, Outcome = R>
+            + 'static,
+    {
+        let address = self.resolve_symbol(symbol.as_ref())?;
+
+        let mut entry = HookEntry::new(
+            name.as_ref().to_owned(),
+            HookInterest::new(HookEvents::CALL).with_address(address),
+        );
+        entry.symbol = Some(symbol.as_ref().to_owned());
+
+        self.insert_hook(entry, Box::new(hook))?;
+
+        Ok(address)
+    }
+
+    /// Resolve the address of the function named `symbol` (or of an import
+    /// thunk or PLT stub for it) using the loaded database.
+    pub fn resolve_symbol<S: AsRef<str>>(&self, symbol: S) -> Result<Address, Error> {
+        let database = self.database
+            .as_deref()
+            .ok_or_else(|| symbols::Error::NoDatabase(symbol.as_ref().to_owned()))?;
+        Ok(symbols::resolve(database, symbol, self.symbol_offset)?)
+    }
+
+    /// Displace the database's addresses by `offset` (wrapping) after its
+    /// image has been rebased, and re-resolve the symbols of hooks added
+    /// with `add_symbol_hook`. The offset of a rebased loader is applied
+    /// by `from_loader`.
+    pub fn rebase_symbols(&mut self, offset: u64) -> Result<(), Error> {
+        let previous = std::mem::replace(&mut self.symbol_offset, offset);
+
+        let resolved = self.hook_index
+            .entries()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.symbol.as_ref().map(|symbol| (i, symbol)))
+            .map(|(i, symbol)| Ok((i, self.resolve_symbol(symbol)?)))
+            .collect::<Result<Vec<_>, Error>>();
+
+        match resolved {
+            Ok(resolved) => {
+                for (position, address) in resolved {
+                    let interest = HookInterest::new(HookEvents::CALL).with_address(address);
+                    self.hook_index.set_interest(position, interest);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.symbol_offset = previous;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn add_intrinsic<I>(&mut self, behaviour: I) -> Result<(), Error>
+    where
+        I: IntrinsicBehaviour<Outcome = R, State = ConcreteState<O>> + 'static,
+    {
+        self.intrinsics
+            .register(behaviour, &self.state)
+            .map_err(Error::Intrinsic)
+    }
+
+    pub fn find_intrinsic<S, I>(&self, name: S) -> Option<&I>
+    where
+        S: AsRef<str>,
+        I: IntrinsicBehaviour<Outcome = R, State = ConcreteState<O>> + 'static,
+    {
+        self.intrinsics.find(name.as_ref())
+    }
+
+    pub fn find_intrinsic_mut<S, I>(&mut self, name: S) -> Option<&mut I>
+    where
+        S: AsRef<str>,
+        I: IntrinsicBehaviour<Outcome = R, State = ConcreteState<O>> + 'static,
+    {
+        self.intrinsics.find_mut(name.as_ref())
+    }
+
+    /// Maintain a shadow call stack from calls and returns; it is kept in
+    /// the state, so hooks can inspect it.
+    pub fn track_calls(&mut self, enabled: bool) {
+        self.state.track_calls(enabled)
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.state.call_stack()
+    }
+
+    pub fn call_stack_mut(&mut self) -> Option<&mut CallStack> {
+        self.state.call_stack_mut()
+    }
+
+    /// The frames of the shadow call stack, innermost first; empty unless
+    /// calls are tracked.
+    pub fn backtrace(&self) -> Vec<Frame> {
+        self.state.backtrace()
+    }
+
+    /// Halt with `outcome(expected, actual)` on returns to an address no
+    /// frame expects, e.g., after the stack is smashed; requires calls to
+    /// be tracked.
+    pub fn halt_on_return_mismatch(&mut self, outcome: Option<fn(Address, Address) -> R>) {
+        self.return_mismatch = outcome;
+    }
+
+    /// Execute `pcode` in place of the instruction at `address`; its
+    /// address is set to `address` and its length gives the fallthrough.
+    pub fn override_instruction<A: Into<Address>>(&mut self, address: A, mut pcode: PCode) {
+        let address = address.into();
+        pcode.address = AddressValue::new(self.state.memory_space(), u64::from(address));
+
+        // overrides are checked before the lifted cache, which is shared
+        // with forks, so are not inserted into it
+        self.overrides.insert(address, InstructionOverride::PCode(StepState::from(pcode)));
+    }
+
+    /// Apply `semantic` to the state in place of executing the instruction
+    /// of `length` bytes at `address`; unless it branches or halts,
+    /// execution continues after the instruction.
+    pub fn override_instruction_with<A, F, T>(&mut self, address: A, length: usize, semantic: F)
+    where
+        A: Into<Address>,
+        F: FnMut(&mut ConcreteState<O>) -> T + Clone + 'static,
+        T: CallbackAction<HookStepAction<R>> + 'static,
+    {
+        let semantic = Box::new(SemanticFn::new(semantic));
+        self.overrides.insert(address.into(), InstructionOverride::Semantic { length, semantic });
+    }
+
+    /// Remove the override of the instruction at `address`; returns
+    /// `false` if there is none.
+    pub fn remove_override<A: Into<Address>>(&mut self, address: A) -> bool {
+        self.overrides.remove(&address.into()).is_some()
+    }
+
+    pub fn has_override<A: Into<Address>>(&self, address: A) -> bool {
+        self.overrides.contains_key(&address.into())
+    }
+
+    /// Record that the function at `address` takes `arguments` integer or
+    /// pointer arguments; when a call to it is skipped under a
+    /// callee-cleanup convention, their stack slots are popped.
+    pub fn set_call_arguments<A: Into<Address>>(&mut self, address: A, arguments: usize) {
+        self.call_arguments.insert(address.into(), arguments);
+    }
+
+    /// Update a decoding context variable from execution as per `switch`;
+    /// instructions are lifted and cached per value of the variable.
+    pub fn add_context_switch(&mut self, switch: ContextSwitch) -> Result<(), Error> {
+        if self.translator_context.get_variable(switch.variable()).is_none() {
+            return Err(Error::UnknownContextVariable(switch.variable().to_owned()))
+        }
+
+        if let ContextSwitch::Register { ref register, .. } = switch {
+            if self.state.registers().register_by_name(register).is_none() {
+                return Err(Error::UnknownRegister(register.clone()))
+            }
+        }
+
+        self.track_context_variable(switch.variable());
+        self.context_switches.push(switch);
+        Ok(())
+    }
+
+    pub fn context_switches(&self) -> &[ContextSwitch] {
+        &self.context_switches
+    }
+
+    pub fn context_variable<S: AsRef<str>>(&self, name: S) -> Option<u32> {
+        self.translator_context.get_variable(name.as_ref())
+    }
+
+    /// Set a decoding context variable for the instructions lifted next;
+    /// it is overwritten by any context switch for the same variable.
+    /// Instructions are then lifted and cached per value of the variable.
+    pub fn set_context_variable<S: AsRef<str>>(&mut self, name: S, value: u32) -> Result<(), Error> {
+        if self.translator_context.set_variable_default(name.as_ref(), value) {
+            self.track_context_variable(name.as_ref());
+            Ok(())
+        } else {
+            Err(Error::UnknownContextVariable(name.as_ref().to_owned()))
+        }
+    }
+
     pub fn database(&self) -> Option<&Database> {
         self.database.as_deref()
     }
@@ -216,10 +597,40 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
         &mut self.state
     }
 
-    pub fn lifted_cache(&self) -> RwLockReadGuard<Map<Address, StepState>> {
+    pub fn lifted_cache(&self) -> RwLockReadGuard<LiftCache> {
         self.translator_cache.read()
     }
 
+    // The range of the current instruction for dispatching `event`, if
+    // any hook filters it by address
+    fn instruction_range(&self, event: HookEvents) -> Result<Option<Range<Address>>, Error> {
+        if self.hook_index.has_filtered(event) {
+            let address = self.state.program_counter_value().map_err(Error::State)?;
+            Ok(Some(address..address + 1usize))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Dispatch the hooks for a read of `operand`, or a write of `value` to
+    // it; if any halts, the first does so once the operation completes
+    fn operand_hooks(&mut self, operand: &Operand, value: Option<&[u8]>) -> Result<(), Error> {
+        for index in self.hook_index.operand_hooks(operand, value.is_some()).iter() {
+            let hook = &mut self.hooks[*index];
+            let outcome = match value {
+                Some(value) => hook.hook_operand_write(&mut self.state, operand, value),
+                None => hook.hook_operand_read(&mut self.state, operand),
+            }
+            .map_err(Error::Hook)?;
+
+            if let HookAction::Halt(r) = outcome.action {
+                self.halt.get_or_insert(r);
+            }
+        }
+
+        Ok(())
+    }
+
     fn read_operand_with<U, F>(
         &mut self,
         operand: &Operand,
@@ -230,10 +641,7 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
     where
         F: Fn(&mut [u8]) -> U,
     {
-        for hook in self.hooks.iter_mut() {
-            hook.hook_operand_read(&mut self.state, operand)
-                .map_err(Error::Hook)?;
-        }
+        self.operand_hooks(operand, None)?;
 
         let res = self.state.with_operand_values(operand, |values| {
             buf.copy_from_slice(values);
@@ -246,8 +654,9 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
 
             debug_assert_eq!(size, buf.len());
 
-            for hook in self.hooks.iter_mut() {
-                let result = hook
+            let range = address..address + size;
+            for index in self.hook_index.hooks(HookEvents::INVALID_MEMORY_ACCESS, Some(range)).iter() {
+                let result = self.hooks[*index]
                     .hook_invalid_memory_access(&mut self.state, &address, size, kind)
                     .map_err(Error::Hook)?;
                 if result.state_changed || result.action.is_value() {
@@ -298,8 +707,9 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
 
             debug_assert_eq!(size, buf.len());
 
-            for hook in self.hooks.iter_mut() {
-                let res = hook
+            let range = address..address + size;
+            for index in self.hook_index.hooks(HookEvents::INVALID_MEMORY_ACCESS, Some(range)).iter() {
+                let res = self.hooks[*index]
                     .hook_invalid_memory_access(
                         &mut self.state,
                         &address,
@@ -330,10 +740,7 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
             res.map_err(Error::State)?
         }
 
-        for hook in self.hooks.iter_mut() {
-            hook.hook_operand_write(&mut self.state, operand, &buf)
-                .map_err(Error::Hook)?;
-        }
+        self.operand_hooks(operand, Some(buf))?;
 
         Ok(())
     }
@@ -525,16 +932,339 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
         }
     }
 
-    fn skip_return(&mut self) -> Result<AddressValue, Error> {
-        // NOTE: for x86, etc. we need to clean-up the stack
-        // arguments; currently, this is the responsibility of
-        // hooks that issue a `HookCallAction::Skip`.
+    fn push_frame(&mut self, callee: Address) -> Result<(), Error> {
+        if self.state.call_stack().is_none() {
+            return Ok(())
+        }
+
+        let call_site = self.state.program_counter_value()?;
+        let stack_pointer = self.state.stack_pointer_value()?;
+        let return_address = self.with_return_location(|operand| {
+            self.state.get_address(operand).map_err(Error::State)
+        })?;
+
+        if let Some(call_stack) = self.state.call_stack_mut() {
+            call_stack.push(Frame::new(call_site, callee, return_address, stack_pointer));
+        }
+
+        Ok(())
+    }
+
+    // Dispatch an indirect branch or return to `destination`; returns the
+    // destination, possibly redirected, or the outcome to halt with
+    fn branch_hooks(
+        &mut self,
+        event: HookEvents,
+        destination: AddressValue,
+    ) -> Result<Result<AddressValue, R>, Error> {
+        let mut destination = destination;
+
+        let range = self.instruction_range(event)?;
+        for index in self.hook_index.hooks(event, range).iter() {
+            let address = Address::from(&destination);
+            let hook = &mut self.hooks[*index];
+
+            let action = if event == HookEvents::IBRANCH {
+                hook.hook_ibranch(&mut self.state, &address)
+            } else {
+                hook.hook_return(&mut self.state, &address)
+            };
+
+            match action.map_err(Error::Hook)?.action {
+                HookBranchAction::Pass => (),
+                HookBranchAction::Redirect(address) => {
+                    destination = AddressValue::new(self.state.memory_space(), u64::from(address));
+                }
+                HookBranchAction::Halt(r) => return Ok(Err(r)),
+            }
+        }
+
+        Ok(Ok(destination))
+    }
+
+    // Cache the instructions lifted from now on by the value of `variable`
+    fn track_context_variable(&mut self, variable: &str) {
+        if !self.context_variables.iter().any(|tracked| tracked == variable) {
+            self.context_variables.push(variable.to_owned());
+        }
+    }
+
+    // Apply the context variables set through the state, then set those
+    // switched by registers, and record the values of all tracked ones
+    fn sync_context(&mut self) -> Result<(), Error> {
+        for (variable, value) in self.state.take_context_writes() {
+            if !self.translator_context.set_variable_default(variable.as_str(), value) {
+                return Err(Error::UnknownContextVariable(variable))
+            }
+            self.track_context_variable(&variable);
+        }
+
+        for switch in self.context_switches.iter() {
+            if let ContextSwitch::Register { variable, register } = switch {
+                let register = self.state.registers()
+                    .register_by_name(register)
+                    .ok_or_else(|| Error::UnknownRegister(register.clone()))?;
+
+                self.context_bytes.resize(register.size(), 0);
+
+                self.state.registers()
+                    .get_register_values(&register, &mut self.context_bytes)
+                    .map_err(|e| Error::State(pcode::Error::Register(e)))?;
+
+                let value = context::register_value::<O>(&self.context_bytes);
+                self.translator_context.set_variable_default(variable.as_str(), value);
+            }
+        }
+
+        self.context_values.clear();
+        for variable in self.context_variables.iter() {
+            self.context_values.push(self.translator_context.get_variable(variable).unwrap_or(0));
+        }
+
+        Ok(())
+    }
+
+    // Split bit 0 from a branch target if any target bit context switch is
+    // tracked; returns the address to branch to and the bit
+    fn split_target(&self, destination: AddressValue) -> (AddressValue, Option<u32>) {
+        let switched = self.context_switches
+            .iter()
+            .any(|switch| matches!(switch, ContextSwitch::TargetBit { .. }));
+
+        if switched {
+            let (target, bit) = context::split_target(u64::from(Address::from(&destination)));
+            (AddressValue::new(self.state.memory_space(), target), Some(bit))
+        } else {
+            (destination, None)
+        }
+    }
+
+    // Set the target bit context switches to `bit`
+    fn set_target_bit(&mut self, bit: u32) {
+        for switch in self.context_switches.iter() {
+            if let ContextSwitch::TargetBit { variable } = switch {
+                self.translator_context.set_variable_default(variable.as_str(), bit);
+            }
+        }
+    }
+
+    // Apply target bit context switches to the destination of an indirect
+    // branch or return
+    fn switch_target(&mut self, destination: AddressValue) -> AddressValue {
+        let (destination, bit) = self.split_target(destination);
+        if let Some(bit) = bit {
+            self.set_target_bit(bit);
+        }
+        destination
+    }
+
+    // Lift the instruction at `address` under the current decoding context,
+    // using the cache; returns what to execute, or the outcome to halt with
+    // if a lift error hook halts
+    fn lift_cached(&mut self, address_value: AddressValue) -> Result<Result<StepState, R>, Error> {
+        let address = Address::from(&address_value);
+
+        self.sync_context()?;
+
+        // begin read lock region
+        let rlock = self.translator_cache.read();
+
+        let cached = rlock.get(&address)
+            .and_then(|lifted| lifted.get(self.context_values.as_slice()))
+            .cloned();
+
+        drop(rlock);
+        // end read lock region
+
+        if let Some(step_state) = cached {
+            Ok(Ok(step_state))
+        } else {
+            // NOTE: possible race here, if another thread populates
+            // the same address. We don't really care, I suppose.
+
+            let view = self
+                .state
+                .view_values_from(address)
+                .map_err(Error::State)?;
+
+            match self.translator.lift_pcode(&mut self.translator_context, address_value.clone(), view) {
+                Ok(pcode) => {
+                    let delay_slot = self.delay_slot_offset(&pcode, view);
+                    let step_state = match delay_slot {
+                        Some(offset) => StepState::from(pcode).with_delay_slot(offset),
+                        None => StepState::from(pcode),
+                    };
+
+                    self.translator_cache
+                        .write()
+                        .entry(address)
+                        .or_default()
+                        .insert(self.context_values.clone(), step_state.clone());
+
+                    Ok(Ok(step_state))
+                }
+                // replacements are not cached, as hooks may apply their
+                // own semantics each time
+                Err(e) => self.lift_error_hooks(address_value, e),
+            }
+        }
+    }
+
+    // The offset of the delay slot of `pcode`, lifted from `bytes`, if any:
+    // the lifted length covers the instruction and its delay slot, whose
+    // length is only given as a minimum, so this is the first offset that
+    // lifts as an instruction ending with them
+    fn delay_slot_offset(&self, pcode: &PCode, bytes: &[u8]) -> Option<usize> {
+        if pcode.delay_slots() == 0 {
+            return None
+        }
+
+        let length = pcode.length();
+        (1..length).find(|offset| {
+            let mut context = self.translator_context.clone();
+            let address = pcode.address() + *offset;
+            matches!(
+                self.translator.lift_pcode(&mut context, address, &bytes[*offset..]),
+                Ok(slot) if slot.delay_slots() == 0 && offset + slot.length() == length
+            )
+        })
+    }
+
+    // Report the instruction at `address` to the architectural step hooks
+    // and set the program counter to it; returns `step_state` to execute,
+    // unless a hook branches elsewhere or halts
+    fn enter_instruction(&mut self, address: Address, step_state: StepState) -> Result<OrOutcome<StepState, R>, Error> {
+        let mut action = HookStepAction::Pass;
+        for index in self.hook_index.hooks_at(HookEvents::ARCHITECTURAL_STEP, address).iter() {
+            match self.hooks[*index]
+                .hook_architectural_step(&mut self.state, &address, &step_state)
+                .map_err(Error::Hook)?
+                .action
+            {
+                HookStepAction::Pass => (),
+                branch @ HookStepAction::Branch(_) => {
+                    action = branch;
+                }
+                halt @ HookStepAction::Halt(_) => {
+                    action = halt;
+                    break
+                }
+            }
+        }
+
+        let program_counter = self.state.registers().program_counter().clone();
+        self.state
+            .set_address(&program_counter, address)
+            .map_err(Error::State)?;
+
+        Ok(match action {
+            HookStepAction::Pass => step_state.into(),
+            HookStepAction::Branch(location) => OrOutcome::Branch(location),
+            HookStepAction::Halt(r) => OrOutcome::Halt(r),
+        })
+    }
+
+    // Lift the override of the instruction at `address_value`, if any
+    fn lift_override(&mut self, address_value: &AddressValue) -> Result<Option<OrOutcome<StepState, R>>, Error> {
+        let address = Address::from(address_value);
+
+        let length = match self.overrides.get(&address) {
+            Some(InstructionOverride::PCode(step_state)) => {
+                let step_state = step_state.clone();
+                return Ok(Some(self.enter_instruction(address, step_state)?))
+            }
+            Some(InstructionOverride::Semantic { length, .. }) => *length,
+            None => return Ok(None),
+        };
+
+        // the semantic is applied once the instruction is entered, as a
+        // no-op of its length; it is applied in place, so that it keeps
+        // any state it captures between executions
+        let step_state = match self.enter_instruction(address, StepState::from(PCode::nop(address_value.clone(), length)))? {
+            OrOutcome::Continue(step_state) => step_state,
+            outcome => return Ok(Some(outcome)),
+        };
+
+        let action = match self.overrides.get_mut(&address) {
+            Some(InstructionOverride::Semantic { semantic, .. }) => {
+                semantic.apply(&mut self.state).map_err(Error::Hook)?
+            }
+            _ => HookStepAction::Pass,
+        };
+
+        Ok(Some(match action {
+            HookStepAction::Pass => step_state.into(),
+            HookStepAction::Branch(location) => {
+                let mut pcode = PCode::nop(address_value.clone(), length);
+                pcode.operations.push(PCodeOp::Branch {
+                    destination: Operand::Address {
+                        value: Address::from(&*location.address()),
+                        size: self.state.memory_space_ref().address_size(),
+                    },
+                });
+                StepState::from(pcode).into()
+            }
+            HookStepAction::Halt(r) => OrOutcome::Halt(r),
+        }))
+    }
+
+    // Dispatch a failure to lift the instruction at `address`; returns what
+    // to execute in its place, or the outcome to halt with
+    fn lift_error_hooks(
+        &mut self,
+        address: AddressValue,
+        error: ir::error::Error,
+    ) -> Result<Result<StepState, R>, Error> {
+        let target = Address::from(&address);
+
+        for index in self.hook_index.hooks_at(HookEvents::LIFT_ERROR, target).iter() {
+            match self.hooks[*index]
+                .hook_lift_error(&mut self.state, &target, &error)
+                .map_err(Error::Hook)?
+                .action
+            {
+                HookLiftAction::Pass => (),
+                HookLiftAction::Replace(pcode) => return Ok(Ok(StepState::from(pcode))),
+                // which would step the same instruction forever
+                HookLiftAction::Skip(0) => return Err(Error::ZeroLengthSkip(target)),
+                HookLiftAction::Skip(length) => {
+                    return Ok(Ok(StepState::from(PCode::nop(address, length))))
+                }
+                HookLiftAction::Halt(r) => return Ok(Err(r)),
+            }
+        }
+
+        Err(Error::Lift(target, error))
+    }
+
+    // The outcome to halt with, if returning to `address` is a mismatch
+    fn pop_frame(&mut self, address: Address) -> Option<R> {
+        let (expected, actual) = self.state.call_stack_mut()?.pop(address).mismatch()?;
+        log::trace!("return to {} does not match expected return to {}", actual, expected);
+        self.return_mismatch.map(|outcome| outcome(expected, actual))
+    }
+
+    fn skip_return(&mut self, callee: Address, skip: HookCallSkip) -> Result<AddressValue, Error> {
+        if let Some(value) = skip.return_value() {
+            self.state.set_return_value(value).map_err(Error::State)?;
+        }
+
         let address = self.with_return_location(|operand| {
             self.state.get_address(operand).map_err(Error::State)
         })?;
 
-        // Next we pop the return address (if needed)
-        let extra_pop = self.state.convention().default_prototype().extra_pop();
+        // Next we pop the return address (if needed) and any stack
+        // arguments for callee-cleanup conventions
+        let parameters = self.state.registers().parameters();
+        let extra_pop = match skip.stack_adjustment() {
+            Some(bytes) => parameters.stack_shift() + bytes,
+            None => {
+                let arguments = skip.arguments()
+                    .or_else(|| self.call_arguments.get(&callee).copied())
+                    .unwrap_or(0);
+                parameters.return_pop(arguments)
+            },
+        };
 
         if extra_pop > 0 {
             let stack_pointer = self.state.registers().stack_pointer().clone();
@@ -561,10 +1291,7 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
         let mut buf = [0u8; MAX_POINTER_SIZE];
         let psize = pointer.size();
 
-        for hook in self.hooks.iter_mut() {
-            hook.hook_operand_read(&mut self.state, pointer)
-                .map_err(Error::Hook)?;
-        }
+        self.operand_hooks(pointer, None)?;
 
         let address = if psize == POINTER_64_SIZE {
             self.read_operand_with(pointer, &mut buf[..psize], source, |buf| {
@@ -637,18 +1364,32 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize>
 impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpreter
     for ConcreteContext<O, R, { OPERAND_SIZE }>
 {
-    type State = PCodeState<u8, O>;
+    type State = ConcreteState<O>;
     type Error = Error;
     type Outcome = R;
 
     fn fork(&self) -> Self {
+        let mut hooks = self.hooks.clone();
+        for hook in hooks.iter_mut() {
+            hook.hook_fork();
+        }
+
         Self {
             database: self.database.clone(),
             translator: self.translator.clone(),
-            translator_context: self.translator.context_database(),
+            translator_context: self.translator_context.clone(),
             translator_cache: self.translator_cache.clone(),
-            hook_names: self.hook_names.clone(),
-            hooks: self.hooks.clone(),
+            context_switches: self.context_switches.clone(),
+            context_variables: self.context_variables.clone(),
+            context_values: self.context_values.clone(),
+            context_bytes: self.context_bytes.clone(),
+            hook_index: self.hook_index.clone(),
+            halt: None,
+            symbol_offset: self.symbol_offset,
+            return_mismatch: self.return_mismatch,
+            overrides: self.overrides.clone(),
+            call_arguments: self.call_arguments.clone(),
+            hooks,
             intrinsics: self.intrinsics.clone(),
             state: self.state.fork(),
             marker: self.marker,
@@ -656,8 +1397,33 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpre
     }
 
     fn restore(&mut self, other: &Self) {
-        self.hooks = other.hooks.clone();
-        self.hook_names = other.hook_names.clone();
+        // hooks are matched by name: those registered in both contexts
+        // are restored individually, and the rest are replaced by the
+        // hooks of `other`
+        let mut current = std::mem::take(&mut self.hooks)
+            .into_iter()
+            .zip(self.hook_index.entries().iter())
+            .map(|(hook, entry)| (entry.name.clone(), hook))
+            .collect::<Map<_, _>>();
+
+        self.hooks = other.hooks.iter()
+            .zip(other.hook_names())
+            .map(|(other, name)| match current.remove(name) {
+                Some(mut hook) if hook.hook_restore((**other).as_any()) => hook,
+                _ => other.clone(),
+            })
+            .collect();
+
+        self.hook_index = other.hook_index.clone();
+        self.halt = None;
+        self.translator_context = other.translator_context.clone();
+        self.context_switches = other.context_switches.clone();
+        self.context_variables = other.context_variables.clone();
+        self.symbol_offset = other.symbol_offset;
+        self.return_mismatch = other.return_mismatch;
+        self.overrides = other.overrides.clone();
+        self.call_arguments = other.call_arguments.clone();
+        self.intrinsics = other.intrinsics.clone();
         self.state.restore(&other.state);
     }
 
@@ -763,8 +1529,9 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpre
         let mut flip = false;
 
         // Invoke hook
-        for hook in self.hooks.iter_mut() {
-            match hook
+        let range = self.instruction_range(HookEvents::CBRANCH)?;
+        for index in self.hook_index.hooks(HookEvents::CBRANCH, range).iter() {
+            match self.hooks[*index]
                 .hook_cbranch(&mut self.state, destination, condition)
                 .map_err(Error::Hook)?
                 .action
@@ -807,31 +1574,49 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpre
             self.state.memory_space(),
             self.get_address_value(destination, ViolationSource::ReadVia)?,
         );
-        Ok(Outcome::Branch(Branch::Global(address)))
+
+        match self.branch_hooks(HookEvents::IBRANCH, address)? {
+            Ok(address) => Ok(Outcome::Branch(Branch::Global(self.switch_target(address)))),
+            Err(r) => Ok(Outcome::Halt(r)),
+        }
     }
 
     fn call(&mut self, destination: &Operand) -> Result<Outcome<R>, Error> {
         match destination {
             Operand::Address { value, .. } => {
-                let mut skip = false;
+                let mut skip = None;
                 let address_value = value.into_address_value(self.state.memory_space_ref());
-                for hook in self.hooks.iter_mut() {
-                    match hook
-                        .hook_call(&mut self.state, &(&address_value).into())
+
+                // direct calls only switch context to odd addresses
+                let (address_value, bit) = match self.split_target(address_value.clone()) {
+                    (target, Some(1)) => (target, Some(1)),
+                    _ => (address_value, None),
+                };
+                let address = Address::from(&address_value);
+                for index in self.hook_index.hooks_at(HookEvents::CALL, address).iter() {
+                    match self.hooks[*index]
+                        .hook_call(&mut self.state, &address)
                         .map_err(Error::Hook)?
                         .action
                     {
                         HookCallAction::Pass => (),
                         HookCallAction::Skip => {
-                            skip = true;
+                            skip = Some(HookCallSkip::default());
+                        }
+                        HookCallAction::SkipWith(options) => {
+                            skip = Some(options);
                         }
                         HookCallAction::Halt(r) => return Ok(Outcome::Halt(r)),
                     }
                 }
 
-                if skip {
-                    Ok(Outcome::Branch(Branch::Global(self.skip_return()?)))
+                if let Some(skip) = skip {
+                    Ok(Outcome::Branch(Branch::Global(self.skip_return(address, skip)?)))
                 } else {
+                    if let Some(bit) = bit {
+                        self.set_target_bit(bit);
+                    }
+                    self.push_frame(address)?;
                     Ok(Outcome::Branch(Branch::Global(address_value)))
                 }
             }
@@ -852,26 +1637,34 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpre
             self.state.memory_space(),
             self.get_address_value(destination, ViolationSource::ReadVia)?,
         );
+        let (address_value, bit) = self.split_target(address_value);
         let address = Address::from(&address_value);
 
-        let mut skip = false;
-        for hook in self.hooks.iter_mut() {
-            match hook
+        let mut skip = None;
+        for index in self.hook_index.hooks_at(HookEvents::CALL, address).iter() {
+            match self.hooks[*index]
                 .hook_call(&mut self.state, &address)
                 .map_err(Error::Hook)?
                 .action
             {
                 HookCallAction::Pass => (),
                 HookCallAction::Skip => {
-                    skip = true;
+                    skip = Some(HookCallSkip::default());
+                }
+                HookCallAction::SkipWith(options) => {
+                    skip = Some(options);
                 }
                 HookCallAction::Halt(r) => return Ok(Outcome::Halt(r)),
             }
         }
 
-        if skip {
-            Ok(Outcome::Branch(Branch::Global(self.skip_return()?)))
+        if let Some(skip) = skip {
+            Ok(Outcome::Branch(Branch::Global(self.skip_return(address, skip)?)))
         } else {
+            if let Some(bit) = bit {
+                self.set_target_bit(bit);
+            }
+            self.push_frame(address)?;
             Ok(Outcome::Branch(Branch::Global(address_value)))
         }
     }
@@ -881,7 +1674,19 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpre
             self.state.memory_space(),
             self.get_address_value(destination, ViolationSource::ReadVia)?,
         );
-        Ok(Outcome::Branch(Branch::Global(address)))
+
+        let target = Address::from(&address);
+
+        let address = match self.branch_hooks(HookEvents::RETURN, address)? {
+            Ok(address) => address,
+            Err(r) => return Ok(Outcome::Halt(r)),
+        };
+
+        if let Some(outcome) = self.pop_frame(target) {
+            return Ok(Outcome::Halt(outcome))
+        }
+
+        Ok(Outcome::Branch(Branch::Global(self.switch_target(address))))
     }
 
     fn int_eq(
@@ -1522,56 +2327,50 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpre
         let address_value = address.into_address_value(self.state.memory_space_ref());
         let address = Address::from(&address_value);
 
-        // begin read lock region
-        let rlock = self.translator_cache.read();
-
-        let cached = rlock.get(&address).map(|step_state| step_state.clone());
-
-        drop(rlock);
-        // end read lock region
-
-        let step_state = if let Some(step_state) = cached {
-            step_state.clone()
-        } else {
-            // NOTE: possible race here, if another thread populates
-            // the same address. We don't really care, I suppose.
-
-            let view = self
-                .state
-                .view_values_from(address)
-                .map_err(Error::State)?;
-            let step_state = StepState::from(
-                self.translator
-                    .lift_pcode(&mut self.translator_context, address_value, view)
-                    .map_err(|e| Error::Lift(address, e))?,
-            );
-
-            self.translator_cache
-                .write()
-                .insert(address, step_state.clone());
+        if let Some(outcome) = self.lift_override(&address_value)? {
+            return Ok(outcome)
+        }
 
-            step_state
+        let step_state = match self.lift_cached(address_value)? {
+            Ok(step_state) => step_state,
+            Err(r) => return Ok(OrOutcome::Halt(r)),
         };
 
+        self.enter_instruction(address, step_state)
+    }
+
+    fn operation(&mut self, location: &Location, step: &PCodeOp) -> Result<OrOutcome<(), Self::Outcome>, Self::Error> {
         // TODO: handle outcomes
-        for hook in self.hooks.iter_mut() {
-            hook.hook_architectural_step(&mut self.state, &address, &step_state)
+        let range = self.instruction_range(HookEvents::OPERATION_STEP)?;
+        for index in self.hook_index.hooks(HookEvents::OPERATION_STEP, range).iter() {
+            self.hooks[*index]
+                .hook_operation_step(&mut self.state, location, step)
                 .map_err(Error::Hook)?;
         }
 
-        let program_counter = self.state.registers().program_counter().clone();
-        self.state
-            .set_address(&program_counter, address)
-            .map_err(Error::State)?;
+        Ok(().into())
+    }
 
-        Ok(step_state.into())
+    fn halted(&mut self) -> Option<R> {
+        self.halt.take()
     }
 
-    fn operation(&mut self, location: &Location, step: &PCodeOp) -> Result<OrOutcome<(), Self::Outcome>, Self::Error> {
-        // TODO: handle outcomes
-        for hook in self.hooks.iter_mut() {
-            hook.hook_operation_step(&mut self.state, location, step)
-                .map_err(Error::Hook)?;
+    fn delay_slot(&mut self, address: &AddressValue, step: &StepState) -> Result<OrOutcome<(), R>, Error> {
+        // the slot is reported with the branch's step state, as its p-code
+        // is part of the branch's; for the same reason, overrides of it do
+        // not apply. The program counter remains at the branch.
+        let address = Address::from(address);
+
+        for index in self.hook_index.hooks_at(HookEvents::ARCHITECTURAL_STEP, address).iter() {
+            match self.hooks[*index]
+                .hook_architectural_step(&mut self.state, &address, step)
+                .map_err(Error::Hook)?
+                .action
+            {
+                HookStepAction::Pass => (),
+                HookStepAction::Branch(location) => return Ok(OrOutcome::Branch(location)),
+                HookStepAction::Halt(r) => return Ok(OrOutcome::Halt(r)),
+            }
         }
 
         Ok(().into())
@@ -1581,3 +2380,407 @@ impl<O: Order, R: Clone + Default + 'static, const OPERAND_SIZE: usize> Interpre
         self.state.memory_space()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fugue::bytes::{BE, LE};
+
+    use fuguex_hooks::types::{Error as HookError, HookOutcome};
+    use fuguex_machine::{Machine, StepOutcome};
+
+    use crate::hooks::HookConcrete;
+    use crate::testing;
+
+    const CODE: u64 = 0x1000;
+    const STACK: u64 = 0x8000;
+    const CALLEE: u64 = 0x2000;
+
+    fn stack_pointer<O: Order>(context: &testing::Context<O>) -> u64 {
+        u64::from(context.state().stack_pointer_value().unwrap())
+    }
+
+    // Push two arguments and call `CALLEE`, which is skipped; returns the
+    // change to the stack pointer over the sequence
+    fn skip_call_x86(convention: &str, arguments: Option<usize>) -> i64 {
+        // push 2; push 1; call CALLEE
+        let mut code = vec![0x6a, 0x02, 0x6a, 0x01];
+        code.extend(testing::call_rel32(CODE + 4, CALLEE));
+        let end = CODE + code.len() as u64;
+
+        let mut context = testing::context_with::<LE>("x86:LE:32:default", convention, CODE, &code, STACK);
+        if let Some(arguments) = arguments {
+            context.set_call_arguments(CALLEE, arguments);
+        }
+        context.on_call("skip", CALLEE, |_: &mut ConcreteState<LE>, _: &Address| {
+            HookCallAction::SkipWith(HookCallSkip::default().with_return_value(7))
+        }).unwrap();
+
+        let before = stack_pointer(&context);
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+
+        let context = machine.interpreter();
+        assert_eq!(context.state().return_value().unwrap(), 7);
+        stack_pointer(context) as i64 - before as i64
+    }
+
+    #[test]
+    fn skip_call_stdcall() {
+        // the callee pops the return address and both arguments
+        assert_eq!(skip_call_x86("windows", Some(2)), 0);
+        // unless the arguments are unknown
+        assert_eq!(skip_call_x86("windows", None), -8);
+    }
+
+    #[test]
+    fn skip_call_cdecl() {
+        // the caller pops the arguments
+        assert_eq!(skip_call_x86("gcc", Some(2)), -8);
+    }
+
+    #[test]
+    fn skip_call_win64() {
+        // Win64's default prototype is `__fastcall`, but the caller pops
+        // the arguments (and the shadow space) regardless of their number
+        let code = testing::call_rel32(CODE, CALLEE);
+        let end = CODE + code.len() as u64;
+
+        let mut context = testing::context_with::<LE>("x86:LE:64:default", "windows", CODE, &code, STACK);
+        context.set_call_arguments(CALLEE, 6);
+        context.on_call("skip", CALLEE, |_: &mut ConcreteState<LE>, _: &Address| HookCallAction::Skip).unwrap();
+
+        let parameters = context.state().registers().parameters();
+        assert!(!parameters.callee_cleanup());
+        assert_eq!(parameters.stack_shift(), 8);
+
+        let before = stack_pointer(&context);
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+        assert_eq!(stack_pointer(machine.interpreter()), before);
+    }
+
+    #[test]
+    fn skip_call_stack_adjustment() {
+        let code = testing::call_rel32(CODE, CALLEE);
+        let end = CODE + code.len() as u64;
+
+        let mut context = testing::context_with::<LE>("x86:LE:64:default", "gcc", CODE, &code, STACK);
+        context.on_call("skip", CALLEE, |_: &mut ConcreteState<LE>, _: &Address| {
+            HookCallAction::SkipWith(HookCallSkip::default().with_stack_adjustment(16))
+        }).unwrap();
+
+        let before = stack_pointer(&context);
+        let mut machine = testing::machine(context);
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+        assert_eq!(stack_pointer(machine.interpreter()), before + 16);
+    }
+
+    // Hooks that record their names when they are dispatched a step
+    fn logged_hooks(names: &[&'static str]) -> (testing::Context<LE>, Arc<std::sync::Mutex<Vec<&'static str>>>) {
+        let mut context = testing::context::<LE>("x86:LE:64:default", CODE, &[0x90], STACK);
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        for name in names.iter().copied() {
+            let log = log.clone();
+            context.on_instruction(name, CODE..CODE + 1, move |_: &mut ConcreteState<LE>, _: &Address| {
+                log.lock().unwrap().push(name);
+            }).unwrap();
+        }
+
+        (context, log)
+    }
+
+    fn dispatched(machine: &mut Machine<testing::Context<LE>>, log: &std::sync::Mutex<Vec<&'static str>>) -> Vec<&'static str> {
+        log.lock().unwrap().clear();
+        testing::run(machine, CODE, CODE + 1);
+        log.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn hook_exists() {
+        let (mut context, _) = logged_hooks(&["a"]);
+
+        let result = context.on_instruction("a", CODE..CODE + 1, |_: &mut ConcreteState<LE>, _: &Address| ());
+        assert!(matches!(result, Err(Error::HookExists(name)) if name == "a"));
+        assert_eq!(context.hook_names().collect::<Vec<_>>(), ["a"]);
+    }
+
+    #[test]
+    fn hook_priority() {
+        let (context, log) = logged_hooks(&["a", "b", "c"]);
+        let mut machine = testing::machine(context);
+
+        assert_eq!(dispatched(&mut machine, &log), ["a", "b", "c"]);
+
+        machine.interpreter_mut().set_hook_priority("c", 1).unwrap();
+        machine.interpreter_mut().set_hook_priority("a", -1).unwrap();
+        assert_eq!(dispatched(&mut machine, &log), ["c", "b", "a"]);
+
+        // hooks of equal priority keep the order in which they were added
+        machine.interpreter_mut().set_hook_priority("c", 0).unwrap();
+        machine.interpreter_mut().set_hook_priority("a", 0).unwrap();
+        assert_eq!(dispatched(&mut machine, &log), ["a", "b", "c"]);
+        assert_eq!(machine.interpreter().hook_names().collect::<Vec<_>>(), ["a", "b", "c"]);
+
+        assert!(matches!(machine.interpreter_mut().set_hook_priority("d", 0), Err(Error::UnknownHook(_))));
+    }
+
+    #[test]
+    fn hook_enabled() {
+        let (context, log) = logged_hooks(&["a", "b"]);
+        let mut machine = testing::machine(context);
+
+        machine.interpreter_mut().set_hook_enabled("a", false).unwrap();
+        assert_eq!(machine.interpreter().is_hook_enabled("a"), Some(false));
+        assert_eq!(dispatched(&mut machine, &log), ["b"]);
+
+        // re-enabled hooks keep their position
+        machine.interpreter_mut().set_hook_enabled("a", true).unwrap();
+        assert_eq!(dispatched(&mut machine, &log), ["a", "b"]);
+
+        assert_eq!(machine.interpreter().is_hook_enabled("c"), None);
+        assert!(matches!(machine.interpreter_mut().set_hook_enabled("c", true), Err(Error::UnknownHook(_))));
+    }
+
+    #[test]
+    fn hook_remove() {
+        let (context, log) = logged_hooks(&["a", "b", "c"]);
+        let mut machine = testing::machine(context);
+
+        machine.interpreter_mut().remove_hook("b").unwrap();
+        assert_eq!(dispatched(&mut machine, &log), ["a", "c"]);
+        assert!(matches!(machine.interpreter_mut().remove_hook("b"), Err(Error::UnknownHook(_))));
+
+        // the remaining hooks are still found by name
+        machine.interpreter_mut().set_hook_enabled("c", false).unwrap();
+        assert_eq!(dispatched(&mut machine, &log), ["a"]);
+
+        // and the name may be reused
+        let log_b = log.clone();
+        machine.interpreter_mut().on_instruction("b", CODE..CODE + 1, move |_: &mut ConcreteState<LE>, _: &Address| {
+            log_b.lock().unwrap().push("b");
+        }).unwrap();
+        assert_eq!(dispatched(&mut machine, &log), ["a", "b"]);
+    }
+
+    // Handles the instructions that fail to lift with `action`, counting
+    // them
+    #[derive(Clone)]
+    struct LiftErrors {
+        action: fn(&ConcreteState<LE>, &Address) -> HookLiftAction<()>,
+        errors: usize,
+    }
+
+    impl HookConcrete for LiftErrors {
+        type State = ConcreteState<LE>;
+        type Error = pcode::Error;
+        type Outcome = ();
+
+        fn hook_lift_error(
+            &mut self,
+            state: &mut Self::State,
+            address: &Address,
+            _error: &ir::error::Error,
+        ) -> Result<HookOutcome<HookLiftAction<Self::Outcome>>, HookError<Self::Error>> {
+            self.errors += 1;
+            Ok((self.action)(state, address).into())
+        }
+    }
+
+    impl ClonableHookConcrete for LiftErrors {}
+
+    // An instruction invalid in 64-bit mode (`push es`), then `mov eax, 1`
+    const INVALID: [u8; 6] = [0x06, 0xb8, 0x01, 0x00, 0x00, 0x00];
+
+    fn lift_errors(action: fn(&ConcreteState<LE>, &Address) -> HookLiftAction<()>) -> Machine<testing::Context<LE>> {
+        let mut context = testing::context::<LE>("x86:LE:64:default", CODE, &INVALID, STACK);
+        context.add_hook_with_interest(
+            "lift",
+            LiftErrors { action, errors: 0 },
+            HookInterest::new(HookEvents::LIFT_ERROR),
+        ).unwrap();
+        testing::machine(context)
+    }
+
+    fn lift_error_count(machine: &Machine<testing::Context<LE>>) -> usize {
+        machine.interpreter().find_hook::<_, LiftErrors>("lift").unwrap().errors
+    }
+
+    fn step_at<O: Order>(machine: &mut Machine<testing::Context<O>>, address: u64) -> Result<StepOutcome<()>, Error> {
+        let space = machine.interpreter().state().memory_space();
+        machine.step(Location::from(AddressValue::new(space, address)))
+    }
+
+    fn eax(machine: &Machine<testing::Context<LE>>) -> u64 {
+        machine.interpreter().state().return_value().unwrap()
+    }
+
+    #[test]
+    fn lift_error_replace() {
+        // branch past `mov eax, 1`
+        let mut machine = lift_errors(|state, address| {
+            let mut pcode = PCode::nop(AddressValue::new(state.memory_space(), u64::from(*address)), 1);
+            pcode.operations.push(PCodeOp::Branch {
+                destination: Operand::Address {
+                    value: Address::from(CODE + INVALID.len() as u64),
+                    size: 8,
+                },
+            });
+            HookLiftAction::Replace(pcode)
+        });
+
+        let end = CODE + INVALID.len() as u64;
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+        assert_eq!(eax(&machine), 0);
+
+        // replacements are not cached
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+        assert_eq!(lift_error_count(&machine), 2);
+    }
+
+    #[test]
+    fn lift_error_skip() {
+        let mut machine = lift_errors(|_, _| HookLiftAction::Skip(1));
+
+        let end = CODE + INVALID.len() as u64;
+        assert!(matches!(testing::run(&mut machine, CODE, end), StepOutcome::Reached));
+        assert_eq!(eax(&machine), 1);
+        assert_eq!(lift_error_count(&machine), 1);
+    }
+
+    #[test]
+    fn lift_error_skip_zero_length() {
+        let mut machine = lift_errors(|_, _| HookLiftAction::Skip(0));
+
+        let result = step_at(&mut machine, CODE);
+        assert!(matches!(result, Err(Error::ZeroLengthSkip(address)) if u64::from(address) == CODE));
+    }
+
+    #[test]
+    fn lift_error_halt() {
+        let mut machine = lift_errors(|_, _| HookLiftAction::Halt(()));
+
+        assert!(matches!(step_at(&mut machine, CODE), Ok(StepOutcome::Halt(()))));
+        assert_eq!(lift_error_count(&machine), 1);
+    }
+
+    #[test]
+    fn lift_error_pass() {
+        let mut machine = lift_errors(|_, _| HookLiftAction::Pass);
+
+        let result = step_at(&mut machine, CODE);
+        assert!(matches!(result, Err(Error::Lift(address, _)) if u64::from(address) == CODE));
+        assert_eq!(lift_error_count(&machine), 1);
+    }
+
+    // The address after the instruction at `address`, if it falls through
+    fn fallthrough(machine: &mut Machine<testing::Context<LE>>, address: u64) -> u64 {
+        match step_at(machine, address).unwrap() {
+            StepOutcome::Branch(next) => u64::from(Address::from(&next)),
+            _ => panic!("instruction at {:#x} does not fall through", address),
+        }
+    }
+
+    #[test]
+    fn context_variable_relifts() {
+        // `mov r0, #1` in ARM mode, and `movs r1, r0` then another
+        // instruction in Thumb mode
+        let code = [0x01, 0x00, 0xa0, 0xe3];
+        let context = testing::context::<LE>("ARM:LE:32:v7", CODE, &code, STACK);
+        let mut machine = testing::machine(context);
+
+        assert_eq!(fallthrough(&mut machine, CODE), CODE + 4);
+
+        // no context switch is registered for TMode, yet the instruction
+        // is not taken from the cache
+        machine.interpreter_mut().set_context_variable("TMode", 1).unwrap();
+        assert_eq!(fallthrough(&mut machine, CODE), CODE + 2);
+
+        // likewise when set through the state, e.g., by a hook
+        machine.interpreter_mut().state_mut().set_context_variable("TMode", 0);
+        assert_eq!(fallthrough(&mut machine, CODE), CODE + 4);
+        assert_eq!(machine.interpreter().context_variable("TMode"), Some(0));
+    }
+
+    // `addiu v0, v0, 1`
+    const ADDIU_V0_1: u32 = 0x2442_0001;
+
+    // Step the MIPS instruction at `CODE`, logging the addresses of the
+    // instructions reported to hooks
+    fn step_mips(code: &[u32]) -> (StepOutcome<()>, Vec<u64>, Machine<testing::Context<BE>>) {
+        let code = code.iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<_>>();
+        let mut context = testing::context::<BE>("MIPS:BE:32:default", CODE, &code, STACK);
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = log.clone();
+        context.on_instruction("log", CODE..CODE + 0x100, move |_: &mut ConcreteState<BE>, address: &Address| {
+            reported.lock().unwrap().push(u64::from(*address));
+        }).unwrap();
+
+        let mut machine = testing::machine(context);
+        let outcome = step_at(&mut machine, CODE).unwrap();
+        let reported = log.lock().unwrap().clone();
+
+        (outcome, reported, machine)
+    }
+
+    fn branched_to(outcome: &StepOutcome<()>) -> u64 {
+        match outcome {
+            StepOutcome::Branch(next) => u64::from(Address::from(next)),
+            _ => panic!("step did not branch"),
+        }
+    }
+
+    fn v0(machine: &Machine<testing::Context<BE>>) -> u64 {
+        machine.interpreter().state().return_value().unwrap()
+    }
+
+    #[test]
+    fn mips_jal_delay_slot() {
+        // jal CALLEE; addiu v0, v0, 1
+        let jal = 0x0c00_0000 | (CALLEE >> 2) as u32;
+        let (outcome, reported, machine) = step_mips(&[jal, ADDIU_V0_1]);
+
+        // the slot is reported once, after the branch, and executes once
+        // before the branch is taken
+        assert_eq!(reported, [CODE, CODE + 4]);
+        assert_eq!(v0(&machine), 1);
+        assert_eq!(branched_to(&outcome), CALLEE);
+
+        let step = machine.step_state();
+        assert_eq!(u64::from(Address::from(&step.delay_slot().unwrap())), CODE + 4);
+        assert_eq!(u64::from(Address::from(&step.fallthrough())), CODE + 8);
+
+        // and the return address follows it
+        let state = machine.interpreter().state();
+        let ra = state.registers().register_by_name("ra").unwrap();
+        let mut bytes = [0u8; 4];
+        state.registers().get_register_values(&ra, &mut bytes).unwrap();
+        assert_eq!(u64::from(u32::from_be_bytes(bytes)), CODE + 8);
+    }
+
+    #[test]
+    fn mips_beq_delay_slot() {
+        // beq zero, zero, +3; addiu v0, v0, 1; two instructions skipped
+        let skipped = 0x2442_0010;
+        let (outcome, reported, machine) = step_mips(&[0x1000_0003, ADDIU_V0_1, skipped, skipped, 0]);
+
+        assert_eq!(reported, [CODE, CODE + 4]);
+        assert_eq!(v0(&machine), 1);
+        assert_eq!(branched_to(&outcome), CODE + 0x10);
+    }
+
+    #[test]
+    fn mips_bne_delay_slot_not_taken() {
+        // bne zero, zero, +3; addiu v0, v0, 1
+        let (outcome, reported, machine) = step_mips(&[0x1400_0003, ADDIU_V0_1, 0]);
+
+        // execution falls through past the slot
+        assert_eq!(reported, [CODE, CODE + 4]);
+        assert_eq!(v0(&machine), 1);
+        assert_eq!(branched_to(&outcome), CODE + 8);
+        assert_eq!(u64::from(Address::from(&machine.step_state().fallthrough())), CODE + 8);
+    }
+}
@@ -1,10 +1,41 @@
+// for the paths generated by `#[derive(AsState)]`
+extern crate fuguex_state as fugue_state;
+
+pub mod callbacks;
+
+pub mod callstack;
+
+pub mod context;
+
+pub mod coverage;
+
+pub mod crash;
+
+mod dispatch;
+
 pub mod driver;
 
+pub mod fuzz;
+
 pub mod hooks;
 
 pub mod interpreter;
 pub use interpreter::*;
 
+pub mod libc;
+
 pub mod microx;
 
+mod overrides;
+
+pub mod state;
+pub use state::ConcreteState;
+
+pub mod symbols;
+
+pub mod syscalls;
+
 pub mod tracker;
+
+#[cfg(test)]
+mod testing;
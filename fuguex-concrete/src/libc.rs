@@ -0,0 +1,828 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use fugue::bytes::Order;
+use fugue::db::Database;
+use fugue::ir::Address;
+
+use fuguex_hooks::types::{HookCallAction, HookCallSkip, HookOutcome};
+use fuguex_hooks::Error;
+
+use fuguex_state::chunked::{self, ChunkState};
+use fuguex_state::paged::{self, MappingMut};
+use fuguex_state::pcode::{self, VariadicArguments};
+use fuguex_state::traits::StateOps;
+
+use crate::hooks::{ClonableHookConcrete, HookConcrete, HookEvents, HookInterest};
+use crate::ConcreteState;
+
+pub const DEFAULT_HEAP_32: u64 = 0x2000_0000;
+pub const DEFAULT_HEAP_64: u64 = 0x5555_6000_0000;
+pub const DEFAULT_HEAP_SIZE: usize = 0x100_0000;
+
+// Memory functions operate on guest-controlled sizes in chunks of at most
+// this many bytes
+const CHUNK_SIZE: usize = 0x1000;
+
+// Field widths and precisions of `printf` conversions are clamped to this
+const MAX_FORMAT_WIDTH: usize = 0x1000;
+
+/// The C library functions with built-in models.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LibcFunction {
+    Abort,
+    Calloc,
+    Exit,
+    Fprintf,
+    Free,
+    Malloc,
+    Memcmp,
+    Memcpy,
+    Memmove,
+    Memset,
+    Printf,
+    Putchar,
+    Puts,
+    Realloc,
+    Snprintf,
+    Sprintf,
+    Strcmp,
+    Strcpy,
+    Strlen,
+    Strncmp,
+    Strncpy,
+}
+
+impl LibcFunction {
+    /// Resolve a symbol name; leading underscores (as used by Mach-O) and
+    /// version or PLT suffixes (e.g., `@GLIBC_2.2.5`, `@plt`) are ignored.
+    pub fn from_symbol<S: AsRef<str>>(symbol: S) -> Option<Self> {
+        let symbol = symbol.as_ref();
+        let name = symbol.split('@').next().unwrap_or(symbol);
+
+        Some(match name.trim_start_matches('_') {
+            "abort" => Self::Abort,
+            "calloc" => Self::Calloc,
+            "exit" | "Exit" => Self::Exit,
+            "fprintf" => Self::Fprintf,
+            "free" => Self::Free,
+            "malloc" => Self::Malloc,
+            "memcmp" => Self::Memcmp,
+            "memcpy" => Self::Memcpy,
+            "memmove" => Self::Memmove,
+            "memset" => Self::Memset,
+            "printf" => Self::Printf,
+            "putchar" => Self::Putchar,
+            "puts" => Self::Puts,
+            "realloc" => Self::Realloc,
+            "snprintf" => Self::Snprintf,
+            "sprintf" => Self::Sprintf,
+            "strcmp" => Self::Strcmp,
+            "strcpy" => Self::Strcpy,
+            "strlen" => Self::Strlen,
+            "strncmp" => Self::Strncmp,
+            "strncpy" => Self::Strncpy,
+            _ => return None,
+        })
+    }
+}
+
+/// How the program terminated when halted by a model.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LibcExit {
+    Exit(i64),
+    Abort,
+}
+
+/// Models of common C library functions, implemented as call hooks.
+///
+/// Calls to bound addresses are skipped and their effects applied
+/// directly to the state. Heap allocations are served from a `[heap]`
+/// `ChunkState` mapping created on first use, so out-of-bounds accesses
+/// and invalid frees are detected by the allocator. Output from the
+/// `printf` family is captured rather than written to a file. Doubles are
+/// read from the integer argument locations, as for soft-float and stack
+/// based conventions; `%g` and `%a` consume their argument but are emitted
+/// verbatim. Field widths and precisions are clamped to 4096.
+pub struct LibcModels<O: Order, R> {
+    functions: BTreeMap<Address, LibcFunction>,
+    heap_base: Option<u64>,
+    heap_size: usize,
+    output: Vec<u8>,
+    exit: Option<LibcExit>,
+    exit_outcome: Option<fn(LibcExit) -> R>,
+    marker: PhantomData<fn() -> O>,
+}
+
+impl<O: Order, R> Clone for LibcModels<O, R> {
+    fn clone(&self) -> Self {
+        Self {
+            functions: self.functions.clone(),
+            heap_base: self.heap_base,
+            heap_size: self.heap_size,
+            output: self.output.clone(),
+            exit: self.exit,
+            exit_outcome: self.exit_outcome,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<O: Order, R> Default for LibcModels<O, R> {
+    fn default() -> Self {
+        Self {
+            functions: BTreeMap::default(),
+            heap_base: None,
+            heap_size: DEFAULT_HEAP_SIZE,
+            output: Vec::default(),
+            exit: None,
+            exit_outcome: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<O: Order, R> LibcModels<O, R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Place the heap at `address`; by default it is placed at
+    /// `DEFAULT_HEAP_32` or `DEFAULT_HEAP_64`.
+    pub fn with_heap(mut self, address: u64, size: usize) -> Self {
+        self.heap_base = Some(address);
+        self.heap_size = size;
+        self
+    }
+
+    /// Map the status of `exit` and `abort` to the outcome the emulator
+    /// halts with; by default it halts with `R::default()`.
+    pub fn with_exit_outcome(mut self, outcome: fn(LibcExit) -> R) -> Self {
+        self.exit_outcome = Some(outcome);
+        self
+    }
+
+    pub fn bind<A: Into<Address>>(&mut self, function: LibcFunction, address: A) {
+        self.functions.insert(address.into(), function);
+    }
+
+    /// Bind the model for `symbol` to `address`; returns `false` if there
+    /// is no model for `symbol`.
+    pub fn bind_symbol<S, A>(&mut self, symbol: S, address: A) -> bool
+    where S: AsRef<str>,
+          A: Into<Address> {
+        if let Some(function) = LibcFunction::from_symbol(symbol) {
+            self.bind(function, address);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Bind each function of `database` that has a model by name; returns
+    /// the number of functions bound.
+    pub fn bind_database(&mut self, database: &Database) -> usize {
+        database.functions()
+            .iter()
+            .filter(|function| self.bind_symbol(function.name(), Address::from(function.address())))
+            .count()
+    }
+
+    pub fn unbind<A: Into<Address>>(&mut self, address: A) -> Option<LibcFunction> {
+        self.functions.remove(&address.into())
+    }
+
+    pub fn function<A: Into<Address>>(&self, address: A) -> Option<LibcFunction> {
+        self.functions.get(&address.into()).copied()
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = (&Address, LibcFunction)> {
+        self.functions.iter().map(|(address, function)| (address, *function))
+    }
+
+    /// The events to register the models for: calls to bound addresses.
+    pub fn interest(&self) -> HookInterest {
+        self.functions.keys().fold(HookInterest::new(HookEvents::CALL), |interest, address| {
+            interest.with_address(*address)
+        })
+    }
+
+    /// The output captured from `printf`, `fprintf`, `puts` and `putchar`.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// How the program terminated, if it called `exit` or `abort`.
+    pub fn exit(&self) -> Option<LibcExit> {
+        self.exit
+    }
+}
+
+type ModelResult<T> = Result<T, Error<pcode::Error>>;
+
+fn memory_error(error: paged::Error) -> Error<pcode::Error> {
+    Error::state(pcode::Error::Memory(error))
+}
+
+fn heap_error(error: chunked::Error) -> Error<pcode::Error> {
+    memory_error(paged::Error::Chunked(error))
+}
+
+fn sign_extend(value: u64, size: usize) -> i64 {
+    if size >= 8 {
+        value as i64
+    } else {
+        let shift = 64 - size * 8;
+        ((value << shift) as i64) >> shift
+    }
+}
+
+fn truncate(value: u64, size: usize) -> u64 {
+    if size >= 8 { value } else { value & ((1u64 << (size * 8)) - 1) }
+}
+
+fn to_bytes<O: Order>(value: u64, size: usize) -> Vec<u8> {
+    let size = size.min(8);
+    if O::ENDIAN.is_big() {
+        value.to_be_bytes()[8 - size..].to_vec()
+    } else {
+        value.to_le_bytes()[..size].to_vec()
+    }
+}
+
+fn argument<O: Order>(state: &ConcreteState<O>, index: usize) -> ModelResult<u64> {
+    state.argument(index).map_err(Error::state)
+}
+
+fn write_bytes<O: Order>(state: &mut ConcreteState<O>, address: u64, bytes: &[u8]) -> ModelResult<()> {
+    if bytes.is_empty() {
+        return Ok(())
+    }
+    state.set_values(Address::from(address), bytes).map_err(Error::state)
+}
+
+// Copies `size` bytes in chunks; overlapping ranges are copied as if via
+// an intermediate buffer.
+fn copy_bytes<O: Order>(state: &mut ConcreteState<O>, destination: u64, source: u64, size: u64) -> ModelResult<()> {
+    let mut buf = [0u8; CHUNK_SIZE];
+    let backwards = destination > source && destination - source < size;
+
+    let mut remaining = size;
+    while remaining > 0 {
+        let length = remaining.min(CHUNK_SIZE as u64);
+        let offset = if backwards { remaining - length } else { size - remaining };
+        let chunk = &mut buf[..length as usize];
+
+        state.get_values(Address::from(source.wrapping_add(offset)), chunk).map_err(Error::state)?;
+        write_bytes(state, destination.wrapping_add(offset), chunk)?;
+
+        remaining -= length;
+    }
+    Ok(())
+}
+
+fn fill_bytes<O: Order>(state: &mut ConcreteState<O>, destination: u64, value: u8, size: u64) -> ModelResult<()> {
+    let buf = [value; CHUNK_SIZE];
+
+    let mut offset = 0;
+    while offset < size {
+        let length = (size - offset).min(CHUNK_SIZE as u64);
+        write_bytes(state, destination.wrapping_add(offset), &buf[..length as usize])?;
+        offset += length;
+    }
+    Ok(())
+}
+
+fn compare_bytes<O: Order>(state: &ConcreteState<O>, lhs: u64, rhs: u64, size: u64) -> ModelResult<u64> {
+    let mut lbuf = [0u8; CHUNK_SIZE];
+    let mut rbuf = [0u8; CHUNK_SIZE];
+
+    let mut offset = 0;
+    while offset < size {
+        let length = (size - offset).min(CHUNK_SIZE as u64) as usize;
+        let (l, r) = (&mut lbuf[..length], &mut rbuf[..length]);
+
+        state.get_values(Address::from(lhs.wrapping_add(offset)), &mut *l).map_err(Error::state)?;
+        state.get_values(Address::from(rhs.wrapping_add(offset)), &mut *r).map_err(Error::state)?;
+
+        let result = compare(l, r);
+        if result != 0 {
+            return Ok(result)
+        }
+        offset += length as u64;
+    }
+    Ok(0)
+}
+
+// Reads a NUL-terminated string of at most `limit` bytes, excluding the NUL.
+fn read_string<O: Order>(state: &ConcreteState<O>, address: u64, limit: Option<usize>) -> ModelResult<Vec<u8>> {
+    let bytes = state.view_values_from(Address::from(address)).map_err(Error::state)?;
+    let bytes = &bytes[..limit.unwrap_or(bytes.len()).min(bytes.len())];
+    if let Some(end) = bytes.iter().position(|b| *b == 0) {
+        Ok(bytes[..end].to_vec())
+    } else if limit.map(|limit| bytes.len() == limit).unwrap_or(false) {
+        Ok(bytes.to_vec())
+    } else {
+        Err(memory_error(paged::Error::UnmappedAddress {
+            address: Address::from(address) + bytes.len(),
+            size: 1,
+        }))
+    }
+}
+
+fn compare(lhs: &[u8], rhs: &[u8]) -> u64 {
+    lhs.iter()
+        .zip(rhs.iter())
+        .find(|(l, r)| l != r)
+        .map(|(l, r)| (*l as i64 - *r as i64) as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Default)]
+struct Conversion {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Conversion {
+    // Zero padding applies only if the `0` flag is set and the conversion
+    // allows it, e.g., integers without a precision.
+    fn pad(&self, out: &mut Vec<u8>, prefix: &[u8], body: &[u8], zero: bool) {
+        let length = prefix.len() + body.len();
+        let padding = self.width.saturating_sub(length);
+
+        if self.left {
+            out.extend_from_slice(prefix);
+            out.extend_from_slice(body);
+            out.extend(std::iter::repeat(b' ').take(padding));
+        } else if self.zero && zero {
+            out.extend_from_slice(prefix);
+            out.extend(std::iter::repeat(b'0').take(padding));
+            out.extend_from_slice(body);
+        } else {
+            out.extend(std::iter::repeat(b' ').take(padding));
+            out.extend_from_slice(prefix);
+            out.extend_from_slice(body);
+        }
+    }
+
+    fn float(&self, value: f64, specifier: u8) -> (&'static [u8], Vec<u8>) {
+        let prefix = if value.is_sign_negative() && !value.is_nan() {
+            &b"-"[..]
+        } else if self.plus {
+            b"+"
+        } else if self.space {
+            b" "
+        } else {
+            b""
+        };
+
+        let value = value.abs();
+        let precision = self.precision.unwrap_or(6);
+
+        let body = if value.is_nan() {
+            "nan".to_owned()
+        } else if value.is_infinite() {
+            "inf".to_owned()
+        } else if specifier.to_ascii_lowercase() == b'e' {
+            // Rust formats the exponent as, e.g., `e3` rather than `e+03`
+            let formatted = format!("{:.*e}", precision, value);
+            let (mantissa, exponent) = formatted.split_at(formatted.find('e').unwrap_or(formatted.len()));
+            let exponent = exponent.get(1..).and_then(|e| e.parse::<i32>().ok()).unwrap_or(0);
+            format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.unsigned_abs())
+        } else {
+            format!("{:.*}", precision, value)
+        };
+
+        let mut body = body.into_bytes();
+        if self.alternate && precision == 0 && value.is_finite() {
+            let end = body.iter().position(|c| *c == b'e').unwrap_or(body.len());
+            body.insert(end, b'.');
+        }
+        if specifier.is_ascii_uppercase() {
+            body.make_ascii_uppercase();
+        }
+
+        (prefix, body)
+    }
+
+    fn digits(&self, digits: String) -> Vec<u8> {
+        match self.precision {
+            Some(0) if digits == "0" => Vec::new(),
+            Some(precision) if precision > digits.len() => {
+                let mut padded = vec![b'0'; precision - digits.len()];
+                padded.extend_from_slice(digits.as_bytes());
+                padded
+            },
+            _ => digits.into_bytes(),
+        }
+    }
+}
+
+fn parse_number(format: &[u8], index: &mut usize) -> usize {
+    let mut value = 0usize;
+    while let Some(digit) = format.get(*index).filter(|c| c.is_ascii_digit()) {
+        value = value.saturating_mul(10).saturating_add((digit - b'0') as usize);
+        *index += 1;
+    }
+    value
+}
+
+// Integers wider than a pointer occupy two argument slots.
+fn integer_argument<O: Order>(state: &ConcreteState<O>, arguments: &mut VariadicArguments, size: usize) -> ModelResult<u64> {
+    let pointer_size = state.memory_space_ref().address_size();
+    if size > pointer_size {
+        let first = arguments.next_argument(state).map_err(Error::state)?;
+        let second = arguments.next_argument(state).map_err(Error::state)?;
+        Ok(if O::ENDIAN.is_big() {
+            (first << 32) | truncate(second, 4)
+        } else {
+            (second << 32) | truncate(first, 4)
+        })
+    } else {
+        arguments.next_argument(state).map(|value| truncate(value, size)).map_err(Error::state)
+    }
+}
+
+fn format<O: Order>(state: &mut ConcreteState<O>, format: &[u8], mut arguments: VariadicArguments) -> ModelResult<Vec<u8>> {
+    let pointer_size = state.memory_space_ref().address_size();
+    let mut out = Vec::new();
+    let mut index = 0;
+
+    while index < format.len() {
+        let c = format[index];
+        index += 1;
+
+        if c != b'%' {
+            out.push(c);
+            continue
+        }
+
+        let start = index - 1;
+        let mut conversion = Conversion::default();
+
+        while let Some(flag) = format.get(index) {
+            match flag {
+                b'-' => conversion.left = true,
+                b'+' => conversion.plus = true,
+                b' ' => conversion.space = true,
+                b'#' => conversion.alternate = true,
+                b'0' => conversion.zero = true,
+                _ => break,
+            }
+            index += 1;
+        }
+
+        if format.get(index) == Some(&b'*') {
+            index += 1;
+            let width = sign_extend(integer_argument(state, &mut arguments, 4)?, 4);
+            conversion.left |= width < 0;
+            conversion.width = width.unsigned_abs() as usize;
+        } else {
+            conversion.width = parse_number(format, &mut index);
+        }
+        conversion.width = conversion.width.min(MAX_FORMAT_WIDTH);
+
+        if format.get(index) == Some(&b'.') {
+            index += 1;
+            if format.get(index) == Some(&b'*') {
+                index += 1;
+                let precision = sign_extend(integer_argument(state, &mut arguments, 4)?, 4);
+                conversion.precision = if precision < 0 { None } else { Some(precision as usize) };
+            } else {
+                conversion.precision = Some(parse_number(format, &mut index));
+            }
+            conversion.precision = conversion.precision.map(|p| p.min(MAX_FORMAT_WIDTH));
+        }
+
+        let size = match (format.get(index), format.get(index + 1)) {
+            (Some(b'h'), Some(b'h')) => { index += 2; 1 },
+            (Some(b'l'), Some(b'l')) => { index += 2; 8 },
+            (Some(b'h'), _) => { index += 1; 2 },
+            (Some(b'l'), _) | (Some(b'z'), _) | (Some(b't'), _) => { index += 1; pointer_size },
+            (Some(b'j'), _) | (Some(b'q'), _) | (Some(b'L'), _) => { index += 1; 8 },
+            _ => 4,
+        };
+
+        let specifier = if let Some(specifier) = format.get(index) {
+            index += 1;
+            *specifier
+        } else {
+            out.extend_from_slice(&format[start..]);
+            break
+        };
+
+        match specifier {
+            b'%' => out.push(b'%'),
+            b'd' | b'i' => {
+                let value = sign_extend(integer_argument(state, &mut arguments, size)?, size);
+                let prefix = if value < 0 {
+                    &b"-"[..]
+                } else if conversion.plus {
+                    b"+"
+                } else if conversion.space {
+                    b" "
+                } else {
+                    b""
+                };
+                let body = conversion.digits(value.unsigned_abs().to_string());
+                conversion.pad(&mut out, prefix, &body, conversion.precision.is_none());
+            },
+            b'u' | b'x' | b'X' | b'o' => {
+                let value = integer_argument(state, &mut arguments, size)?;
+                let (digits, prefix): (String, &[u8]) = match specifier {
+                    b'u' => (value.to_string(), b""),
+                    b'x' => (format!("{:x}", value), if conversion.alternate && value != 0 { &b"0x"[..] } else { b"" }),
+                    b'X' => (format!("{:X}", value), if conversion.alternate && value != 0 { &b"0X"[..] } else { b"" }),
+                    _ => (format!("{:o}", value), if conversion.alternate && value != 0 { &b"0"[..] } else { b"" }),
+                };
+                let body = conversion.digits(digits);
+                conversion.pad(&mut out, prefix, &body, conversion.precision.is_none());
+            },
+            b'c' => {
+                let value = integer_argument(state, &mut arguments, 4)?;
+                conversion.pad(&mut out, b"", &[value as u8], false);
+            },
+            b's' => {
+                let address = integer_argument(state, &mut arguments, pointer_size)?;
+                let body = if address == 0 {
+                    b"(null)".to_vec()
+                } else {
+                    read_string(state, address, conversion.precision)?
+                };
+                conversion.pad(&mut out, b"", &body, false);
+            },
+            b'p' => {
+                let address = integer_argument(state, &mut arguments, pointer_size)?;
+                if address == 0 {
+                    conversion.pad(&mut out, b"", b"(nil)", false);
+                } else {
+                    conversion.pad(&mut out, b"0x", format!("{:x}", address).as_bytes(), conversion.precision.is_none());
+                }
+            },
+            b'f' | b'F' | b'e' | b'E' => {
+                let value = f64::from_bits(integer_argument(state, &mut arguments, 8)?);
+                let finite = value.is_finite();
+                let (prefix, body) = conversion.float(value, specifier);
+                conversion.pad(&mut out, prefix, &body, finite);
+            },
+            b'g' | b'G' | b'a' | b'A' => {
+                integer_argument(state, &mut arguments, 8)?;
+                out.extend_from_slice(&format[start..index]);
+            },
+            b'n' => {
+                let address = integer_argument(state, &mut arguments, pointer_size)?;
+                write_bytes(state, address, &to_bytes::<O>(out.len() as u64, size))?;
+            },
+            _ => out.extend_from_slice(&format[start..index]),
+        }
+    }
+
+    Ok(out)
+}
+
+impl<O: Order, R> LibcModels<O, R> {
+    fn heap<'a>(&self, state: &'a mut ConcreteState<O>) -> ModelResult<&'a mut ChunkState<u8>> {
+        let base = Address::from(self.heap_base.unwrap_or(if state.memory_space_ref().address_size() >= 8 {
+            DEFAULT_HEAP_64
+        } else {
+            DEFAULT_HEAP_32
+        }));
+
+        if state.memory().mapping_for(base).is_none() {
+            state.memory_mut()
+                .mapping("[heap]", base, self.heap_size)
+                .map_err(memory_error)?;
+        }
+
+        match state.memory_mut().mapping_for_mut(base) {
+            Some(MappingMut::Dynamic(heap)) => Ok(heap),
+            _ => Err(Error::Other(anyhow::anyhow!("heap at {} overlaps an existing static mapping", base))),
+        }
+    }
+
+    // Allocation failures are reported to the program as NULL.
+    fn allocate(&self, state: &mut ConcreteState<O>, size: u64, zero: bool) -> ModelResult<u64> {
+        let heap = self.heap(state)?;
+        let result = if zero {
+            heap.allocate_with(size.max(1) as usize, |_, bytes| bytes.iter_mut().for_each(|b| *b = 0))
+        } else {
+            heap.allocate(size.max(1) as usize)
+        };
+
+        match result {
+            Ok(address) => Ok(u64::from(address)),
+            Err(chunked::Error::NotEnoughFreeSpace(_)) => Ok(0),
+            Err(e) => Err(heap_error(e)),
+        }
+    }
+
+    fn print(&mut self, state: &mut ConcreteState<O>, format_index: usize) -> ModelResult<u64> {
+        let fmt = read_string(state, argument(state, format_index)?, None)?;
+        let arguments = state.variadic_arguments(format_index + 1);
+        let formatted = format(state, &fmt, arguments)?;
+        self.output.extend_from_slice(&formatted);
+        Ok(formatted.len() as u64)
+    }
+
+    fn model(&mut self, state: &mut ConcreteState<O>, function: LibcFunction) -> ModelResult<Result<u64, LibcExit>> {
+        use LibcFunction::*;
+
+        let pointer_size = state.memory_space_ref().address_size();
+
+        let value = match function {
+            Abort => return Ok(Err(LibcExit::Abort)),
+            Exit => return Ok(Err(LibcExit::Exit(sign_extend(argument(state, 0)?, 4)))),
+            Malloc => self.allocate(state, argument(state, 0)?, false)?,
+            Calloc => {
+                let count = argument(state, 0)?;
+                let size = argument(state, 1)?;
+                match count.checked_mul(size) {
+                    Some(total) if total <= usize::MAX as u64 => self.allocate(state, total, true)?,
+                    _ => 0,
+                }
+            },
+            Realloc => {
+                let address = argument(state, 0)?;
+                let size = argument(state, 1)?;
+                if address == 0 {
+                    self.allocate(state, size, false)?
+                } else if size == 0 {
+                    self.heap(state)?.deallocate(address).map_err(heap_error)?;
+                    0
+                } else {
+                    match self.heap(state)?.reallocate(address, size as usize) {
+                        Ok(address) => u64::from(address),
+                        Err(chunked::Error::NotEnoughFreeSpace(_)) => 0,
+                        Err(e) => return Err(heap_error(e)),
+                    }
+                }
+            },
+            Free => {
+                let address = argument(state, 0)?;
+                if address != 0 {
+                    self.heap(state)?.deallocate(address).map_err(heap_error)?;
+                }
+                0
+            },
+            Memcpy | Memmove => {
+                let destination = argument(state, 0)?;
+                copy_bytes(state, destination, argument(state, 1)?, argument(state, 2)?)?;
+                destination
+            },
+            Memset => {
+                let destination = argument(state, 0)?;
+                fill_bytes(state, destination, argument(state, 1)? as u8, argument(state, 2)?)?;
+                destination
+            },
+            Memcmp => compare_bytes(state, argument(state, 0)?, argument(state, 1)?, argument(state, 2)?)?,
+            Strlen => read_string(state, argument(state, 0)?, None)?.len() as u64,
+            Strcpy => {
+                let destination = argument(state, 0)?;
+                let mut bytes = read_string(state, argument(state, 1)?, None)?;
+                bytes.push(0);
+                write_bytes(state, destination, &bytes)?;
+                destination
+            },
+            Strncpy => {
+                let destination = argument(state, 0)?;
+                let size = argument(state, 2)?;
+                let bytes = read_string(state, argument(state, 1)?, Some(size as usize))?;
+                write_bytes(state, destination, &bytes)?;
+                fill_bytes(state, destination.wrapping_add(bytes.len() as u64), 0, size - bytes.len() as u64)?;
+                destination
+            },
+            Strcmp => {
+                let mut lhs = read_string(state, argument(state, 0)?, None)?;
+                let mut rhs = read_string(state, argument(state, 1)?, None)?;
+                lhs.push(0);
+                rhs.push(0);
+                compare(&lhs, &rhs)
+            },
+            Strncmp => {
+                let size = argument(state, 2)? as usize;
+                let mut lhs = read_string(state, argument(state, 0)?, Some(size))?;
+                let mut rhs = read_string(state, argument(state, 1)?, Some(size))?;
+                lhs.push(0);
+                rhs.push(0);
+                compare(&lhs[..size.min(lhs.len())], &rhs[..size.min(rhs.len())])
+            },
+            Printf => self.print(state, 0)?,
+            Fprintf => self.print(state, 1)?,
+            Sprintf | Snprintf => {
+                let destination = argument(state, 0)?;
+                let (limit, format_index) = if function == Snprintf {
+                    (Some(argument(state, 1)? as usize), 2)
+                } else {
+                    (None, 1)
+                };
+
+                let fmt = read_string(state, argument(state, format_index)?, None)?;
+                let arguments = state.variadic_arguments(format_index + 1);
+                let mut formatted = format(state, &fmt, arguments)?;
+                let length = formatted.len() as u64;
+
+                if let Some(limit) = limit {
+                    formatted.truncate(limit.saturating_sub(1));
+                }
+                if limit != Some(0) {
+                    formatted.push(0);
+                    write_bytes(state, destination, &formatted)?;
+                }
+                length
+            },
+            Puts => {
+                let bytes = read_string(state, argument(state, 0)?, None)?;
+                self.output.extend_from_slice(&bytes);
+                self.output.push(b'\n');
+                bytes.len() as u64 + 1
+            },
+            Putchar => {
+                let value = argument(state, 0)? as u8;
+                self.output.push(value);
+                value as u64
+            },
+        };
+
+        Ok(Ok(truncate(value, pointer_size)))
+    }
+}
+
+impl<O, R> HookConcrete for LibcModels<O, R>
+where
+    O: Order + 'static,
+    R: Default + 'static,
+{
+    type State = ConcreteState<O>;
+    type Error = pcode::Error;
+    type Outcome = R;
+
+    fn hook_call(
+        &mut self,
+        state: &mut Self::State,
+        destination: &Address,
+    ) -> Result<HookOutcome<HookCallAction<Self::Outcome>>, Error<Self::Error>> {
+        let function = if let Some(function) = self.functions.get(destination) {
+            *function
+        } else {
+            return Ok(HookCallAction::Pass.into())
+        };
+
+        log::trace!("libc model {:?} at {}", function, destination);
+
+        match self.model(state, function)? {
+            Ok(value) => Ok(HookOutcome::from(HookCallAction::SkipWith(
+                HookCallSkip::new().with_return_value(value)
+            )).state_changed(true)),
+            Err(exit) => {
+                self.exit = Some(exit);
+                let outcome = self.exit_outcome.map(|f| f(exit)).unwrap_or_default();
+                Ok(HookOutcome::from(HookCallAction::Halt(outcome)).state_changed(true))
+            },
+        }
+    }
+}
+
+impl<O, R> ClonableHookConcrete for LibcModels<O, R>
+where
+    O: Order + 'static,
+    R: Default + 'static,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn float(conversion: &Conversion, value: f64, specifier: u8) -> String {
+        let (prefix, body) = conversion.float(value, specifier);
+        let mut out = Vec::new();
+        conversion.pad(&mut out, prefix, &body, value.is_finite());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn float_conversions() {
+        let default = Conversion::default();
+        assert_eq!(float(&default, 1.5, b'f'), "1.500000");
+        assert_eq!(float(&default, -0.25, b'e'), "-2.500000e-01");
+        assert_eq!(float(&default, 12345.0, b'E'), "1.234500E+04");
+        assert_eq!(float(&default, f64::INFINITY, b'F'), "INF");
+        assert_eq!(float(&default, f64::NAN, b'f'), "nan");
+
+        let padded = Conversion { zero: true, plus: true, width: 8, precision: Some(2), ..Conversion::default() };
+        assert_eq!(float(&padded, 3.14159, b'f'), "+0003.14");
+
+        let alternate = Conversion { alternate: true, precision: Some(0), ..Conversion::default() };
+        assert_eq!(float(&alternate, 2.0, b'f'), "2.");
+    }
+}
@@ -0,0 +1,67 @@
+use std::marker::PhantomData;
+
+use dyn_clone::{clone_trait_object, DynClone};
+
+use fuguex_hooks::types::{Error, HookStepAction};
+use fuguex_machine::StepState;
+use fuguex_state::pcode;
+
+use crate::callbacks::CallbackAction;
+use crate::ConcreteState;
+
+pub(crate) trait Semantic<O, R>: DynClone {
+    fn apply(&mut self, state: &mut ConcreteState<O>) -> Result<HookStepAction<R>, Error<pcode::Error>>;
+}
+
+clone_trait_object!(<O, R> Semantic<O, R>);
+
+pub(crate) struct SemanticFn<F, T> {
+    semantic: F,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<F, T> SemanticFn<F, T> {
+    pub fn new(semantic: F) -> Self {
+        Self {
+            semantic,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<F: Clone, T> Clone for SemanticFn<F, T> {
+    fn clone(&self) -> Self {
+        Self::new(self.semantic.clone())
+    }
+}
+
+impl<F, T, O, R> Semantic<O, R> for SemanticFn<F, T>
+where
+    F: FnMut(&mut ConcreteState<O>) -> T + Clone,
+    T: CallbackAction<HookStepAction<R>>,
+{
+    fn apply(&mut self, state: &mut ConcreteState<O>) -> Result<HookStepAction<R>, Error<pcode::Error>> {
+        (self.semantic)(state).into_action()
+    }
+}
+
+/// Replaces the lifted semantics of an instruction.
+pub(crate) enum InstructionOverride<O, R> {
+    PCode(StepState),
+    Semantic {
+        length: usize,
+        semantic: Box<dyn Semantic<O, R>>,
+    },
+}
+
+impl<O, R> Clone for InstructionOverride<O, R> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::PCode(step_state) => Self::PCode(step_state.clone()),
+            Self::Semantic { length, semantic } => Self::Semantic {
+                length: *length,
+                semantic: semantic.clone(),
+            },
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::ops::{Deref, DerefMut};
+
+use fugue::bytes::Order;
+use fugue::ir::Address;
+
+use fuguex_state::pcode::{Error, PCodeState};
+use fuguex_state::traits::{AsState, State, StateOps};
+use fuguex_state::vfs::FileSystem;
+
+use crate::callstack::{CallStack, Frame};
+
+/// The state of a `ConcreteContext`: a `PCodeState`, which it dereferences
+/// to, the `FileSystem` of the emulated process and, if calls are tracked,
+/// its shadow `CallStack`.
+///
+/// The first two are reachable through `AsState`, so hooks and intrinsics
+/// generic over `S: AsState<PCodeState<u8, O>>` (or `AsState<FileSystem>`,
+/// or `AsState2` of both) can be used with a `ConcreteContext`; all are
+/// forked and restored together.
+#[derive(Debug, Clone, AsState)]
+pub struct ConcreteState<O: Order> {
+    #[fugue]
+    pcode: PCodeState<u8, O>,
+    #[fugue]
+    files: FileSystem,
+    calls: Option<CallStack>,
+    context_writes: Vec<(String, u32)>,
+}
+
+impl<O: Order> ConcreteState<O> {
+    pub fn new(pcode: PCodeState<u8, O>) -> Self {
+        Self::with_file_system(pcode, FileSystem::default())
+    }
+
+    pub fn with_file_system(pcode: PCodeState<u8, O>, files: FileSystem) -> Self {
+        Self { pcode, files, calls: None, context_writes: Vec::new() }
+    }
+
+    pub fn pcode(&self) -> &PCodeState<u8, O> {
+        &self.pcode
+    }
+
+    pub fn pcode_mut(&mut self) -> &mut PCodeState<u8, O> {
+        &mut self.pcode
+    }
+
+    pub fn file_system(&self) -> &FileSystem {
+        &self.files
+    }
+
+    pub fn file_system_mut(&mut self) -> &mut FileSystem {
+        &mut self.files
+    }
+
+    /// Maintain a shadow call stack from calls and returns.
+    pub fn track_calls(&mut self, enabled: bool) {
+        self.calls = if enabled {
+            Some(self.calls.take().unwrap_or_default())
+        } else {
+            None
+        };
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.calls.as_ref()
+    }
+
+    pub fn call_stack_mut(&mut self) -> Option<&mut CallStack> {
+        self.calls.as_mut()
+    }
+
+    /// The frames of the shadow call stack, innermost first; empty unless
+    /// calls are tracked.
+    pub fn backtrace(&self) -> Vec<Frame> {
+        self.calls
+            .iter()
+            .flat_map(|stack| stack.backtrace().copied())
+            .collect()
+    }
+
+    /// Set a decoding context variable for the instructions lifted next,
+    /// e.g., from a hook; it is overwritten by any context switch for the
+    /// same variable, and an unknown variable fails the next lift.
+    pub fn set_context_variable<S: Into<String>>(&mut self, name: S, value: u32) {
+        self.context_writes.push((name.into(), value));
+    }
+
+    pub(crate) fn take_context_writes(&mut self) -> Vec<(String, u32)> {
+        std::mem::take(&mut self.context_writes)
+    }
+
+    pub fn into_parts(self) -> (PCodeState<u8, O>, FileSystem) {
+        (self.pcode, self.files)
+    }
+}
+
+impl<O: Order> From<PCodeState<u8, O>> for ConcreteState<O> {
+    fn from(pcode: PCodeState<u8, O>) -> Self {
+        Self::new(pcode)
+    }
+}
+
+impl<O: Order> Deref for ConcreteState<O> {
+    type Target = PCodeState<u8, O>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pcode
+    }
+}
+
+impl<O: Order> DerefMut for ConcreteState<O> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.pcode
+    }
+}
+
+impl<O: Order> State for ConcreteState<O> {
+    type Error = Error;
+
+    fn fork(&self) -> Self {
+        Self {
+            pcode: self.pcode.fork(),
+            files: self.files.fork(),
+            calls: self.calls.clone(),
+            context_writes: self.context_writes.clone(),
+        }
+    }
+
+    fn restore(&mut self, other: &Self) {
+        self.pcode.restore(&other.pcode);
+        self.files.restore(&other.files);
+        self.calls.clone_from(&other.calls);
+        self.context_writes.clone_from(&other.context_writes);
+    }
+}
+
+impl<O: Order> StateOps for ConcreteState<O> {
+    type Value = u8;
+
+    #[inline(always)]
+    fn copy_values<F, T>(&mut self, from: F, to: T, size: usize) -> Result<(), Self::Error>
+    where F: Into<Address>,
+          T: Into<Address> {
+        self.pcode.copy_values(from, to, size)
+    }
+
+    #[inline(always)]
+    fn get_values<A>(&self, address: A, values: &mut [Self::Value]) -> Result<(), Self::Error>
+    where A: Into<Address> {
+        self.pcode.get_values(address, values)
+    }
+
+    #[inline(always)]
+    fn view_values<A>(&self, address: A, size: usize) -> Result<&[Self::Value], Self::Error>
+    where A: Into<Address> {
+        self.pcode.view_values(address, size)
+    }
+
+    #[inline(always)]
+    fn view_values_mut<A>(&mut self, address: A, size: usize) -> Result<&mut [Self::Value], Self::Error>
+    where A: Into<Address> {
+        self.pcode.view_values_mut(address, size)
+    }
+
+    #[inline(always)]
+    fn set_values<A>(&mut self, address: A, values: &[Self::Value]) -> Result<(), Self::Error>
+    where A: Into<Address> {
+        self.pcode.set_values(address, values)
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.pcode.len()
+    }
+}
@@ -0,0 +1,60 @@
+use fugue::db::Database;
+use fugue::ir::Address;
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, Error)]
+pub enum Error {
+    #[error("no database available to resolve symbol `{0}`")]
+    NoDatabase(String),
+    #[error("unknown symbol `{0}`")]
+    Unknown(String),
+    #[error("ambiguous symbol `{symbol}` resolves to {} addresses", .addresses.len())]
+    Ambiguous { symbol: String, addresses: Vec<Address> },
+}
+
+// Strip the decorations disassemblers use for import thunks and PLT
+// stubs, e.g., `malloc@plt`, `.malloc`, `j_malloc` and `__imp_malloc`.
+fn stub_target(name: &str) -> &str {
+    let name = name.split('@').next().unwrap_or(name);
+    ["__imp_", "j_", "."].iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Resolve the address of the function named `symbol` in `database`,
+/// displaced by `offset` if the image has been rebased.
+///
+/// Exact matches are preferred; otherwise, import thunks and PLT stubs
+/// for `symbol` are considered.
+pub fn resolve<S: AsRef<str>>(database: &Database, symbol: S, offset: u64) -> Result<Address, Error> {
+    let symbol = symbol.as_ref();
+
+    let mut exact = Vec::new();
+    let mut stubs = Vec::new();
+
+    for function in database.functions().iter() {
+        let address = Address::from(u64::from(Address::from(function.address())).wrapping_add(offset));
+        let name = function.name();
+
+        let matches = if name == symbol {
+            &mut exact
+        } else if stub_target(name) == symbol {
+            &mut stubs
+        } else {
+            continue
+        };
+
+        if !matches.contains(&address) {
+            matches.push(address);
+        }
+    }
+
+    let mut addresses = if exact.is_empty() { stubs } else { exact };
+
+    match addresses.len() {
+        0 => Err(Error::Unknown(symbol.to_owned())),
+        1 => Ok(addresses.pop().unwrap()),
+        _ => Err(Error::Ambiguous { symbol: symbol.to_owned(), addresses }),
+    }
+}
@@ -0,0 +1,1409 @@
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use fugue::bytes::Order;
+use fugue::ir::il::pcode::Operand;
+use fugue::ir::{Address, AddressValue};
+
+use fuguex_intrinsics::{Error, IntrinsicAction, IntrinsicBehaviour};
+
+use fuguex_state::flat::Access;
+use fuguex_state::pcode;
+use fuguex_state::traits::StateOps;
+use fuguex_state::vfs::{Descriptor, OpenOptions, Whence};
+
+use crate::ConcreteState;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+pub const DEFAULT_BRK_32: u64 = 0x1000_0000;
+pub const DEFAULT_BRK_64: u64 = 0x5555_5600_0000;
+pub const DEFAULT_MMAP_32: u64 = 0x4000_0000;
+pub const DEFAULT_MMAP_64: u64 = 0x7fff_f000_0000;
+pub const DEFAULT_EPOCH: u64 = 1_600_000_000;
+
+/// The default limit on the memory mapped by `brk` and `mmap`.
+pub const DEFAULT_MEMORY_LIMIT: u64 = 1 << 30;
+
+/// The most bytes transferred by a single read or write; longer transfers
+/// are shortened, as by Linux's `MAX_RW_COUNT`.
+pub const MAX_IO_SIZE: usize = 1 << 24;
+
+/// The most buffers accepted by `readv` and `writev`, as `UIO_MAXIOV`.
+pub const MAX_IOVECS: u64 = 1024;
+
+const ENOENT: u64 = 2;
+const EBADF: u64 = 9;
+const ENOMEM: u64 = 12;
+const EFAULT: u64 = 14;
+const EINVAL: u64 = 22;
+
+const AT_FDCWD: i64 = -100;
+
+const PROT_READ: u64 = 1;
+const PROT_WRITE: u64 = 2;
+const PROT_EXEC: u64 = 4;
+
+const MAP_FIXED: u64 = 0x10;
+
+const SEEK_SET: u64 = 0;
+const SEEK_CUR: u64 = 1;
+const SEEK_END: u64 = 2;
+
+const O_ACCMODE: u64 = 3;
+const O_WRONLY: u64 = 1;
+const O_RDWR: u64 = 2;
+
+/// The system calls with built-in models; calls not listed here return
+/// `-ENOSYS` (or halt, see `Syscalls::halt_on_unsupported`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Syscall {
+    Access,
+    Brk,
+    ClockGettime,
+    Close,
+    Dup,
+    Dup2,
+    Exit,
+    ExitGroup,
+    GetEgid,
+    GetEuid,
+    GetGid,
+    GetPid,
+    GetPpid,
+    GetTid,
+    GetTimeOfDay,
+    GetUid,
+    Lseek,
+    Mmap,
+    Mmap2,
+    Mprotect,
+    Munmap,
+    Open,
+    OpenAt,
+    Read,
+    Readv,
+    SetTidAddress,
+    Time,
+    Uname,
+    Write,
+    Writev,
+}
+
+const X86_64_SYSCALLS: &[(u64, Syscall)] = &[
+    (0, Syscall::Read),
+    (1, Syscall::Write),
+    (2, Syscall::Open),
+    (3, Syscall::Close),
+    (8, Syscall::Lseek),
+    (9, Syscall::Mmap),
+    (10, Syscall::Mprotect),
+    (11, Syscall::Munmap),
+    (12, Syscall::Brk),
+    (19, Syscall::Readv),
+    (20, Syscall::Writev),
+    (21, Syscall::Access),
+    (32, Syscall::Dup),
+    (33, Syscall::Dup2),
+    (39, Syscall::GetPid),
+    (60, Syscall::Exit),
+    (63, Syscall::Uname),
+    (96, Syscall::GetTimeOfDay),
+    (102, Syscall::GetUid),
+    (104, Syscall::GetGid),
+    (107, Syscall::GetEuid),
+    (108, Syscall::GetEgid),
+    (110, Syscall::GetPpid),
+    (186, Syscall::GetTid),
+    (201, Syscall::Time),
+    (218, Syscall::SetTidAddress),
+    (228, Syscall::ClockGettime),
+    (231, Syscall::ExitGroup),
+    (257, Syscall::OpenAt),
+];
+
+const X86_SYSCALLS: &[(u64, Syscall)] = &[
+    (1, Syscall::Exit),
+    (3, Syscall::Read),
+    (4, Syscall::Write),
+    (5, Syscall::Open),
+    (6, Syscall::Close),
+    (13, Syscall::Time),
+    (19, Syscall::Lseek),
+    (20, Syscall::GetPid),
+    (24, Syscall::GetUid),
+    (33, Syscall::Access),
+    (41, Syscall::Dup),
+    (45, Syscall::Brk),
+    (47, Syscall::GetGid),
+    (49, Syscall::GetEuid),
+    (50, Syscall::GetEgid),
+    (63, Syscall::Dup2),
+    (64, Syscall::GetPpid),
+    (78, Syscall::GetTimeOfDay),
+    (91, Syscall::Munmap),
+    (122, Syscall::Uname),
+    (125, Syscall::Mprotect),
+    (145, Syscall::Readv),
+    (146, Syscall::Writev),
+    (192, Syscall::Mmap2),
+    (199, Syscall::GetUid),
+    (200, Syscall::GetGid),
+    (201, Syscall::GetEuid),
+    (202, Syscall::GetEgid),
+    (224, Syscall::GetTid),
+    (252, Syscall::ExitGroup),
+    (258, Syscall::SetTidAddress),
+    (265, Syscall::ClockGettime),
+    (295, Syscall::OpenAt),
+];
+
+const ARM_SYSCALLS: &[(u64, Syscall)] = &[
+    (1, Syscall::Exit),
+    (3, Syscall::Read),
+    (4, Syscall::Write),
+    (5, Syscall::Open),
+    (6, Syscall::Close),
+    (19, Syscall::Lseek),
+    (20, Syscall::GetPid),
+    (24, Syscall::GetUid),
+    (33, Syscall::Access),
+    (41, Syscall::Dup),
+    (45, Syscall::Brk),
+    (47, Syscall::GetGid),
+    (49, Syscall::GetEuid),
+    (50, Syscall::GetEgid),
+    (63, Syscall::Dup2),
+    (64, Syscall::GetPpid),
+    (78, Syscall::GetTimeOfDay),
+    (91, Syscall::Munmap),
+    (122, Syscall::Uname),
+    (125, Syscall::Mprotect),
+    (145, Syscall::Readv),
+    (146, Syscall::Writev),
+    (192, Syscall::Mmap2),
+    (199, Syscall::GetUid),
+    (200, Syscall::GetGid),
+    (201, Syscall::GetEuid),
+    (202, Syscall::GetEgid),
+    (224, Syscall::GetTid),
+    (248, Syscall::ExitGroup),
+    (256, Syscall::SetTidAddress),
+    (263, Syscall::ClockGettime),
+    (322, Syscall::OpenAt),
+];
+
+const AARCH64_SYSCALLS: &[(u64, Syscall)] = &[
+    (23, Syscall::Dup),
+    (56, Syscall::OpenAt),
+    (57, Syscall::Close),
+    (62, Syscall::Lseek),
+    (63, Syscall::Read),
+    (64, Syscall::Write),
+    (65, Syscall::Readv),
+    (66, Syscall::Writev),
+    (93, Syscall::Exit),
+    (94, Syscall::ExitGroup),
+    (96, Syscall::SetTidAddress),
+    (113, Syscall::ClockGettime),
+    (160, Syscall::Uname),
+    (169, Syscall::GetTimeOfDay),
+    (172, Syscall::GetPid),
+    (173, Syscall::GetPpid),
+    (174, Syscall::GetUid),
+    (175, Syscall::GetEuid),
+    (176, Syscall::GetGid),
+    (177, Syscall::GetEgid),
+    (178, Syscall::GetTid),
+    (214, Syscall::Brk),
+    (215, Syscall::Munmap),
+    (222, Syscall::Mmap),
+    (226, Syscall::Mprotect),
+];
+
+const MIPS_SYSCALLS: &[(u64, Syscall)] = &[
+    (4001, Syscall::Exit),
+    (4003, Syscall::Read),
+    (4004, Syscall::Write),
+    (4005, Syscall::Open),
+    (4006, Syscall::Close),
+    (4013, Syscall::Time),
+    (4019, Syscall::Lseek),
+    (4020, Syscall::GetPid),
+    (4024, Syscall::GetUid),
+    (4033, Syscall::Access),
+    (4041, Syscall::Dup),
+    (4045, Syscall::Brk),
+    (4047, Syscall::GetGid),
+    (4049, Syscall::GetEuid),
+    (4050, Syscall::GetEgid),
+    (4063, Syscall::Dup2),
+    (4064, Syscall::GetPpid),
+    (4078, Syscall::GetTimeOfDay),
+    (4090, Syscall::Mmap),
+    (4091, Syscall::Munmap),
+    (4122, Syscall::Uname),
+    (4125, Syscall::Mprotect),
+    (4145, Syscall::Readv),
+    (4146, Syscall::Writev),
+    (4210, Syscall::Mmap2),
+    (4222, Syscall::GetTid),
+    (4246, Syscall::ExitGroup),
+    (4252, Syscall::SetTidAddress),
+    (4263, Syscall::ClockGettime),
+    (4288, Syscall::OpenAt),
+];
+
+/// How system calls are made for a given architecture: the user-op that
+/// the trapping instruction lifts to, the registers holding the call
+/// number, arguments and result, and the call numbering.
+#[derive(Debug, Clone)]
+pub struct SyscallAbi {
+    intrinsic: &'static str,
+    trap: Option<(u64, u64)>,
+    number: &'static str,
+    arguments: &'static [&'static str],
+    stack_arguments: Option<u64>,
+    result: &'static str,
+    error_flag: Option<&'static str>,
+    machine: &'static str,
+    enosys: u64,
+    o_creat: u64,
+    o_excl: u64,
+    o_trunc: u64,
+    o_append: u64,
+    map_anonymous: u64,
+    syscalls: &'static [(u64, Syscall)],
+}
+
+impl SyscallAbi {
+    pub const X86_64: Self = Self {
+        intrinsic: "syscall",
+        trap: None,
+        number: "RAX",
+        arguments: &["RDI", "RSI", "RDX", "R10", "R8", "R9"],
+        stack_arguments: None,
+        result: "RAX",
+        error_flag: None,
+        machine: "x86_64",
+        enosys: 38,
+        o_creat: 0o100,
+        o_excl: 0o200,
+        o_trunc: 0o1000,
+        o_append: 0o2000,
+        map_anonymous: 0x20,
+        syscalls: X86_64_SYSCALLS,
+    };
+
+    // `int 0x80` lifts to `swi(0x80)` followed by a call through its
+    // result; we resume after the two byte instruction instead.
+    pub const X86: Self = Self {
+        intrinsic: "swi",
+        trap: Some((0x80, 2)),
+        number: "EAX",
+        arguments: &["EBX", "ECX", "EDX", "ESI", "EDI", "EBP"],
+        stack_arguments: None,
+        result: "EAX",
+        error_flag: None,
+        machine: "i686",
+        enosys: 38,
+        o_creat: 0o100,
+        o_excl: 0o200,
+        o_trunc: 0o1000,
+        o_append: 0o2000,
+        map_anonymous: 0x20,
+        syscalls: X86_SYSCALLS,
+    };
+
+    pub const ARM: Self = Self {
+        intrinsic: "software_interrupt",
+        trap: None,
+        number: "r7",
+        arguments: &["r0", "r1", "r2", "r3", "r4", "r5"],
+        stack_arguments: None,
+        result: "r0",
+        error_flag: None,
+        machine: "armv7l",
+        enosys: 38,
+        o_creat: 0o100,
+        o_excl: 0o200,
+        o_trunc: 0o1000,
+        o_append: 0o2000,
+        map_anonymous: 0x20,
+        syscalls: ARM_SYSCALLS,
+    };
+
+    pub const AARCH64: Self = Self {
+        intrinsic: "CallSupervisor",
+        trap: None,
+        number: "x8",
+        arguments: &["x0", "x1", "x2", "x3", "x4", "x5"],
+        stack_arguments: None,
+        result: "x0",
+        error_flag: None,
+        machine: "aarch64",
+        enosys: 38,
+        o_creat: 0o100,
+        o_excl: 0o200,
+        o_trunc: 0o1000,
+        o_append: 0o2000,
+        map_anonymous: 0x20,
+        syscalls: AARCH64_SYSCALLS,
+    };
+
+    // o32: arguments five and six are passed on the stack, and errors are
+    // signalled via `a3` with a positive errno in `v0`.
+    pub const MIPS: Self = Self {
+        intrinsic: "syscall",
+        trap: None,
+        number: "v0",
+        arguments: &["a0", "a1", "a2", "a3"],
+        stack_arguments: Some(16),
+        result: "v0",
+        error_flag: Some("a3"),
+        machine: "mips",
+        enosys: 89,
+        o_creat: 0x100,
+        o_excl: 0x400,
+        o_trunc: 0x200,
+        o_append: 0x8,
+        map_anonymous: 0x800,
+        syscalls: MIPS_SYSCALLS,
+    };
+
+    /// The ABI for a language tag, e.g., `x86:LE:64:default`.
+    pub fn for_language<L: AsRef<str>>(language: L) -> Option<Self> {
+        let mut parts = language.as_ref().split(':');
+        let processor = parts.next()?;
+        let _endian = parts.next()?;
+        let bits = parts.next()?;
+
+        Some(match (processor, bits) {
+            ("x86", "64") => Self::X86_64,
+            ("x86", "32") => Self::X86,
+            ("ARM", "32") => Self::ARM,
+            ("AARCH64", "64") => Self::AARCH64,
+            ("MIPS", "32") => Self::MIPS,
+            _ => return None,
+        })
+    }
+
+    pub fn intrinsic(&self) -> &'static str {
+        self.intrinsic
+    }
+
+    pub fn syscall(&self, number: u64) -> Option<Syscall> {
+        self.syscalls.iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, syscall)| *syscall)
+    }
+
+    pub fn number(&self, syscall: Syscall) -> Option<u64> {
+        self.syscalls.iter()
+            .find(|(_, s)| *s == syscall)
+            .map(|(n, _)| *n)
+    }
+}
+
+/// Linux user-mode system call emulation.
+///
+/// Files are served from the `FileSystem` of the `ConcreteState`, so runs
+/// do not depend on the host; the file system and the program break are
+/// restored along with the rest of the `ConcreteContext`. Time advances
+/// by `clock_step` nanoseconds on each query from `DEFAULT_EPOCH`.
+pub struct Syscalls<O: Order, R> {
+    abi: SyscallAbi,
+    brk_start: Option<u64>,
+    brk: u64,
+    brk_end: u64,
+    mmap_next: Option<u64>,
+    mapped: u64,
+    memory_limit: u64,
+    pid: u64,
+    clock: u64,
+    clock_step: u64,
+    exit_status: Option<i64>,
+    halt_on_unsupported: bool,
+    marker: PhantomData<fn() -> (O, R)>,
+}
+
+impl<O: Order, R> Clone for Syscalls<O, R> {
+    fn clone(&self) -> Self {
+        Self {
+            abi: self.abi.clone(),
+            brk_start: self.brk_start,
+            brk: self.brk,
+            brk_end: self.brk_end,
+            mmap_next: self.mmap_next,
+            mapped: self.mapped,
+            memory_limit: self.memory_limit,
+            pid: self.pid,
+            clock: self.clock,
+            clock_step: self.clock_step,
+            exit_status: self.exit_status,
+            halt_on_unsupported: self.halt_on_unsupported,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<O: Order, R> Syscalls<O, R> {
+    pub fn new(abi: SyscallAbi) -> Self {
+        Self {
+            abi,
+            brk_start: None,
+            brk: 0,
+            brk_end: 0,
+            mmap_next: None,
+            mapped: 0,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            pid: 1000,
+            clock: DEFAULT_EPOCH * 1_000_000_000,
+            clock_step: 1_000_000,
+            exit_status: None,
+            halt_on_unsupported: false,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn for_language<L: AsRef<str>>(language: L) -> Option<Self> {
+        SyscallAbi::for_language(language).map(Self::new)
+    }
+
+    pub fn abi(&self) -> &SyscallAbi {
+        &self.abi
+    }
+
+    /// Start the program break at `address`; by default it is placed at
+    /// `DEFAULT_BRK_32` or `DEFAULT_BRK_64`.
+    pub fn with_brk(mut self, address: u64) -> Self {
+        self.brk_start = Some(page_up(address).unwrap_or(!(PAGE_SIZE - 1)));
+        self
+    }
+
+    pub fn with_mmap_base(mut self, address: u64) -> Self {
+        self.mmap_next = Some(page_up(address).unwrap_or(!(PAGE_SIZE - 1)));
+        self
+    }
+
+    /// Limit the memory mapped by `brk` and `mmap` to `bytes`; requests
+    /// beyond it fail with `-ENOMEM`. Unmapping does not release memory,
+    /// as mappings are never reused.
+    pub fn with_memory_limit(mut self, bytes: u64) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    pub fn with_pid(mut self, pid: u64) -> Self {
+        self.pid = pid;
+        self
+    }
+
+    pub fn with_clock(mut self, seconds: u64, step_nanoseconds: u64) -> Self {
+        self.clock = seconds.saturating_mul(1_000_000_000);
+        self.clock_step = step_nanoseconds;
+        self
+    }
+
+    pub fn halt_on_unsupported(mut self, halt: bool) -> Self {
+        self.halt_on_unsupported = halt;
+        self
+    }
+
+    /// The status passed to `exit` or `exit_group`, if the program has
+    /// exited.
+    pub fn exit_status(&self) -> Option<i64> {
+        self.exit_status
+    }
+}
+
+fn page_up(value: u64) -> Option<u64> {
+    value.checked_add(PAGE_SIZE - 1).map(|value| value & !(PAGE_SIZE - 1))
+}
+
+// The pages `[address, address + length)`, if `address` is page aligned
+// and the range does not wrap
+fn page_range(address: u64, length: u64) -> Result<Range<u64>, u64> {
+    if address % PAGE_SIZE != 0 {
+        return Err(EINVAL)
+    }
+
+    let end = page_up(length)
+        .and_then(|size| address.checked_add(size))
+        .ok_or(ENOMEM)?;
+
+    Ok(address..end)
+}
+
+fn from_bytes<O: Order>(bytes: &[u8]) -> u64 {
+    let mut value = 0u64;
+    if O::ENDIAN.is_big() {
+        for b in bytes.iter().take(8) {
+            value = (value << 8) | *b as u64;
+        }
+    } else {
+        for b in bytes.iter().take(8).rev() {
+            value = (value << 8) | *b as u64;
+        }
+    }
+    value
+}
+
+fn to_bytes<O: Order>(value: u64, size: usize) -> Vec<u8> {
+    let size = size.min(8);
+    if O::ENDIAN.is_big() {
+        value.to_be_bytes()[8 - size..].to_vec()
+    } else {
+        value.to_le_bytes()[..size].to_vec()
+    }
+}
+
+type SyscallResult = Result<Result<u64, u64>, Error<pcode::Error>>;
+
+impl<O: Order, R> Syscalls<O, R> {
+    fn pointer_size(state: &ConcreteState<O>) -> usize {
+        state.memory_space_ref().address_size()
+    }
+
+    fn read_register(&self, state: &ConcreteState<O>, name: &str) -> Result<u64, Error<pcode::Error>> {
+        let register = state.registers()
+            .register_by_name(name)
+            .ok_or_else(|| unknown_register(name))?;
+
+        let mut buf = vec![0u8; register.size()];
+        state.registers()
+            .get_register_values(&register, &mut buf)
+            .map_err(|e| Error::state(pcode::Error::Register(e)))?;
+
+        Ok(from_bytes::<O>(&buf))
+    }
+
+    fn write_register(&self, state: &mut ConcreteState<O>, name: &str, value: u64) -> Result<(), Error<pcode::Error>> {
+        let register = state.registers()
+            .register_by_name(name)
+            .ok_or_else(|| unknown_register(name))?;
+
+        let mut buf = vec![0u8; register.size()];
+        let bytes = to_bytes::<O>(value, buf.len());
+        if O::ENDIAN.is_big() {
+            let offset = buf.len() - bytes.len();
+            buf[offset..].copy_from_slice(&bytes);
+        } else {
+            buf[..bytes.len()].copy_from_slice(&bytes);
+        }
+
+        state.registers_mut()
+            .set_register_values(&register, &buf)
+            .map_err(|e| Error::state(pcode::Error::Register(e)))
+    }
+
+    fn argument(&self, state: &ConcreteState<O>, index: usize) -> Result<u64, Error<pcode::Error>> {
+        if let Some(name) = self.abi.arguments.get(index) {
+            return self.read_register(state, name)
+        }
+
+        let offset = if let Some(offset) = self.abi.stack_arguments {
+            offset
+        } else {
+            return Ok(0)
+        };
+
+        let size = Self::pointer_size(state);
+        let stack_pointer = state.stack_pointer_value().map_err(Error::state)?;
+        let address = u64::from(stack_pointer)
+            .wrapping_add(offset)
+            .wrapping_add(((index - self.abi.arguments.len()) * size) as u64);
+
+        Ok(read_word(state, address, size).unwrap_or(0))
+    }
+
+    fn set_result(&self, state: &mut ConcreteState<O>, result: Result<u64, u64>) -> Result<(), Error<pcode::Error>> {
+        if let Some(flag) = self.abi.error_flag {
+            let (value, failed) = match result {
+                Ok(value) => (value, 0),
+                Err(errno) => (errno, 1),
+            };
+            self.write_register(state, self.abi.result, value)?;
+            self.write_register(state, flag, failed)
+        } else {
+            let value = match result {
+                Ok(value) => value,
+                Err(errno) => errno.wrapping_neg(),
+            };
+            self.write_register(state, self.abi.result, value)
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        let now = self.clock;
+        self.clock = self.clock.wrapping_add(self.clock_step);
+        now
+    }
+
+    // The memory mapped after mapping `size` more bytes, if within the
+    // limit
+    fn reserve(&self, size: u64) -> Result<u64, u64> {
+        self.mapped.checked_add(size)
+            .filter(|mapped| *mapped <= self.memory_limit)
+            .ok_or(ENOMEM)
+    }
+
+    // Map fresh zero-filled pages at `address`; with `fixed`, pages that
+    // are already mapped are reused and zeroed.
+    fn map_pages(&self, state: &mut ConcreteState<O>, name: &str, address: u64, size: u64, fixed: bool) -> Result<(), u64> {
+        let end = address.checked_add(size).ok_or(ENOMEM)?;
+
+        let base = Address::from(address);
+        if state.memory_mut().static_mapping(name, base, size as usize).is_ok() {
+            return Ok(())
+        }
+
+        if !fixed {
+            return Err(ENOMEM)
+        }
+
+        for page in (address..end).step_by(PAGE_SIZE as usize) {
+            let page_address = Address::from(page);
+            if state.memory_mut().static_mapping(name, page_address, PAGE_SIZE as usize).is_err() {
+                let _ = protect(state, page..page + PAGE_SIZE, PROT_READ | PROT_WRITE);
+                state.set_values(page_address, &[0u8; PAGE_SIZE as usize]).map_err(|_| EINVAL)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn brk(&mut self, state: &mut ConcreteState<O>, address: u64) -> Result<u64, u64> {
+        let start = self.brk_start.unwrap_or(0);
+
+        if address < start {
+            return Ok(self.brk)
+        }
+
+        if address > self.brk_end {
+            let end = if let Some(end) = page_up(address) {
+                end
+            } else {
+                return Ok(self.brk)
+            };
+
+            let mapped = match self.reserve(end - self.brk_end) {
+                Ok(mapped) => mapped,
+                Err(_) => return Ok(self.brk),
+            };
+
+            if self.map_pages(state, "[heap]", self.brk_end, end - self.brk_end, false).is_err() {
+                return Ok(self.brk)
+            }
+
+            self.mapped = mapped;
+            self.brk_end = end;
+        }
+
+        self.brk = address;
+        Ok(self.brk)
+    }
+
+    fn mmap(&mut self, state: &mut ConcreteState<O>, args: &[u64], offset: u64) -> Result<u64, u64> {
+        let (address, length, prot, flags, fd) = (args[0], args[1], args[2], args[3], args[4]);
+
+        if length == 0 {
+            return Err(EINVAL)
+        }
+
+        let size = page_up(length).ok_or(ENOMEM)?;
+        let mapped = self.reserve(size)?;
+        let fixed = flags & MAP_FIXED != 0;
+
+        let (base, next) = if fixed {
+            (page_range(address, size)?.start, self.mmap_next)
+        } else {
+            let base = free_range(state, self.mmap_next.unwrap_or(0), size)?;
+            (base, Some(base + size))
+        };
+
+        let contents = if flags & self.abi.map_anonymous == 0 {
+            let files = state.file_system();
+            let descriptor = files.descriptor(fd).ok_or(EBADF)?;
+            if let Descriptor::File { path, .. } = descriptor {
+                let bytes = files.file(path).ok_or(EBADF)?;
+                let start = (offset as usize).min(bytes.len());
+                let end = start.saturating_add(length as usize).min(bytes.len());
+                Some(bytes[start..end].to_vec())
+            } else {
+                return Err(EBADF)
+            }
+        } else {
+            None
+        };
+
+        self.map_pages(state, "[mmap]", base, size, fixed)?;
+
+        self.mapped = mapped;
+        self.mmap_next = next;
+
+        if let Some(contents) = contents {
+            state.set_values(Address::from(base), &contents).map_err(|_| EFAULT)?;
+        }
+
+        let _ = protect(state, base..base + size, prot);
+
+        Ok(base)
+    }
+
+    fn open(&self, state: &mut ConcreteState<O>, path: String, flags: u64) -> Result<u64, u64> {
+        let access = flags & O_ACCMODE;
+        let options = OpenOptions::new()
+            .read(access != O_WRONLY)
+            .write(access == O_WRONLY || access == O_RDWR)
+            .append(flags & self.abi.o_append != 0)
+            .create(flags & self.abi.o_creat != 0)
+            .exclusive(flags & self.abi.o_excl != 0)
+            .truncate(flags & self.abi.o_trunc != 0);
+
+        state.file_system_mut().open(path, options).map_err(|e| e.errno())
+    }
+
+    fn lseek(&self, state: &mut ConcreteState<O>, fd: u64, offset: i64, whence: u64) -> Result<u64, u64> {
+        let whence = match whence {
+            SEEK_SET => Whence::Start,
+            SEEK_CUR => Whence::Current,
+            SEEK_END => Whence::End,
+            _ => return Err(EINVAL),
+        };
+        state.file_system_mut().seek(fd, offset, whence).map_err(|e| e.errno())
+    }
+
+    fn write_words(&self, state: &mut ConcreteState<O>, address: u64, words: &[u64]) -> Result<(), u64> {
+        let size = Self::pointer_size(state);
+        let bytes = words.iter()
+            .flat_map(|word| to_bytes::<O>(*word, size))
+            .collect::<Vec<_>>();
+        state.set_values(Address::from(address), &bytes).map_err(|_| EFAULT)
+    }
+
+    // Read `count` iovecs at `address`, shortening them so that they total
+    // at most `MAX_IO_SIZE` bytes
+    fn iovecs(&self, state: &ConcreteState<O>, address: u64, count: u64) -> Result<Vec<(u64, usize)>, u64> {
+        if count > MAX_IOVECS {
+            return Err(EINVAL)
+        }
+
+        let size = Self::pointer_size(state) as u64;
+        let mut remaining = MAX_IO_SIZE;
+
+        (0..count).map(|i| {
+            let entry = address.checked_add(i * 2 * size).ok_or(EFAULT)?;
+            let base = read_word(state, entry, size as usize).ok_or(EFAULT)?;
+            let length = entry.checked_add(size)
+                .and_then(|entry| read_word(state, entry, size as usize))
+                .ok_or(EFAULT)?;
+
+            let length = (length.min(remaining as u64)) as usize;
+            remaining -= length;
+
+            Ok((base, length))
+        }).collect()
+    }
+
+    fn dispatch(&mut self, state: &mut ConcreteState<O>, syscall: Syscall, args: &[u64]) -> SyscallResult {
+        let size = Self::pointer_size(state);
+
+        Ok(match syscall {
+            Syscall::Read => {
+                let data = match state.file_system_mut().read(args[0], (args[2] as usize).min(MAX_IO_SIZE)) {
+                    Ok(data) => data,
+                    Err(e) => return Ok(Err(e.errno())),
+                };
+                if state.set_values(Address::from(args[1]), &data).is_err() {
+                    // leave the input for a later read
+                    let _ = state.file_system_mut().unread(args[0], data.len());
+                    return Ok(Err(EFAULT))
+                }
+                Ok(data.len() as u64)
+            },
+            Syscall::Write => {
+                match read_bytes(state, args[1], (args[2] as usize).min(MAX_IO_SIZE)) {
+                    Some(data) => state.file_system_mut().write(args[0], &data)
+                        .map(|n| n as u64)
+                        .map_err(|e| e.errno()),
+                    None => Err(EFAULT),
+                }
+            },
+            Syscall::Readv => {
+                let iovecs = match self.iovecs(state, args[1], args[2]) {
+                    Ok(iovecs) => iovecs,
+                    Err(errno) => return Ok(Err(errno)),
+                };
+                let mut total = 0u64;
+                for (base, length) in iovecs {
+                    let data = match state.file_system_mut().read(args[0], length) {
+                        Ok(data) => data,
+                        Err(e) => return Ok(Err(e.errno())),
+                    };
+                    if state.set_values(Address::from(base), &data).is_err() {
+                        // as for `read`, the input not delivered is left;
+                        // that already delivered is reported
+                        let _ = state.file_system_mut().unread(args[0], data.len());
+                        return Ok(if total == 0 { Err(EFAULT) } else { Ok(total) })
+                    }
+                    total += data.len() as u64;
+                    if data.len() < length {
+                        break
+                    }
+                }
+                Ok(total)
+            },
+            Syscall::Writev => {
+                let iovecs = match self.iovecs(state, args[1], args[2]) {
+                    Ok(iovecs) => iovecs,
+                    Err(errno) => return Ok(Err(errno)),
+                };
+                let mut total = 0u64;
+                for (base, length) in iovecs {
+                    let data = match read_bytes(state, base, length) {
+                        Some(data) => data,
+                        None => return Ok(Err(EFAULT)),
+                    };
+                    match state.file_system_mut().write(args[0], &data) {
+                        Ok(n) => total += n as u64,
+                        Err(e) => return Ok(Err(e.errno())),
+                    }
+                }
+                Ok(total)
+            },
+            Syscall::Open => match read_string(state, args[0]) {
+                Some(path) => self.open(state, path, args[1]),
+                None => Err(EFAULT),
+            },
+            Syscall::OpenAt => {
+                let path = match read_string(state, args[1]) {
+                    Some(path) => path,
+                    None => return Ok(Err(EFAULT)),
+                };
+                let dirfd = sign_extend(args[0], size);
+                if dirfd != AT_FDCWD && !path.starts_with('/') {
+                    Err(EBADF)
+                } else {
+                    self.open(state, path, args[2])
+                }
+            },
+            Syscall::Access => match read_string(state, args[0]) {
+                Some(path) if state.file_system().exists(&path) => Ok(0),
+                Some(_) => Err(ENOENT),
+                None => Err(EFAULT),
+            },
+            Syscall::Close => state.file_system_mut().close(args[0]).map(|_| 0).map_err(|e| e.errno()),
+            Syscall::Dup => state.file_system_mut().dup(args[0]).map_err(|e| e.errno()),
+            Syscall::Dup2 => state.file_system_mut().dup2(args[0], args[1]).map_err(|e| e.errno()),
+            Syscall::Lseek => self.lseek(state, args[0], sign_extend(args[1], size), args[2]),
+            Syscall::Brk => self.brk(state, args[0]),
+            Syscall::Mmap => self.mmap(state, args, args[5]),
+            Syscall::Mmap2 => match args[5].checked_mul(PAGE_SIZE) {
+                Some(offset) => self.mmap(state, args, offset),
+                None => Err(EINVAL),
+            },
+            Syscall::Munmap => {
+                // mappings are never reused; revoke access to the pages,
+                // of which some may already be unmapped
+                match page_range(args[0], args[1]) {
+                    Ok(range) if range.is_empty() => Err(EINVAL),
+                    Ok(range) => match protect(state, range, 0) {
+                        Ok(()) | Err(ENOMEM) => Ok(0),
+                        Err(errno) => Err(errno),
+                    },
+                    Err(_) => Err(EINVAL),
+                }
+            },
+            Syscall::Mprotect => {
+                page_range(args[0], args[1])
+                    .and_then(|range| protect(state, range, args[2]))
+                    .map(|_| 0)
+            },
+            Syscall::Exit | Syscall::ExitGroup => {
+                self.exit_status = Some(sign_extend(args[0], 4));
+                Ok(0)
+            },
+            Syscall::GetPid | Syscall::GetTid | Syscall::SetTidAddress => Ok(self.pid),
+            Syscall::GetPpid => Ok(self.pid.saturating_sub(1)),
+            Syscall::GetUid | Syscall::GetEuid | Syscall::GetGid | Syscall::GetEgid => Ok(0),
+            Syscall::ClockGettime => {
+                let now = self.tick();
+                if args[1] == 0 {
+                    Err(EFAULT)
+                } else {
+                    self.write_words(state, args[1], &[now / 1_000_000_000, now % 1_000_000_000])
+                        .map(|_| 0)
+                }
+            },
+            Syscall::GetTimeOfDay => {
+                let now = self.tick();
+                if args[0] == 0 {
+                    Ok(0)
+                } else {
+                    self.write_words(state, args[0], &[now / 1_000_000_000, (now % 1_000_000_000) / 1000])
+                        .map(|_| 0)
+                }
+            },
+            Syscall::Time => {
+                let seconds = self.tick() / 1_000_000_000;
+                if args[0] != 0 {
+                    if let Err(errno) = self.write_words(state, args[0], &[seconds]) {
+                        return Ok(Err(errno))
+                    }
+                }
+                Ok(seconds)
+            },
+            Syscall::Uname => {
+                let fields = ["Linux", "fuguex", "5.10.0", "#1 SMP", self.abi.machine, "(none)"];
+                let mut buf = vec![0u8; 65 * fields.len()];
+                for (i, field) in fields.iter().enumerate() {
+                    buf[i * 65..i * 65 + field.len()].copy_from_slice(field.as_bytes());
+                }
+                state.set_values(Address::from(args[0]), &buf)
+                    .map(|_| 0)
+                    .map_err(|_| EFAULT)
+            },
+        })
+    }
+}
+
+fn unknown_register(name: &str) -> Error<pcode::Error> {
+    Error::Other(anyhow::anyhow!("system call ABI register `{}` not defined by language", name))
+}
+
+fn sign_extend(value: u64, size: usize) -> i64 {
+    if size >= 8 {
+        value as i64
+    } else {
+        let shift = 64 - size * 8;
+        ((value << shift) as i64) >> shift
+    }
+}
+
+fn read_bytes<O: Order>(state: &ConcreteState<O>, address: u64, size: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; size];
+    state.get_values(Address::from(address), &mut buf).ok()?;
+    Some(buf)
+}
+
+fn read_word<O: Order>(state: &ConcreteState<O>, address: u64, size: usize) -> Option<u64> {
+    read_bytes(state, address, size).map(|bytes| from_bytes::<O>(&bytes))
+}
+
+fn read_string<O: Order>(state: &ConcreteState<O>, address: u64) -> Option<String> {
+    let bytes = state.view_values_from(Address::from(address)).ok()?;
+    let end = bytes.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+// The lowest page aligned address from `from` at which `size` bytes are
+// unmapped and within the address space
+fn free_range<O: Order>(state: &ConcreteState<O>, from: u64, size: u64) -> Result<u64, u64> {
+    let bits = state.memory_space_ref().address_size() * 8;
+    let limit = if bits >= 64 { u64::MAX } else { (1 << bits) - 1 };
+
+    let mut base = page_up(from).ok_or(ENOMEM)?;
+    let mut end = base.checked_add(size).ok_or(ENOMEM)?;
+
+    // segments are visited in order of their start address
+    for segment in state.memory().segments().intervals(Address::from(base)..Address::from(u64::MAX)) {
+        if u64::from(segment.start) >= end {
+            break
+        }
+        base = base.max(page_up(u64::from(segment.end)).ok_or(ENOMEM)?);
+        end = base.checked_add(size).ok_or(ENOMEM)?;
+    }
+
+    if end - 1 > limit {
+        Err(ENOMEM)
+    } else {
+        Ok(base)
+    }
+}
+
+// Update page permissions for the pages of `range`; executable pages
+// remain readable so that they can be lifted. Fails with `ENOMEM` if any
+// page is unmapped, or `EINVAL` if the permissions of a mapped page
+// cannot be changed; all mapped pages are updated regardless.
+fn protect<O: Order>(state: &mut ConcreteState<O>, range: Range<u64>, prot: u64) -> Result<(), u64> {
+    if range.is_empty() {
+        return Ok(())
+    }
+
+    // only visit mapped pages, so that the cost is bounded by the memory
+    // mapped, rather than the size of the range
+    let segments = state.memory()
+        .segments()
+        .intervals(Address::from(range.start)..Address::from(range.end))
+        .map(|interval| {
+            u64::from(interval.start).max(range.start)..u64::from(interval.end).min(range.end)
+        })
+        .collect::<Vec<_>>();
+
+    let mut unmapped = false;
+    let mut failed = false;
+    let mut covered = range.start;
+
+    for segment in segments {
+        unmapped |= segment.start > covered;
+        covered = covered.max(segment.end);
+
+        for page in segment.clone().step_by(PAGE_SIZE as usize) {
+            let size = (segment.end - page).min(PAGE_SIZE) as usize;
+            let res = state.memory_mut().with_flat_mut(Address::from(page), size, |flat, address, size| {
+                let permissions = flat.permissions_mut();
+
+                if prot & (PROT_READ | PROT_EXEC) != 0 {
+                    permissions.set_region(&address, size, Access::Read);
+                } else {
+                    permissions.clear_region(&address, size, Access::Read);
+                }
+
+                if prot & PROT_WRITE != 0 {
+                    permissions.set_region(&address, size, Access::Write);
+                } else {
+                    permissions.clear_region(&address, size, Access::Write);
+                }
+
+                Ok(())
+            });
+
+            failed |= res.is_err();
+        }
+    }
+
+    if failed {
+        Err(EINVAL)
+    } else if unmapped || covered < range.end {
+        Err(ENOMEM)
+    } else {
+        Ok(())
+    }
+}
+
+impl<O: Order + 'static, R: Clone + Default + 'static> IntrinsicBehaviour for Syscalls<O, R> {
+    type Outcome = R;
+    type State = ConcreteState<O>;
+
+    fn intrinsic(&self) -> &str {
+        self.abi.intrinsic
+    }
+
+    fn initialise(&mut self, state: &Self::State) -> Result<(), Error<pcode::Error>> {
+        let wide = Self::pointer_size(state) >= 8;
+
+        let brk = *self.brk_start.get_or_insert(if wide { DEFAULT_BRK_64 } else { DEFAULT_BRK_32 });
+        self.brk = brk;
+        self.brk_end = brk;
+
+        self.mmap_next.get_or_insert(if wide { DEFAULT_MMAP_64 } else { DEFAULT_MMAP_32 });
+
+        Ok(())
+    }
+
+    fn handle_intrinsic(
+        &mut self,
+        state: &mut Self::State,
+        inputs: &[Operand],
+        _output: Option<&Operand>,
+    ) -> Result<IntrinsicAction<R>, Error<pcode::Error>> {
+        let fallthrough = if let Some((vector, length)) = self.abi.trap {
+            match inputs.first() {
+                Some(Operand::Constant { value, .. }) if *value == vector => Some(length),
+                _ => return Ok(IntrinsicAction::Pass),
+            }
+        } else {
+            None
+        };
+
+        let number = self.read_register(state, self.abi.number)?;
+        let args = (0..6)
+            .map(|i| self.argument(state, i))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let syscall = self.abi.syscall(number);
+
+        let result = if let Some(syscall) = syscall {
+            log::trace!("syscall {:?}{:x?}", syscall, args);
+            self.dispatch(state, syscall, &args)?
+        } else if self.halt_on_unsupported {
+            log::warn!("unsupported syscall {}", number);
+            return Ok(IntrinsicAction::Halt(R::default()))
+        } else {
+            log::warn!("unsupported syscall {}; returning ENOSYS", number);
+            Err(self.abi.enosys)
+        };
+
+        if matches!(syscall, Some(Syscall::Exit) | Some(Syscall::ExitGroup)) {
+            return Ok(IntrinsicAction::Halt(R::default()))
+        }
+
+        self.set_result(state, result)?;
+
+        if let Some(length) = fallthrough {
+            let program_counter = state.program_counter_value().map_err(Error::state)?;
+            Ok(IntrinsicAction::Branch(AddressValue::new(
+                state.memory_space(),
+                u64::from(program_counter) + length,
+            )))
+        } else {
+            Ok(IntrinsicAction::Pass)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fugue::bytes::LE;
+
+    use crate::testing;
+
+    const STACK: u64 = 0x8000;
+    const UNMAPPED: u64 = 0xdead_0000;
+    const MAP_ANONYMOUS: u64 = 0x20;
+    const MAP_PRIVATE: u64 = 0x2;
+
+    const ABIS: &[SyscallAbi] = &[
+        SyscallAbi::X86_64,
+        SyscallAbi::X86,
+        SyscallAbi::ARM,
+        SyscallAbi::AARCH64,
+        SyscallAbi::MIPS,
+    ];
+
+    fn context() -> testing::Context<LE> {
+        testing::context("x86:LE:64:default", 0x1000, &[], STACK)
+    }
+
+    fn syscalls(state: &ConcreteState<LE>) -> Syscalls<LE, ()> {
+        let mut syscalls = Syscalls::for_language("x86:LE:64:default").unwrap();
+        syscalls.initialise(state).unwrap();
+        syscalls
+    }
+
+    fn call(syscalls: &mut Syscalls<LE, ()>, state: &mut ConcreteState<LE>, syscall: Syscall, args: &[u64]) -> Result<u64, u64> {
+        let mut args = args.to_vec();
+        args.resize(6, 0);
+        syscalls.dispatch(state, syscall, &args).unwrap()
+    }
+
+    fn bytes(state: &ConcreteState<LE>, address: u64, size: usize) -> Vec<u8> {
+        read_bytes(state, address, size).unwrap()
+    }
+
+    #[test]
+    fn numbers_are_unique() {
+        for abi in ABIS {
+            for (i, (number, syscall)) in abi.syscalls.iter().enumerate() {
+                assert!(abi.syscalls[..i].iter().all(|(n, _)| n != number),
+                        "{}: {} is assigned twice", abi.machine, number);
+                assert_eq!(abi.syscall(*number), Some(*syscall));
+                assert_eq!(abi.number(*syscall).and_then(|n| abi.syscall(n)), Some(*syscall));
+            }
+        }
+    }
+
+    #[test]
+    fn known_numbers() {
+        let known: &[(SyscallAbi, &[(u64, Syscall)])] = &[
+            (SyscallAbi::X86_64, &[(0, Syscall::Read), (1, Syscall::Write), (9, Syscall::Mmap), (12, Syscall::Brk), (231, Syscall::ExitGroup), (257, Syscall::OpenAt)]),
+            (SyscallAbi::X86, &[(3, Syscall::Read), (45, Syscall::Brk), (192, Syscall::Mmap2), (252, Syscall::ExitGroup), (295, Syscall::OpenAt)]),
+            (SyscallAbi::ARM, &[(3, Syscall::Read), (45, Syscall::Brk), (192, Syscall::Mmap2), (248, Syscall::ExitGroup), (322, Syscall::OpenAt)]),
+            (SyscallAbi::AARCH64, &[(56, Syscall::OpenAt), (63, Syscall::Read), (214, Syscall::Brk), (222, Syscall::Mmap), (94, Syscall::ExitGroup)]),
+            (SyscallAbi::MIPS, &[(4003, Syscall::Read), (4045, Syscall::Brk), (4090, Syscall::Mmap), (4210, Syscall::Mmap2), (4246, Syscall::ExitGroup)]),
+        ];
+
+        for (abi, numbers) in known {
+            for (number, syscall) in numbers.iter() {
+                assert_eq!(abi.syscall(*number), Some(*syscall), "{}: {}", abi.machine, number);
+            }
+        }
+
+        // not available on 64-bit x86 or AArch64
+        assert_eq!(SyscallAbi::X86_64.number(Syscall::Mmap2), None);
+        assert_eq!(SyscallAbi::AARCH64.number(Syscall::Open), None);
+        assert_eq!(SyscallAbi::X86_64.syscall(1 << 20), None);
+    }
+
+    #[test]
+    fn abi_for_language() {
+        let machine = |language| SyscallAbi::for_language(language).map(|abi| abi.machine);
+
+        assert_eq!(machine("x86:LE:64:default"), Some("x86_64"));
+        assert_eq!(machine("x86:LE:32:default"), Some("i686"));
+        assert_eq!(machine("ARM:LE:32:v7"), Some("armv7l"));
+        assert_eq!(machine("AARCH64:LE:64:v8A"), Some("aarch64"));
+        assert_eq!(machine("MIPS:BE:32:default"), Some("mips"));
+        assert_eq!(machine("MIPS:BE:64:default"), None);
+        assert_eq!(machine("x86"), None);
+    }
+
+    #[test]
+    fn read_write() {
+        let mut context = context();
+        let state = context.state_mut();
+        let mut syscalls = syscalls(state);
+
+        state.file_system_mut().set_stdin(b"hello world".to_vec());
+
+        assert_eq!(call(&mut syscalls, state, Syscall::Read, &[0, STACK, 5]), Ok(5));
+        assert_eq!(bytes(state, STACK, 5), b"hello");
+
+        assert_eq!(call(&mut syscalls, state, Syscall::Write, &[1, STACK, 5]), Ok(5));
+        assert_eq!(state.file_system().stdout(), b"hello");
+
+        // short read at the end of the input
+        assert_eq!(call(&mut syscalls, state, Syscall::Read, &[0, STACK, 64]), Ok(6));
+        assert_eq!(call(&mut syscalls, state, Syscall::Read, &[0, STACK, 64]), Ok(0));
+    }
+
+    #[test]
+    fn read_fault_keeps_input() {
+        let mut context = context();
+        let state = context.state_mut();
+        let mut syscalls = syscalls(state);
+
+        state.file_system_mut().set_stdin(b"hello world".to_vec());
+
+        assert_eq!(call(&mut syscalls, state, Syscall::Read, &[0, UNMAPPED, 5]), Err(EFAULT));
+        assert_eq!(call(&mut syscalls, state, Syscall::Read, &[0, STACK, 5]), Ok(5));
+        assert_eq!(bytes(state, STACK, 5), b"hello");
+
+        // the first iovec is delivered, the second faults
+        let iovecs = STACK + 0x100;
+        syscalls.write_words(state, iovecs, &[STACK, 3, UNMAPPED, 3]).unwrap();
+
+        assert_eq!(call(&mut syscalls, state, Syscall::Readv, &[0, iovecs, 2]), Ok(3));
+        assert_eq!(bytes(state, STACK, 3), b" wo");
+        assert_eq!(call(&mut syscalls, state, Syscall::Read, &[0, STACK, 64]), Ok(3));
+        assert_eq!(bytes(state, STACK, 3), b"rld");
+    }
+
+    #[test]
+    fn errno() {
+        let mut context = context();
+        let state = context.state_mut();
+        let mut syscalls = syscalls(state);
+
+        assert_eq!(call(&mut syscalls, state, Syscall::Read, &[42, STACK, 1]), Err(EBADF));
+        assert_eq!(call(&mut syscalls, state, Syscall::Write, &[0, STACK, 1]), Err(EBADF));
+        assert_eq!(call(&mut syscalls, state, Syscall::Write, &[1, UNMAPPED, 1]), Err(EFAULT));
+        assert_eq!(call(&mut syscalls, state, Syscall::Readv, &[0, STACK, MAX_IOVECS + 1]), Err(EINVAL));
+        assert_eq!(call(&mut syscalls, state, Syscall::Readv, &[0, UNMAPPED, 1]), Err(EFAULT));
+        assert_eq!(call(&mut syscalls, state, Syscall::Lseek, &[0, 0, SEEK_SET]), Err(29));
+        assert_eq!(call(&mut syscalls, state, Syscall::Close, &[42]), Err(EBADF));
+        assert_eq!(call(&mut syscalls, state, Syscall::Open, &[UNMAPPED, 0]), Err(EFAULT));
+        assert_eq!(call(&mut syscalls, state, Syscall::Munmap, &[STACK + 1, PAGE_SIZE]), Err(EINVAL));
+        assert_eq!(call(&mut syscalls, state, Syscall::ClockGettime, &[0, 0]), Err(EFAULT));
+
+        state.set_values(Address::from(STACK), b"/missing\0").unwrap();
+        assert_eq!(call(&mut syscalls, state, Syscall::Open, &[STACK, 0]), Err(ENOENT));
+        assert_eq!(call(&mut syscalls, state, Syscall::Access, &[STACK]), Err(ENOENT));
+
+        state.file_system_mut().add_file("/file", b"contents".to_vec());
+        state.set_values(Address::from(STACK), b"/file\0").unwrap();
+        let fd = call(&mut syscalls, state, Syscall::Open, &[STACK, 0]).unwrap();
+        assert_eq!(call(&mut syscalls, state, Syscall::Lseek, &[fd, 0, 3]), Err(EINVAL));
+        assert_eq!(call(&mut syscalls, state, Syscall::Write, &[fd, STACK, 1]), Err(EBADF));
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut context = context();
+        let state = context.state_mut();
+        let mut syscalls = syscalls(state);
+
+        syscalls.write_register(state, "RAX", 1 << 20).unwrap();
+        assert!(matches!(syscalls.handle_intrinsic(state, &[], None), Ok(IntrinsicAction::Pass)));
+        assert_eq!(syscalls.read_register(state, "RAX").unwrap(), 38u64.wrapping_neg());
+
+        let mut syscalls = syscalls.halt_on_unsupported(true);
+        assert!(matches!(syscalls.handle_intrinsic(state, &[], None), Ok(IntrinsicAction::Halt(()))));
+    }
+
+    #[test]
+    fn brk() {
+        let mut context = context();
+        let state = context.state_mut();
+        let mut syscalls = syscalls(state).with_memory_limit(0x2000);
+        syscalls.initialise(state).unwrap();
+
+        let start = call(&mut syscalls, state, Syscall::Brk, &[0]).unwrap();
+        assert_eq!(start, DEFAULT_BRK_64);
+
+        assert_eq!(call(&mut syscalls, state, Syscall::Brk, &[start + 0x1800]), Ok(start + 0x1800));
+        state.set_values(Address::from(start + 0x17ff), &[0xff]).unwrap();
+        assert_eq!(bytes(state, start + 0x1fff, 1), [0]);
+
+        // below the start, or beyond the memory limit, the break is not
+        // moved
+        assert_eq!(call(&mut syscalls, state, Syscall::Brk, &[start - 1]), Ok(start + 0x1800));
+        assert_eq!(call(&mut syscalls, state, Syscall::Brk, &[start + 0x2001]), Ok(start + 0x1800));
+
+        // shrinking keeps the pages mapped, so they can be regrown
+        assert_eq!(call(&mut syscalls, state, Syscall::Brk, &[start]), Ok(start));
+        assert_eq!(call(&mut syscalls, state, Syscall::Brk, &[start + 0x2000]), Ok(start + 0x2000));
+    }
+
+    #[test]
+    fn mmap() {
+        let mut context = context();
+        let state = context.state_mut();
+        let mut syscalls = syscalls(state);
+
+        let anonymous = MAP_PRIVATE | MAP_ANONYMOUS;
+        let rw = PROT_READ | PROT_WRITE;
+
+        let first = call(&mut syscalls, state, Syscall::Mmap, &[0, 0x1800, rw, anonymous, u64::MAX]).unwrap();
+        assert_eq!(first, DEFAULT_MMAP_64);
+
+        let second = call(&mut syscalls, state, Syscall::Mmap, &[0, 0x1000, rw, anonymous, u64::MAX]).unwrap();
+        assert_eq!(second, first + 0x2000);
+
+        state.set_values(Address::from(second), &[1, 2, 3]).unwrap();
+        assert_eq!(bytes(state, second, 3), [1, 2, 3]);
+
+        // fixed mappings replace the contents of mapped pages
+        assert_eq!(call(&mut syscalls, state, Syscall::Mmap, &[second, 0x1000, rw, anonymous | MAP_FIXED, u64::MAX]), Ok(second));
+        assert_eq!(bytes(state, second, 3), [0, 0, 0]);
+
+        // file mappings are initialised from the file
+        state.file_system_mut().add_file("/file", b"contents".to_vec());
+        let fd = state.file_system_mut().open("/file", OpenOptions::new().read(true)).unwrap();
+        let file = call(&mut syscalls, state, Syscall::Mmap, &[0, 8, PROT_READ, MAP_PRIVATE, fd, 4]).unwrap();
+        assert_eq!(bytes(state, file, 5), b"ents\0");
+
+        // without write access, the pages cannot be written
+        assert!(state.set_values(Address::from(file), &[0]).is_err());
+
+        assert_eq!(call(&mut syscalls, state, Syscall::Mmap, &[0, 0, rw, anonymous, u64::MAX]), Err(EINVAL));
+        assert_eq!(call(&mut syscalls, state, Syscall::Mmap, &[0x1001, 0x1000, rw, anonymous | MAP_FIXED, u64::MAX]), Err(EINVAL));
+        assert_eq!(call(&mut syscalls, state, Syscall::Mmap, &[0, 0x1000, rw, MAP_PRIVATE, 42]), Err(EBADF));
+        assert_eq!(call(&mut syscalls, state, Syscall::Mmap, &[0, 0x1000, rw, MAP_PRIVATE, 1]), Err(EBADF));
+        assert_eq!(call(&mut syscalls, state, Syscall::Mmap, &[0, u64::MAX, rw, anonymous, u64::MAX]), Err(ENOMEM));
+        assert_eq!(call(&mut syscalls, state, Syscall::Mmap, &[0, DEFAULT_MEMORY_LIMIT, rw, anonymous, u64::MAX]), Err(ENOMEM));
+    }
+
+    #[test]
+    fn mmap_skips_mapped() {
+        let mut context = context();
+        let state = context.state_mut();
+        let mut syscalls = Syscalls::<LE, ()>::for_language("x86:LE:64:default")
+            .unwrap()
+            .with_mmap_base(STACK - 0x1000);
+        syscalls.initialise(state).unwrap();
+
+        let anonymous = MAP_PRIVATE | MAP_ANONYMOUS;
+        let rw = PROT_READ | PROT_WRITE;
+
+        // the stack page at `STACK` is skipped rather than failing
+        let address = call(&mut syscalls, state, Syscall::Mmap, &[0, 0x2000, rw, anonymous, u64::MAX]).unwrap();
+        assert_eq!(address, STACK + 0x1000);
+
+        let address = call(&mut syscalls, state, Syscall::Mmap, &[0, 0x1000, rw, anonymous, u64::MAX]).unwrap();
+        assert_eq!(address, STACK + 0x3000);
+    }
+}
@@ -0,0 +1,101 @@
+// Fixtures for tests that lift and execute code; these need the SLEIGH
+// processor specifications, found in `FUGUE_PROCESSORS` or, by default,
+// `data/processors` in the workspace.
+
+use std::path::PathBuf;
+
+use fugue::bytes::Order;
+use fugue::ir::{Address, AddressValue, LanguageDB};
+
+use fuguex_loader::{endian_for, translator_for, MappedImage, Protection, Region};
+use fuguex_machine::{Bound, Machine, StepOutcome};
+use fuguex_state::pcode::PCodeState;
+
+use crate::ConcreteContext;
+
+pub(crate) type Context<O> = ConcreteContext<O, (), 64>;
+
+pub(crate) fn processors() -> PathBuf {
+    std::env::var_os("FUGUE_PROCESSORS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/processors")))
+}
+
+pub(crate) fn language_db() -> LanguageDB {
+    let path = processors();
+    LanguageDB::from_directory_with(&path, true)
+        .unwrap_or_else(|e| panic!("processor specifications at {}: {}", path.display(), e))
+}
+
+// The convention used by `context`: gcc's for x86, which has several
+pub(crate) fn default_convention(language: &str) -> &'static str {
+    if language.starts_with("x86:") { "gcc" } else { "default" }
+}
+
+// Map each of `regions` read-write-execute at its address
+pub(crate) fn image<O: Order>(language: &str, convention: &str, regions: Vec<(u64, Vec<u8>)>) -> MappedImage<PCodeState<u8, O>> {
+    let translator = translator_for(&language_db(), language)
+        .unwrap_or_else(|e| panic!("language {}: {}", language, e));
+
+    let convention = translator.compiler_conventions()
+        .get(convention)
+        .cloned()
+        .unwrap_or_else(|| panic!("language {} has no convention {}", language, convention));
+
+    let endian = endian_for(language).expect("endian");
+    let base = regions.iter().map(|(address, _)| *address).min().unwrap_or(0);
+    let regions = regions.into_iter()
+        .enumerate()
+        .map(|(i, (address, bytes))| {
+            let region = Region::new(format!("region{}", i), address, bytes.len(), Protection::READ_WRITE_EXECUTE);
+            (region, bytes)
+        });
+
+    MappedImage::from_regions(translator, endian, base, None, regions)
+        .expect("regions")
+        .pcode_state(&convention)
+}
+
+// A context with `code` mapped at `address`, followed by a page of zeros,
+// and a stack page at `stack`
+pub(crate) fn context<O: Order>(language: &str, address: u64, code: &[u8], stack: u64) -> Context<O> {
+    context_with(language, default_convention(language), address, code, stack)
+}
+
+pub(crate) fn context_with<O: Order>(language: &str, convention: &str, address: u64, code: &[u8], stack: u64) -> Context<O> {
+    let mut bytes = code.to_vec();
+    bytes.resize(code.len() + 0x1000, 0);
+
+    let mut context = Context::from_loader(image::<O>(language, convention, vec![
+        (address, bytes),
+        (stack, vec![0u8; 0x1000]),
+    ]));
+
+    let space = context.state().memory_space();
+    let top = stack + 0x1000 - 2 * space.address_size() as u64;
+    context.state_mut()
+        .set_stack_pointer_value(Address::new(&*space, top))
+        .expect("stack pointer");
+
+    context
+}
+
+pub(crate) fn machine<O: Order>(context: Context<O>) -> Machine<Context<O>> {
+    Machine::new(context)
+}
+
+// x86 `call rel32` from `from` to `to`
+pub(crate) fn call_rel32(from: u64, to: u64) -> Vec<u8> {
+    let mut bytes = vec![0xe8];
+    bytes.extend_from_slice(&(to.wrapping_sub(from + 5) as u32).to_le_bytes());
+    bytes
+}
+
+// Step from `from` until `until` is reached, or execution halts
+pub(crate) fn run<O: Order>(machine: &mut Machine<Context<O>>, from: u64, until: u64) -> StepOutcome<()> {
+    let space = machine.interpreter().state().memory_space();
+    let (_, outcome) = machine
+        .step_until(AddressValue::new(space.clone(), from), Bound::address(AddressValue::new(space, until)))
+        .expect("step");
+    outcome
+}
@@ -1,5 +1,5 @@
 use fugue::bytes::Order;
-use fugue::ir::il::pcode::Operand;
+use fugue::ir::il::pcode::{Operand, PCodeOp};
 use fugue::ir::Address;
 use fuguex_hooks::types::{Error, HookCBranchAction, HookOutcome, HookStepAction};
 use fuguex_machine::StepState;
@@ -8,6 +8,9 @@ use fuguex_state::{AsState, StateOps};
 
 use crate::hooks::{ClonableHookConcrete, HookConcrete};
 
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
 use std::marker::PhantomData;
 
 pub struct BranchTracker<S, O, R> {
@@ -36,6 +39,17 @@ impl<S, O, R> Clone for BranchTracker<S, O, R> {
     }
 }
 
+impl<S, O, R> BranchTracker<S, O, R> {
+    /// The conditional branches executed, as `(address, taken, not_taken)`.
+    pub fn tracked(&self) -> &[(Address, Address, Address)] {
+        &self.tracked
+    }
+
+    pub fn clear(&mut self) {
+        self.tracked.clear();
+    }
+}
+
 impl<S, O, R> HookConcrete for BranchTracker<S, O, R>
 where
     S: AsState<PCodeState<u8, O>> + StateOps<Value = u8> + 'static,
@@ -80,3 +94,457 @@ where
     R: 'static,
 {
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// Sequential execution into a new block, e.g., after a skipped call.
+    Fallthrough,
+    Jump,
+    Conditional,
+    Indirect,
+    Call,
+    Return,
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fallthrough => "fallthrough",
+            Self::Jump => "jump",
+            Self::Conditional => "conditional",
+            Self::Indirect => "indirect",
+            Self::Call => "call",
+            Self::Return => "return",
+        }
+    }
+
+    // The kind of control transfer performed by an instruction, if any;
+    // branches to constants are within the instruction's p-code.
+    fn of_operations(operations: &[PCodeOp]) -> Option<Self> {
+        operations.iter().fold(None, |kind, operation| {
+            let next = match operation {
+                PCodeOp::Branch { destination: Operand::Address { .. } } => Self::Jump,
+                PCodeOp::CBranch { destination: Operand::Address { .. }, .. } => Self::Conditional,
+                PCodeOp::IBranch { .. } => Self::Indirect,
+                PCodeOp::Call { .. } | PCodeOp::ICall { .. } => Self::Call,
+                PCodeOp::Return { .. } => Self::Return,
+                _ => return kind,
+            };
+            Some(kind.map_or(next, |kind| kind.max(next)))
+        })
+    }
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BlockInfo {
+    last: Address,
+    hits: u64,
+}
+
+impl BlockInfo {
+    /// The address of the last instruction executed in the block.
+    pub fn last(&self) -> Address {
+        self.last
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct EdgeInfo {
+    kind: EdgeKind,
+    hits: u64,
+}
+
+impl EdgeInfo {
+    pub fn kind(&self) -> EdgeKind {
+        self.kind
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
+
+/// A control-flow graph of executed blocks and the edges between them.
+///
+/// Blocks are as executed: they start at the target of a control transfer
+/// and end at the next instruction that transfers control, so a block may
+/// overlap the tail of another.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlFlowGraph {
+    blocks: BTreeMap<Address, BlockInfo>,
+    edges: BTreeMap<(Address, Address), EdgeInfo>,
+}
+
+impl ControlFlowGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (&Address, &BlockInfo)> {
+        self.blocks.iter()
+    }
+
+    pub fn block(&self, address: &Address) -> Option<&BlockInfo> {
+        self.blocks.get(address)
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = (&Address, &Address, &EdgeInfo)> {
+        self.edges.iter().map(|((from, to), edge)| (from, to, edge))
+    }
+
+    pub fn edge(&self, from: &Address, to: &Address) -> Option<&EdgeInfo> {
+        self.edges.get(&(*from, *to))
+    }
+
+    pub fn successors<'a>(&'a self, address: &'a Address) -> impl Iterator<Item = (&'a Address, &'a EdgeInfo)> + 'a {
+        self.edges
+            .range((*address, Address::from(0u64))..)
+            .take_while(move |((from, _), _)| from == address)
+            .map(|((_, to), edge)| (to, edge))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.edges.clear();
+    }
+
+    fn enter(&mut self, block: Address) {
+        self.blocks
+            .entry(block)
+            .or_insert(BlockInfo { last: block, hits: 0 })
+            .hits += 1;
+    }
+
+    fn leave(&mut self, block: Address, last: Address) {
+        if let Some(info) = self.blocks.get_mut(&block) {
+            info.last = last;
+        }
+    }
+
+    fn add_edge(&mut self, from: Address, to: Address, kind: EdgeKind, hits: u64) {
+        let edge = self.edges
+            .entry((from, to))
+            .or_insert(EdgeInfo { kind, hits: 0 });
+        edge.kind = edge.kind.max(kind);
+        edge.hits += hits;
+    }
+
+    /// Add the blocks, edges and hit counts of `other`, e.g., a graph
+    /// recorded on another path.
+    pub fn merge(&mut self, other: &Self) {
+        for (address, block) in other.blocks.iter() {
+            let info = self.blocks
+                .entry(*address)
+                .or_insert(BlockInfo { last: block.last, hits: 0 });
+            info.last = info.last.max(block.last);
+            info.hits += block.hits;
+        }
+
+        for ((from, to), edge) in other.edges.iter() {
+            self.add_edge(*from, *to, edge.kind, edge.hits);
+        }
+    }
+
+    /// Render the graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box];\n");
+
+        for (address, block) in self.blocks.iter() {
+            let _ = writeln!(
+                dot,
+                "    \"{:#x}\" [label=\"{:#x}..{:#x}\\nhits: {}\"];",
+                u64::from(*address),
+                u64::from(*address),
+                u64::from(block.last),
+                block.hits,
+            );
+        }
+
+        for ((from, to), edge) in self.edges.iter() {
+            let _ = writeln!(
+                dot,
+                "    \"{:#x}\" -> \"{:#x}\" [label=\"{} ({})\"];",
+                u64::from(*from),
+                u64::from(*to),
+                edge.kind,
+                edge.hits,
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the graph as JSON, with addresses as hexadecimal strings.
+    pub fn to_json(&self) -> String {
+        let blocks = self.blocks.iter().map(|(address, block)| {
+            format!(
+                "{{\"address\":\"{:#x}\",\"last\":\"{:#x}\",\"hits\":{}}}",
+                u64::from(*address),
+                u64::from(block.last),
+                block.hits,
+            )
+        }).collect::<Vec<_>>();
+
+        let edges = self.edges.iter().map(|((from, to), edge)| {
+            format!(
+                "{{\"from\":\"{:#x}\",\"to\":\"{:#x}\",\"kind\":\"{}\",\"hits\":{}}}",
+                u64::from(*from),
+                u64::from(*to),
+                edge.kind,
+                edge.hits,
+            )
+        }).collect::<Vec<_>>();
+
+        format!("{{\"blocks\":[{}],\"edges\":[{}]}}", blocks.join(","), edges.join(","))
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PendingStep {
+    block: Address,
+    address: Address,
+    fallthrough: Address,
+    delay_slot: Option<Address>,
+    kind: Option<EdgeKind>,
+}
+
+/// Records a `ControlFlowGraph` of the executed blocks and edges.
+///
+/// The graph is kept when the context is restored, so it accumulates
+/// over all paths explored from a fork; the position within the current
+/// path is restored.
+pub struct ControlFlowRecorder<S, O, R> {
+    graph: ControlFlowGraph,
+    pending: Option<PendingStep>,
+    marker: PhantomData<(S, O, R)>,
+}
+
+impl<S, O, R> Default for ControlFlowRecorder<S, O, R> {
+    fn default() -> Self {
+        Self {
+            graph: ControlFlowGraph::default(),
+            pending: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S, O, R> Clone for ControlFlowRecorder<S, O, R> {
+    fn clone(&self) -> Self {
+        Self {
+            graph: self.graph.clone(),
+            pending: self.pending,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<S, O, R> ControlFlowRecorder<S, O, R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn graph(&self) -> &ControlFlowGraph {
+        &self.graph
+    }
+
+    pub fn into_graph(self) -> ControlFlowGraph {
+        self.graph
+    }
+
+    pub fn clear(&mut self) {
+        self.graph.clear();
+        self.pending = None;
+    }
+
+    /// Merge the graph recorded by `other`, e.g., in a separately forked
+    /// context.
+    pub fn merge(&mut self, other: &Self) {
+        self.graph.merge(&other.graph);
+    }
+}
+
+impl<S, O, R> HookConcrete for ControlFlowRecorder<S, O, R>
+where
+    S: AsState<PCodeState<u8, O>> + StateOps<Value = u8> + 'static,
+    O: Order + 'static,
+    R: 'static,
+{
+    type State = S;
+    type Error = PCodeError;
+    type Outcome = R;
+
+    fn hook_architectural_step(
+        &mut self,
+        _state: &mut Self::State,
+        address: &Address,
+        operation: &StepState,
+    ) -> Result<HookOutcome<HookStepAction<Self::Outcome>>, Error<Self::Error>> {
+        let address = *address;
+
+        // delay slots execute as part of the preceding instruction
+        if matches!(self.pending, Some(ref previous) if previous.delay_slot == Some(address)) {
+            return Ok(HookStepAction::Pass.into())
+        }
+
+        let block = match self.pending.take() {
+            Some(previous) if previous.kind.is_none() && previous.fallthrough == address => {
+                previous.block
+            }
+            Some(previous) => {
+                let kind = match previous.kind {
+                    Some(EdgeKind::Call) if previous.fallthrough == address => EdgeKind::Fallthrough,
+                    Some(kind) => kind,
+                    None => EdgeKind::Indirect,
+                };
+
+                self.graph.leave(previous.block, previous.address);
+                self.graph.add_edge(previous.block, address, kind, 1);
+                self.graph.enter(address);
+
+                address
+            }
+            None => {
+                self.graph.enter(address);
+                address
+            }
+        };
+
+        self.pending = Some(PendingStep {
+            block,
+            address,
+            fallthrough: operation.fallthrough().into(),
+            delay_slot: operation.delay_slot().map(Address::from),
+            kind: EdgeKind::of_operations(operation.operations().operations()),
+        });
+
+        Ok(HookStepAction::Pass.into())
+    }
+
+    fn hook_restore(&mut self, other: &dyn Any) -> bool {
+        if let Some(other) = other.downcast_ref::<Self>() {
+            if let Some(pending) = self.pending {
+                self.graph.leave(pending.block, pending.address);
+            }
+            self.pending = other.pending;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<S, O, R> ClonableHookConcrete for ControlFlowRecorder<S, O, R>
+where
+    S: AsState<PCodeState<u8, O>> + StateOps<Value = u8> + 'static,
+    O: Order + 'static,
+    R: 'static,
+{
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(value: u64) -> Address {
+        Address::from(value)
+    }
+
+    // 0x1000 -> 0x2000 -> 0x1000, with 0x1000 ending at 0x1008
+    fn graph() -> ControlFlowGraph {
+        let mut graph = ControlFlowGraph::new();
+
+        graph.enter(address(0x1000));
+        graph.leave(address(0x1000), address(0x1008));
+        graph.add_edge(address(0x1000), address(0x2000), EdgeKind::Call, 1);
+
+        graph.enter(address(0x2000));
+        graph.leave(address(0x2000), address(0x2004));
+        graph.add_edge(address(0x2000), address(0x100c), EdgeKind::Return, 1);
+
+        graph.enter(address(0x100c));
+
+        graph
+    }
+
+    #[test]
+    fn blocks_and_edges() {
+        let graph = graph();
+
+        assert_eq!(graph.blocks().count(), 3);
+        assert_eq!(graph.block(&address(0x1000)).map(BlockInfo::last), Some(address(0x1008)));
+        assert_eq!(graph.block(&address(0x100c)).map(BlockInfo::last), Some(address(0x100c)));
+        assert_eq!(graph.edge(&address(0x1000), &address(0x2000)).map(EdgeInfo::kind), Some(EdgeKind::Call));
+        assert!(graph.edge(&address(0x2000), &address(0x1000)).is_none());
+
+        let successors = graph.successors(&address(0x2000)).map(|(to, _)| *to).collect::<Vec<_>>();
+        assert_eq!(successors, vec![address(0x100c)]);
+    }
+
+    #[test]
+    fn hits_accumulate() {
+        let mut graph = graph();
+
+        graph.enter(address(0x1000));
+        graph.add_edge(address(0x1000), address(0x2000), EdgeKind::Jump, 1);
+
+        assert_eq!(graph.block(&address(0x1000)).map(BlockInfo::hits), Some(2));
+
+        // the most specific kind is kept
+        let edge = graph.edge(&address(0x1000), &address(0x2000)).copied();
+        assert_eq!(edge.map(|e| e.kind()), Some(EdgeKind::Call));
+        assert_eq!(edge.map(|e| e.hits()), Some(2));
+    }
+
+    #[test]
+    fn merge() {
+        let mut graph = graph();
+
+        let mut other = ControlFlowGraph::new();
+        other.enter(address(0x1000));
+        other.leave(address(0x1000), address(0x1010));
+        other.add_edge(address(0x1000), address(0x3000), EdgeKind::Conditional, 2);
+
+        graph.merge(&other);
+
+        let block = graph.block(&address(0x1000)).copied();
+        assert_eq!(block.map(|b| b.hits()), Some(2));
+        assert_eq!(block.map(|b| b.last()), Some(address(0x1010)));
+        assert_eq!(graph.edge(&address(0x1000), &address(0x3000)).map(EdgeInfo::hits), Some(2));
+        assert_eq!(graph.successors(&address(0x1000)).count(), 2);
+    }
+
+    #[test]
+    fn render() {
+        let mut graph = ControlFlowGraph::new();
+        graph.enter(address(0x10));
+        graph.add_edge(address(0x10), address(0x20), EdgeKind::Jump, 1);
+
+        assert_eq!(
+            graph.to_dot(),
+            "digraph cfg {\n    node [shape=box];\n    \"0x10\" [label=\"0x10..0x10\\nhits: 1\"];\n    \"0x10\" -> \"0x20\" [label=\"jump (1)\"];\n}\n",
+        );
+
+        assert_eq!(
+            graph.to_json(),
+            "{\"blocks\":[{\"address\":\"0x10\",\"last\":\"0x10\",\"hits\":1}],\"edges\":[{\"from\":\"0x10\",\"to\":\"0x20\",\"kind\":\"jump\",\"hits\":1}]}",
+        );
+
+        assert_eq!(ControlFlowGraph::new().to_json(), "{\"blocks\":[],\"edges\":[]}");
+    }
+}
@@ -1,4 +1,6 @@
+use fugue::ir::il::pcode::PCode;
 use fugue::ir::il::Location;
+use fugue::ir::Address;
 use thiserror::Error;
 
 pub enum HookAction<R> {
@@ -6,6 +8,14 @@ pub enum HookAction<R> {
     Halt(R),
 }
 
+/// The action for an indirect branch or return, taken after its
+/// destination is computed.
+pub enum HookBranchAction<R> {
+    Pass,
+    Redirect(Address),
+    Halt(R),
+}
+
 pub enum HookCBranchAction<R> {
     Pass,
     Flip,
@@ -15,6 +25,66 @@ pub enum HookCBranchAction<R> {
 pub enum HookCallAction<R> {
     Pass,
     Skip,
+    SkipWith(HookCallSkip),
+    Halt(R),
+}
+
+/// How a skipped call returns: the return value to set, and the number of
+/// arguments of the skipped function. The return address is always popped
+/// as per the prototype, as are the stack arguments if the convention is
+/// callee-cleanup (e.g., stdcall).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HookCallSkip {
+    return_value: Option<u64>,
+    arguments: Option<usize>,
+    stack_adjustment: Option<u64>,
+}
+
+impl HookCallSkip {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_return_value(self, value: u64) -> Self {
+        Self { return_value: Some(value), ..self }
+    }
+
+    /// The skipped function takes `arguments` integer or pointer
+    /// arguments; overrides the number registered with the context.
+    pub fn with_arguments(self, arguments: usize) -> Self {
+        Self { arguments: Some(arguments), ..self }
+    }
+
+    /// Pop `bytes` bytes in addition to the return address, regardless of
+    /// the convention; takes priority over `with_arguments`.
+    pub fn with_stack_adjustment(self, bytes: u64) -> Self {
+        Self { stack_adjustment: Some(bytes), ..self }
+    }
+
+    pub fn return_value(&self) -> Option<u64> {
+        self.return_value
+    }
+
+    pub fn arguments(&self) -> Option<usize> {
+        self.arguments
+    }
+
+    pub fn stack_adjustment(&self) -> Option<u64> {
+        self.stack_adjustment
+    }
+}
+
+/// The action for an instruction that cannot be lifted; `Pass` leaves it
+/// to the next hook, or fails with the lift error.
+pub enum HookLiftAction<R> {
+    Pass,
+    /// Execute `PCode` in place of the instruction; its length gives the
+    /// fallthrough.
+    Replace(PCode),
+    /// Continue after the instruction's length in bytes, e.g., after the
+    /// hook has applied its semantics to the state; the length must not
+    /// be zero.
+    Skip(usize),
     Halt(R),
 }
 
@@ -5,6 +5,8 @@ use fuguex_state::State;
 
 use std::collections::HashMap;
 
+use downcast_rs::{impl_downcast, Downcast};
+
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -31,7 +33,7 @@ pub enum IntrinsicAction<O> {
     Halt(O),
 }
 
-pub trait IntrinsicBehaviour: dyn_clone::DynClone {
+pub trait IntrinsicBehaviour: dyn_clone::DynClone + Downcast {
     type Outcome;
     type State: State;
 
@@ -52,6 +54,7 @@ pub trait IntrinsicBehaviour: dyn_clone::DynClone {
     ) -> Result<IntrinsicAction<Self::Outcome>, Error<<Self::State as State>::Error>>;
 }
 dyn_clone::clone_trait_object!(<Outcome, State> IntrinsicBehaviour<Outcome=Outcome, State=State> where State: fuguex_state::State);
+impl_downcast!(IntrinsicBehaviour assoc Outcome, State where State: fuguex_state::State);
 
 #[derive(Clone)]
 pub struct IntrinsicHandler<O, S: State> {
@@ -92,6 +95,20 @@ impl<O: Clone, S: State> IntrinsicHandler<O, S> {
         Ok(())
     }
 
+    pub fn find<IN: IntrinsicBehaviour<Outcome = O, State = S> + 'static>(
+        &self,
+        name: &str,
+    ) -> Option<&IN> {
+        self.handlers.get(name).and_then(|handler| handler.downcast_ref::<IN>())
+    }
+
+    pub fn find_mut<IN: IntrinsicBehaviour<Outcome = O, State = S> + 'static>(
+        &mut self,
+        name: &str,
+    ) -> Option<&mut IN> {
+        self.handlers.get_mut(name).and_then(|handler| handler.downcast_mut::<IN>())
+    }
+
     pub fn handle(
         &mut self,
         name: &str,
@@ -0,0 +1,516 @@
+use fugue::bytes::{Endian, Order};
+use fugue::db::Database;
+use fugue::ir::{LanguageDB, Translator};
+use fugue::ir::convention::Convention;
+
+use fuguex_state::paged::PagedState;
+use fuguex_state::pcode::PCodeState;
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::image::{Export, Import, MappedImage, Module, Protection, Region};
+use crate::{endian_for, translator_for, Either, Error, LoaderMapping};
+
+pub const DEFAULT_MODULE_ALIGNMENT: u64 = 0x10000;
+
+enum ModuleSource {
+    Module(Module),
+    Database(Module, Arc<Database>),
+    PE(String, Vec<u8>),
+}
+
+struct PendingModule {
+    source: ModuleSource,
+    base: Option<u64>,
+}
+
+/// The placement of a module within a composed address space.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LoadedModule {
+    name: String,
+    range: Range<u64>,
+    entry: Option<u64>,
+}
+
+impl LoadedModule {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn base(&self) -> u64 {
+        self.range.start
+    }
+
+    pub fn range(&self) -> &Range<u64> {
+        &self.range
+    }
+
+    pub fn entry(&self) -> Option<u64> {
+        self.entry
+    }
+}
+
+/// Compose a main executable and its libraries into a single address space.
+///
+/// Modules are placed in the order they are added: at their requested base
+/// if given (it is an error for such a module to overlap another), otherwise
+/// at their preferred base if it is free, otherwise at the next free address
+/// aligned to `alignment`. Imports are then resolved against the exports of
+/// all modules by name, preferring the module named by the import's library.
+pub struct ModuleLoader {
+    translator: Translator,
+    language: Option<String>,
+    endian: Endian,
+    alignment: u64,
+    modules: Vec<PendingModule>,
+}
+
+impl ModuleLoader {
+    pub fn new(translator: Translator, endian: Endian) -> Self {
+        Self {
+            translator,
+            language: None,
+            endian,
+            alignment: DEFAULT_MODULE_ALIGNMENT,
+            modules: Vec::default(),
+        }
+    }
+
+    pub fn for_language<L>(language_db: &LanguageDB, language: L) -> Result<Self, Error>
+    where L: AsRef<str> {
+        let language = language.as_ref();
+        let mut loader = Self::new(translator_for(language_db, language)?, endian_for(language)?);
+        loader.language = Some(language.to_owned());
+        Ok(loader)
+    }
+
+    pub fn alignment(&mut self, alignment: u64) -> &mut Self {
+        self.alignment = alignment.max(1);
+        self
+    }
+
+    pub fn add_module(&mut self, module: Module, base: Option<u64>) -> &mut Self {
+        self.modules.push(PendingModule { source: ModuleSource::Module(module), base });
+        self
+    }
+
+    /// Add a PE image; if it is placed away from its preferred image base,
+    /// its base relocations are applied.
+    pub fn add_pe<N: AsRef<str>>(&mut self, name: N, bytes: Vec<u8>, base: Option<u64>) -> &mut Self {
+        self.modules.push(PendingModule {
+            source: ModuleSource::PE(name.as_ref().to_owned(), bytes),
+            base,
+        });
+        self
+    }
+
+    /// Add the segments of a `Database`. Its functions are exported by
+    /// name, and its import address table slots, i.e., functions named
+    /// `__imp_<name>`, are imported. As a `Database` has no relocations,
+    /// a module placed away from its preferred base is displaced as by
+    /// `MappedDatabase::from_database_rebased`.
+    pub fn add_database<N, D, F>(&mut self, name: N, database: D, base: Option<u64>, mut segment_filter: F) -> &mut Self
+    where N: AsRef<str>,
+          D: Into<Arc<Database>>,
+          F: FnMut(&fugue::db::Segment) -> bool {
+        let database = database.into();
+
+        let regions = database.segments().iter().filter(|(_, v)| segment_filter(v)).map(|(k, v)| {
+            let size = (1 + *k.end() - *k.start()) as usize;
+            (Region::new(v.name(), *k.start(), size, Protection::default()), v.bytes().to_vec())
+        }).collect::<Vec<_>>();
+
+        let mapped = |address: u64| regions.iter().any(|(r, _)| r.contains(address));
+
+        let mut exports = Vec::default();
+        let mut imports = Vec::default();
+
+        for function in database.functions().iter() {
+            let address = function.address();
+            if !mapped(address) {
+                continue
+            }
+
+            if let Some(name) = function.name().strip_prefix("__imp_") {
+                imports.push(Import::new("", Some(name), 0, address));
+            } else {
+                exports.push(Export::new(function.name(), address));
+            }
+        }
+
+        let preferred = regions.iter().map(|(r, _)| r.address()).min().unwrap_or(0);
+        let module = Module::new(name, self.endian, preferred, regions)
+            .with_exports(exports)
+            .with_imports(imports);
+
+        self.modules.push(PendingModule { source: ModuleSource::Database(module, database), base });
+        self
+    }
+
+    pub fn load(self) -> Result<MappedModules<PagedState<u8>>, Error> {
+        let Self { translator, language, endian, alignment, modules: pending_modules } = self;
+
+        let mut placed = Vec::<LoadedModule>::with_capacity(pending_modules.len());
+        let mut modules = Vec::<Module>::with_capacity(pending_modules.len());
+        let mut databases = Vec::default();
+
+        for pending in pending_modules.into_iter() {
+            let (name, preferred, size) = match pending.source {
+                ModuleSource::Module(ref module) | ModuleSource::Database(ref module, _) => {
+                    let extent = module.extent();
+                    (module.name().to_owned(), extent.start, extent.end - extent.start)
+                },
+                ModuleSource::PE(ref name, ref bytes) => {
+                    let (base, size) = Module::pe_extent(bytes)?;
+                    (name.clone(), base, size as u64)
+                },
+            };
+
+            let base = place(&placed, preferred, size, pending.base, alignment)?;
+            let offset = base.wrapping_sub(preferred);
+
+            let module = match pending.source {
+                // the extent may start below the module's base
+                ModuleSource::Module(module) => {
+                    let rebased = module.base().wrapping_add(offset);
+                    module.rebase(rebased)
+                },
+                ModuleSource::Database(module, database) => {
+                    databases.push((name.clone(), database, offset));
+                    let rebased = module.base().wrapping_add(offset);
+                    module.rebase(rebased)
+                },
+                ModuleSource::PE(name, bytes) => Module::from_pe(name, &bytes, Some(base))?,
+            };
+
+            check_language(language.as_deref(), &module)?;
+
+            placed.push(LoadedModule {
+                name,
+                range: extent(base, size)?,
+                entry: module.entry(),
+            });
+            modules.push(module);
+        }
+
+        let exports = modules.iter()
+            .map(|m| (m.name().to_ascii_lowercase(), m.exports().to_vec()))
+            .collect::<Vec<_>>();
+
+        let resolve = |import: &Import| -> Option<u64> {
+            let name = import.name()?;
+            let library = import.library().to_ascii_lowercase();
+
+            exports.iter()
+                .filter(|(module, _)| *module == library)
+                .chain(exports.iter().filter(|(module, _)| *module != library))
+                .find_map(|(_, exports)| exports.iter().find(|e| e.name() == name))
+                .map(|e| e.address())
+        };
+
+        let mut regions = Vec::default();
+        let mut imports = Vec::default();
+        let mut all_exports = Vec::default();
+        let mut bindings = Vec::default();
+        let mut unresolved = Vec::default();
+
+        for module in modules.into_iter() {
+            let (module_regions, module_imports, module_exports) = module.into_parts();
+
+            for import in module_imports.iter() {
+                if let Some(address) = resolve(import) {
+                    bindings.push((import.clone(), address));
+                } else {
+                    unresolved.push(import.clone());
+                }
+            }
+
+            regions.extend(module_regions);
+            imports.extend(module_imports);
+            all_exports.extend(module_exports);
+        }
+
+        let base = placed.first().map(|m| m.base()).unwrap_or(0);
+        let entry = placed.first().and_then(|m| m.entry());
+
+        let mut image = MappedImage::from_regions(translator, endian, base, entry, regions)?
+            .with_imports(imports)
+            .with_exports(all_exports);
+
+        for (import, address) in bindings.iter() {
+            image.bind_import(import, *address)?;
+        }
+
+        Ok(MappedModules {
+            image,
+            modules: placed,
+            databases,
+            unresolved,
+        })
+    }
+}
+
+fn check_language(expected: Option<&str>, module: &Module) -> Result<(), Error> {
+    match (expected, module.language()) {
+        (Some(expected), Some(language)) if expected != language => {
+            Err(Error::UnsupportedFormat(format!(
+                "module `{}` has language `{}`; expected `{}`",
+                module.name(),
+                language,
+                expected,
+            )))
+        },
+        _ => Ok(()),
+    }
+}
+
+fn overflow(address: u64, size: u64) -> Error {
+    Error::AddressOverflow { address, size }
+}
+
+// The range of `size` bytes at `base`, if it does not wrap
+fn extent(base: u64, size: u64) -> Result<Range<u64>, Error> {
+    base.checked_add(size)
+        .map(|end| base..end)
+        .ok_or_else(|| overflow(base, size))
+}
+
+// The first address at or above `address` aligned to `alignment`
+fn align_up(address: u64, alignment: u64) -> Result<u64, Error> {
+    address.checked_add(alignment - 1)
+        .map(|address| address / alignment * alignment)
+        .ok_or_else(|| overflow(address, alignment))
+}
+
+// The lowest address above all placed modules, aligned to `alignment`, at
+// which `size` bytes are free
+fn next_free(placed: &[LoadedModule], size: u64, alignment: u64) -> Result<u64, Error> {
+    let mut candidate = align_up(placed.iter().map(|m| m.range.end).max().unwrap_or(alignment), alignment)?;
+
+    loop {
+        let range = extent(candidate, size)?;
+        if let Some(conflict) = placed.iter().find(|m| overlaps(&m.range, &range)) {
+            candidate = align_up(conflict.range.end, alignment)?;
+        } else {
+            return Ok(candidate)
+        }
+    }
+}
+
+// The base of a module of `size` bytes with the given preferred and
+// requested bases
+fn place(placed: &[LoadedModule], preferred: u64, size: u64, requested: Option<u64>, alignment: u64) -> Result<u64, Error> {
+    if let Some(base) = requested {
+        let range = extent(base, size)?;
+        if placed.iter().any(|m| overlaps(&m.range, &range)) {
+            return Err(Error::OverlappedMapping { address: base, size: size as usize })
+        }
+        Ok(base)
+    } else if extent(preferred, size).map_or(true, |range| placed.iter().any(|m| overlaps(&m.range, &range))) {
+        next_free(placed, size, alignment)
+    } else {
+        Ok(preferred)
+    }
+}
+
+fn overlaps(lhs: &Range<u64>, rhs: &Range<u64>) -> bool {
+    lhs.start < rhs.end && rhs.start < lhs.end
+}
+
+/// Several modules mapped into a single address space; the entry point is
+/// that of the first module added.
+#[derive(Clone)]
+pub struct MappedModules<S> {
+    image: MappedImage<S>,
+    modules: Vec<LoadedModule>,
+    databases: Vec<(String, Arc<Database>, u64)>,
+    unresolved: Vec<Import>,
+}
+
+impl<S> MappedModules<S> {
+    pub fn image(&self) -> &MappedImage<S> {
+        &self.image
+    }
+
+    pub fn image_mut(&mut self) -> &mut MappedImage<S> {
+        &mut self.image
+    }
+
+    pub fn into_image(self) -> MappedImage<S> {
+        self.image
+    }
+
+    pub fn modules(&self) -> &[LoadedModule] {
+        &self.modules
+    }
+
+    pub fn module_by_name<N: AsRef<str>>(&self, name: N) -> Option<&LoadedModule> {
+        let name = name.as_ref();
+        self.modules.iter().find(|m| m.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The `Database` of the module named `name`, if it was added from one.
+    pub fn database_by_name<N: AsRef<str>>(&self, name: N) -> Option<&Arc<Database>> {
+        self.database_entry(name).map(|(_, database, _)| database)
+    }
+
+    /// The displacement (wrapping) of the addresses of the `Database` of
+    /// the module named `name` from where it was placed.
+    pub fn database_offset_by_name<N: AsRef<str>>(&self, name: N) -> Option<u64> {
+        self.database_entry(name).map(|(_, _, offset)| *offset)
+    }
+
+    fn database_entry<N: AsRef<str>>(&self, name: N) -> Option<&(String, Arc<Database>, u64)> {
+        let name = name.as_ref();
+        self.databases.iter().find(|(module, _, _)| module.eq_ignore_ascii_case(name))
+    }
+
+    /// Imports not provided by the exports of any module; these can be bound
+    /// to stubs via `MappedImage::bind_import`.
+    pub fn unresolved(&self) -> &[Import] {
+        &self.unresolved
+    }
+}
+
+impl MappedModules<PagedState<u8>> {
+    pub fn pcode_state<O: Order>(self, convention: &Convention) -> MappedModules<PCodeState<u8, O>> {
+        MappedModules {
+            image: self.image.pcode_state(convention),
+            modules: self.modules,
+            databases: self.databases,
+            unresolved: self.unresolved,
+        }
+    }
+
+    pub fn pcode_state_with<O: Order, C: AsRef<str>>(self, convention: C) -> Either<MappedModules<PCodeState<u8, O>>, Self> {
+        let convention = convention.as_ref();
+        if let Some(convention) = self.image.translator().compiler_conventions().get(convention).cloned() {
+            Either::Left(self.pcode_state(&convention))
+        } else {
+            Either::Right(self)
+        }
+    }
+}
+
+impl<S> LoaderMapping<S> for MappedModules<S> {
+    /// The `Database` of the first module added from one.
+    fn database(&self) -> Option<Arc<Database>> {
+        self.databases.first().map(|(_, database, _)| database.clone())
+    }
+
+    fn symbol_offset(&self) -> u64 {
+        self.databases.first().map(|(_, _, offset)| *offset).unwrap_or(0)
+    }
+
+    fn translator(&self) -> Arc<Translator> {
+        self.image.translator()
+    }
+
+    fn into_state(self) -> S {
+        self.image.into_state()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loaded(name: &str, range: Range<u64>) -> LoadedModule {
+        LoadedModule { name: name.to_owned(), range, entry: None }
+    }
+
+    #[test]
+    fn placement() -> Result<(), Error> {
+        let alignment = DEFAULT_MODULE_ALIGNMENT;
+
+        // the first module is placed at its preferred base
+        assert_eq!(place(&[], 0x40_0000, 0x3000, None, alignment)?, 0x40_0000);
+
+        let placed = [loaded("main", 0x40_0000..0x40_3000)];
+
+        // a free preferred base is kept, and a requested base is honoured
+        assert_eq!(place(&placed, 0x1000_0000, 0x1000, None, alignment)?, 0x1000_0000);
+        assert_eq!(place(&placed, 0x1000_0000, 0x1000, Some(0x50_0000), alignment)?, 0x50_0000);
+
+        // a taken preferred base moves the module after the last module
+        assert_eq!(place(&placed, 0x40_1000, 0x1000, None, alignment)?, 0x41_0000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn next_free_skips_conflicts() -> Result<(), Error> {
+        let placed = [
+            loaded("a", 0x1_0000..0x1_8000),
+            loaded("b", 0x2_0000..0x4_0000),
+            loaded("c", 0x0..0x8000),
+        ];
+
+        assert_eq!(next_free(&placed, 0x1000, 0x1_0000)?, 0x4_0000);
+        assert_eq!(next_free(&[], 0x1000, 0x1_0000)?, 0x1_0000);
+        assert_eq!(next_free(&placed, 0x1000, 1)?, 0x4_0000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn collisions() {
+        let placed = [loaded("main", 0x40_0000..0x40_3000)];
+
+        assert!(matches!(
+            place(&placed, 0x1000_0000, 0x1000, Some(0x40_2000), DEFAULT_MODULE_ALIGNMENT),
+            Err(Error::OverlappedMapping { address: 0x40_2000, size: 0x1000 })
+        ));
+
+        // adjacent modules do not collide
+        assert!(place(&placed, 0x1000_0000, 0x1000, Some(0x40_3000), DEFAULT_MODULE_ALIGNMENT).is_ok());
+    }
+
+    #[test]
+    fn overflowing_placements() {
+        let placed = [loaded("main", 0xffff_ffff_ffff_0000..0xffff_ffff_ffff_f000)];
+
+        assert!(matches!(
+            place(&[], 0, 0x2000, Some(u64::MAX - 0x1000), 0x1000),
+            Err(Error::AddressOverflow { .. })
+        ));
+        assert!(matches!(
+            place(&placed, 0xffff_ffff_ffff_0000, 0x1000, None, 0x1_0000),
+            Err(Error::AddressOverflow { .. })
+        ));
+        assert!(matches!(align_up(u64::MAX - 1, 0x1000), Err(Error::AddressOverflow { .. })));
+
+        // a preferred base that wraps is placed elsewhere
+        assert_eq!(place(&[], u64::MAX - 0x800, 0x1000, None, 0x1000).ok(), Some(0x1000));
+    }
+
+    #[test]
+    fn rebasing() {
+        let module = Module::new("lib", Endian::Little, 0x1000_1000, vec![
+            (Region::new("headers", 0x1000_0000, 0x1000, Protection::READ), vec![0u8; 0x10]),
+            (Region::new(".text", 0x1000_1000, 0x1000, Protection::READ_EXECUTE), vec![0xc3]),
+        ])
+        .with_entry(Some(0x1000_1000))
+        .with_exports(vec![Export::new("f", 0x1000_1010)])
+        .with_imports(vec![Import::new("libc", Some("malloc"), 0, 0x1000_0800)]);
+
+        // placed by extent, which starts below the module's base
+        let extent = module.extent();
+        assert_eq!(extent, 0x1000_0000..0x1000_2000);
+
+        let offset = 0x2000_0000u64.wrapping_sub(extent.start);
+        let rebased = module.base().wrapping_add(offset);
+        let module = module.rebase(rebased);
+
+        assert_eq!(module.base(), 0x2000_1000);
+        assert_eq!(module.extent(), 0x2000_0000..0x2000_2000);
+        assert_eq!(module.entry(), Some(0x2000_1000));
+        assert_eq!(module.exports()[0].address(), 0x2000_1010);
+        assert_eq!(module.imports()[0].address(), 0x2000_0800);
+
+        // moving down wraps the offset
+        let module = module.rebase(0x1000);
+        assert_eq!(module.extent(), 0x0..0x2000);
+    }
+}
@@ -0,0 +1,434 @@
+use fugue::bytes::{Endian, Order};
+use fugue::ir::{LanguageDB, Translator};
+use fugue::ir::convention::Convention;
+
+use fuguex_state::paged::PagedState;
+use fuguex_state::pcode::PCodeState;
+use fuguex_state::StateOps;
+
+use goblin::elf::header::{EM_386, EM_AARCH64, EM_ARM, EM_MIPS, EM_X86_64, ET_CORE};
+use goblin::elf::note::NT_PRSTATUS;
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+use goblin::elf::Elf;
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::image::{MappedImage, Protection, Region};
+use crate::{translator_for, Either, Error, LoaderMapping};
+
+// Layouts of `elf_prstatus::pr_reg` (`elf_gregset_t`) for each supported
+// architecture; `None` marks slots without a corresponding register.
+
+const X86_REGISTERS: &[Option<&str>] = &[
+    Some("EBX"), Some("ECX"), Some("EDX"), Some("ESI"), Some("EDI"), Some("EBP"), Some("EAX"),
+    Some("DS"), Some("ES"), Some("FS"), Some("GS"), None, Some("EIP"), Some("CS"),
+    Some("eflags"), Some("ESP"), Some("SS"),
+];
+
+const X86_64_REGISTERS: &[Option<&str>] = &[
+    Some("R15"), Some("R14"), Some("R13"), Some("R12"), Some("RBP"), Some("RBX"), Some("R11"),
+    Some("R10"), Some("R9"), Some("R8"), Some("RAX"), Some("RCX"), Some("RDX"), Some("RSI"),
+    Some("RDI"), None, Some("RIP"), Some("CS"), Some("rflags"), Some("RSP"), Some("SS"),
+    Some("FS_OFFSET"), Some("GS_OFFSET"), Some("DS"), Some("ES"), Some("FS"), Some("GS"),
+];
+
+const ARM_REGISTERS: &[Option<&str>] = &[
+    Some("r0"), Some("r1"), Some("r2"), Some("r3"), Some("r4"), Some("r5"), Some("r6"),
+    Some("r7"), Some("r8"), Some("r9"), Some("r10"), Some("r11"), Some("r12"), Some("sp"),
+    Some("lr"), Some("pc"), Some("cpsr"), None,
+];
+
+const AARCH64_REGISTERS: &[Option<&str>] = &[
+    Some("x0"), Some("x1"), Some("x2"), Some("x3"), Some("x4"), Some("x5"), Some("x6"),
+    Some("x7"), Some("x8"), Some("x9"), Some("x10"), Some("x11"), Some("x12"), Some("x13"),
+    Some("x14"), Some("x15"), Some("x16"), Some("x17"), Some("x18"), Some("x19"), Some("x20"),
+    Some("x21"), Some("x22"), Some("x23"), Some("x24"), Some("x25"), Some("x26"), Some("x27"),
+    Some("x28"), Some("x29"), Some("x30"), Some("sp"), Some("pc"), None,
+];
+
+const MIPS_REGISTERS: &[Option<&str>] = &[
+    None, None, None, None, None, None,
+    Some("zero"), Some("at"), Some("v0"), Some("v1"), Some("a0"), Some("a1"), Some("a2"),
+    Some("a3"), Some("t0"), Some("t1"), Some("t2"), Some("t3"), Some("t4"), Some("t5"),
+    Some("t6"), Some("t7"), Some("s0"), Some("s1"), Some("s2"), Some("s3"), Some("s4"),
+    Some("s5"), Some("s6"), Some("s7"), Some("t8"), Some("t9"), Some("k0"), Some("k1"),
+    Some("gp"), Some("sp"), Some("s8"), Some("ra"), Some("lo"), Some("hi"), Some("pc"),
+];
+
+// Decoding context variables set from a bit of a captured register, as
+// (variable, register, bit), e.g., ARM's `TMode` from the CPSR's T bit.
+
+const ARM_CONTEXT: &[(&str, &str, u32)] = &[("TMode", "cpsr", 5)];
+
+/// The largest segment `MappedCore::from_core` maps; segments are zero-filled
+/// up to their untrusted `p_memsz`, so larger ones are rejected.
+pub const MAX_SEGMENT_SIZE: usize = 1 << 30;
+
+struct CoreLayout {
+    language: String,
+    registers: &'static [Option<&'static str>],
+    context: &'static [(&'static str, &'static str, u32)],
+}
+
+fn layout_for(elf: &Elf) -> Result<CoreLayout, Error> {
+    let endian = if elf.little_endian { "LE" } else { "BE" };
+    let (language, registers, context) = match (elf.header.e_machine, elf.is_64) {
+        (EM_386, false) => ("x86:LE:32:default".to_owned(), X86_REGISTERS, &[][..]),
+        (EM_X86_64, true) => ("x86:LE:64:default".to_owned(), X86_64_REGISTERS, &[][..]),
+        (EM_ARM, false) => (format!("ARM:{}:32:v7", endian), ARM_REGISTERS, ARM_CONTEXT),
+        (EM_AARCH64, true) => ("AARCH64:LE:64:v8A".to_owned(), AARCH64_REGISTERS, &[][..]),
+        (EM_MIPS, false) => (format!("MIPS:{}:32:default", endian), MIPS_REGISTERS, &[][..]),
+        (machine, _) => return Err(Error::UnsupportedFormat(format!("ELF core for machine type {}", machine))),
+    };
+
+    Ok(CoreLayout { language, registers, context })
+}
+
+fn read_word(bytes: &[u8], offset: usize, size: usize, endian: Endian) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + size)?;
+    Some(if endian.is_big() {
+        bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+    } else {
+        bytes.iter().rev().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+    })
+}
+
+/// The register state of a thread captured by an `NT_PRSTATUS` note.
+#[derive(Debug, Clone)]
+pub struct CoreThread {
+    pid: u32,
+    signal: u16,
+    registers: Vec<(&'static str, u64)>,
+    context: Vec<(&'static str, u32)>,
+}
+
+impl CoreThread {
+    fn parse(desc: &[u8], is_64: bool, endian: Endian, layout: &CoreLayout) -> Result<Self, Error> {
+        let word = if is_64 { 8 } else { 4 };
+        let (pid_offset, registers_offset) = if is_64 { (32, 112) } else { (24, 72) };
+
+        let truncated = || Error::UnsupportedFormat("truncated NT_PRSTATUS note".to_owned());
+
+        let signal = read_word(desc, 12, 2, endian).ok_or_else(truncated)? as u16;
+        let pid = read_word(desc, pid_offset, 4, endian).ok_or_else(truncated)? as u32;
+
+        let registers = layout.registers.iter()
+            .enumerate()
+            .filter_map(|(i, name)| name.map(|name| (i, name)))
+            .map(|(i, name)| {
+                read_word(desc, registers_offset + i * word, word, endian)
+                    .map(|value| (name, value))
+                    .ok_or_else(truncated)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let context = layout.context.iter()
+            .filter_map(|(variable, register, bit)| {
+                registers.iter()
+                    .find(|(name, _)| name == register)
+                    .map(|(_, value)| (*variable, ((value >> bit) & 1) as u32))
+            })
+            .collect();
+
+        Ok(Self { pid, signal, registers, context })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn signal(&self) -> u16 {
+        self.signal
+    }
+
+    pub fn registers(&self) -> &[(&'static str, u64)] {
+        &self.registers
+    }
+
+    pub fn register<N: AsRef<str>>(&self, name: N) -> Option<u64> {
+        let name = name.as_ref();
+        self.registers.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+    }
+
+    /// The decoding context variables implied by the captured registers,
+    /// e.g., `TMode` for a thread stopped in Thumb mode.
+    pub fn context(&self) -> &[(&'static str, u32)] {
+        &self.context
+    }
+
+    /// Write each captured register to `state`; registers unknown to the
+    /// language are ignored.
+    pub fn apply<O: Order>(&self, state: &mut PCodeState<u8, O>) -> Result<(), Error> {
+        for (name, value) in self.registers.iter() {
+            let register = if let Some(register) = state.registers().register_by_name(name) {
+                register
+            } else {
+                continue
+            };
+
+            let size = register.size().min(8);
+            let bytes = if O::ENDIAN.is_big() {
+                value.to_be_bytes()[8 - size..].to_vec()
+            } else {
+                value.to_le_bytes()[..size].to_vec()
+            };
+
+            let view = state.registers_mut().view_values_mut(register.offset(), size)?;
+            view.copy_from_slice(&bytes);
+        }
+        Ok(())
+    }
+}
+
+/// A process image restored from an ELF core dump.
+#[derive(Clone)]
+pub struct MappedCore<S> {
+    image: MappedImage<S>,
+    threads: Vec<CoreThread>,
+    thread: usize,
+}
+
+impl<S> MappedCore<S> {
+    pub fn image(&self) -> &MappedImage<S> {
+        &self.image
+    }
+
+    pub fn image_mut(&mut self) -> &mut MappedImage<S> {
+        &mut self.image
+    }
+
+    /// Threads in the order their notes appear; the thread that received
+    /// the signal that caused the dump is first.
+    pub fn threads(&self) -> &[CoreThread] {
+        &self.threads
+    }
+
+    pub fn into_image(self) -> MappedImage<S> {
+        self.image
+    }
+}
+
+impl MappedCore<PagedState<u8>> {
+    pub fn from_core(bytes: &[u8], language_db: &LanguageDB) -> Result<Self, Error> {
+        let elf = Elf::parse(bytes)?;
+
+        if elf.header.e_type != ET_CORE {
+            return Err(Error::UnsupportedFormat("ELF is not a core file".to_owned()))
+        }
+
+        let layout = layout_for(&elf)?;
+        let endian = if elf.little_endian { Endian::Little } else { Endian::Big };
+        let translator = translator_for(language_db, &layout.language)?;
+
+        let mut regions = Vec::default();
+        for (i, phdr) in elf.program_headers.iter().filter(|p| p.p_type == PT_LOAD).enumerate() {
+            let size = usize::try_from(phdr.p_memsz)
+                .ok()
+                .filter(|size| *size <= MAX_SEGMENT_SIZE)
+                .ok_or_else(|| Error::ImageTooLarge {
+                    size: phdr.p_memsz.min(usize::MAX as u64) as usize,
+                    limit: MAX_SEGMENT_SIZE,
+                })?;
+
+            if phdr.p_vaddr.checked_add(phdr.p_memsz).is_none() {
+                return Err(Error::UnsupportedFormat(format!(
+                    "segment at {:#x} of {:#x} bytes overflows the address space",
+                    phdr.p_vaddr,
+                    phdr.p_memsz,
+                )))
+            }
+
+            let offset = phdr.p_offset as usize;
+            let available = (phdr.p_filesz as usize).min(size).min(bytes.len().saturating_sub(offset));
+
+            let data = if available > 0 {
+                bytes[offset..offset + available].to_vec()
+            } else {
+                Vec::default()
+            };
+
+            let protection = Protection::new(
+                phdr.p_flags & PF_R != 0,
+                phdr.p_flags & PF_W != 0,
+                phdr.p_flags & PF_X != 0,
+            );
+
+            regions.push((Region::new(format!("load{}", i), phdr.p_vaddr, size, protection), data));
+        }
+
+        let mut threads = Vec::default();
+        if let Some(notes) = elf.iter_note_headers(bytes) {
+            for note in notes {
+                let note = note?;
+                if note.n_type == NT_PRSTATUS && note.name == "CORE" {
+                    threads.push(CoreThread::parse(note.desc, elf.is_64, endian, &layout)?);
+                }
+            }
+        }
+
+        let base = regions.iter().map(|(r, _)| r.address()).min().unwrap_or(0);
+        let image = MappedImage::from_regions(translator, endian, base, None, regions)?;
+
+        Ok(Self { image, threads, thread: 0 })
+    }
+
+    pub fn from_core_path<P>(path: P, language_db: &LanguageDB) -> Result<Self, Error>
+    where P: AsRef<Path> {
+        let bytes = std::fs::read(path)?;
+        Self::from_core(&bytes, language_db)
+    }
+
+    /// Build a `PCodeState` with the registers of the first thread.
+    pub fn pcode_state<O: Order>(self, convention: &Convention) -> Result<MappedCore<PCodeState<u8, O>>, Error> {
+        self.pcode_state_for(convention, 0)
+    }
+
+    /// Build a `PCodeState` with the registers of the thread at `index`;
+    /// its decoding context is given by `LoaderMapping::context_variables`.
+    pub fn pcode_state_for<O: Order>(self, convention: &Convention, index: usize) -> Result<MappedCore<PCodeState<u8, O>>, Error> {
+        let mut image = self.image.pcode_state::<O>(convention);
+
+        if let Some(thread) = self.threads.get(index) {
+            thread.apply(image.state_mut())?;
+        }
+
+        Ok(MappedCore {
+            image,
+            threads: self.threads,
+            thread: index,
+        })
+    }
+
+    pub fn pcode_state_with<O: Order, C: AsRef<str>>(self, convention: C) -> Result<Either<MappedCore<PCodeState<u8, O>>, Self>, Error> {
+        let convention = convention.as_ref();
+        if let Some(convention) = self.image.translator().compiler_conventions().get(convention).cloned() {
+            Ok(Either::Left(self.pcode_state(&convention)?))
+        } else {
+            Ok(Either::Right(self))
+        }
+    }
+}
+
+impl<S> LoaderMapping<S> for MappedCore<S> {
+    fn context_variables(&self) -> Vec<(String, u32)> {
+        self.threads.get(self.thread)
+            .map(|thread| {
+                thread.context()
+                    .iter()
+                    .map(|(variable, value)| ((*variable).to_owned(), *value))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn translator(&self) -> Arc<Translator> {
+        self.image.translator()
+    }
+
+    fn into_state(self) -> S {
+        self.image.into_state()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout(registers: &'static [Option<&'static str>]) -> CoreLayout {
+        CoreLayout { language: String::new(), registers, context: &[] }
+    }
+
+    fn prstatus(size: usize, signal: (usize, &[u8]), pid: (usize, &[u8])) -> Vec<u8> {
+        let mut desc = vec![0u8; size];
+        desc[signal.0..signal.0 + signal.1.len()].copy_from_slice(signal.1);
+        desc[pid.0..pid.0 + pid.1.len()].copy_from_slice(pid.1);
+        desc
+    }
+
+    #[test]
+    fn read_words() {
+        let bytes = [0x01, 0x02, 0x03, 0x04];
+        assert_eq!(read_word(&bytes, 0, 4, Endian::Little), Some(0x04030201));
+        assert_eq!(read_word(&bytes, 0, 4, Endian::Big), Some(0x01020304));
+        assert_eq!(read_word(&bytes, 2, 2, Endian::Big), Some(0x0304));
+        assert_eq!(read_word(&bytes, 2, 4, Endian::Little), None);
+    }
+
+    #[test]
+    fn prstatus_x86_64() -> Result<(), Error> {
+        let layout = layout(X86_64_REGISTERS);
+        let mut desc = prstatus(
+            112 + X86_64_REGISTERS.len() * 8,
+            (12, &11u16.to_le_bytes()),
+            (32, &1234u32.to_le_bytes()),
+        );
+
+        desc[112 + 16 * 8..112 + 17 * 8].copy_from_slice(&0x401000u64.to_le_bytes());
+        desc[112 + 19 * 8..112 + 20 * 8].copy_from_slice(&0x7ffe_0000u64.to_le_bytes());
+
+        let thread = CoreThread::parse(&desc, true, Endian::Little, &layout)?;
+
+        assert_eq!(thread.signal(), 11);
+        assert_eq!(thread.pid(), 1234);
+        assert_eq!(thread.register("RIP"), Some(0x401000));
+        assert_eq!(thread.register("RSP"), Some(0x7ffe_0000));
+        assert_eq!(thread.register("RAX"), Some(0));
+        assert_eq!(thread.registers().len(), X86_64_REGISTERS.iter().flatten().count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn prstatus_arm_big_endian() -> Result<(), Error> {
+        let layout = layout(ARM_REGISTERS);
+        let mut desc = prstatus(
+            72 + ARM_REGISTERS.len() * 4,
+            (12, &6u16.to_be_bytes()),
+            (24, &42u32.to_be_bytes()),
+        );
+
+        desc[72 + 15 * 4..72 + 16 * 4].copy_from_slice(&0x8000u32.to_be_bytes());
+
+        let thread = CoreThread::parse(&desc, false, Endian::Big, &layout)?;
+
+        assert_eq!(thread.signal(), 6);
+        assert_eq!(thread.pid(), 42);
+        assert_eq!(thread.register("pc"), Some(0x8000));
+        assert_eq!(thread.register("cpsr"), Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn prstatus_arm_thumb() -> Result<(), Error> {
+        let layout = CoreLayout { context: ARM_CONTEXT, ..layout(ARM_REGISTERS) };
+        let mut desc = prstatus(
+            72 + ARM_REGISTERS.len() * 4,
+            (12, &11u16.to_le_bytes()),
+            (24, &42u32.to_le_bytes()),
+        );
+
+        let thread = CoreThread::parse(&desc, false, Endian::Little, &layout)?;
+        assert_eq!(thread.context(), &[("TMode", 0)]);
+
+        // CPSR with the T bit set
+        desc[72 + 16 * 4..72 + 17 * 4].copy_from_slice(&0x6000_0030u32.to_le_bytes());
+
+        let thread = CoreThread::parse(&desc, false, Endian::Little, &layout)?;
+        assert_eq!(thread.context(), &[("TMode", 1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn prstatus_truncated() {
+        let layout = layout(X86_REGISTERS);
+        let desc = vec![0u8; 72 + 4];
+        assert!(CoreThread::parse(&desc, false, Endian::Little, &layout).is_err());
+
+        let desc = vec![0u8; 8];
+        assert!(CoreThread::parse(&desc, false, Endian::Little, &layout).is_err());
+    }
+}
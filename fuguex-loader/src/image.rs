@@ -0,0 +1,501 @@
+use fugue::bytes::{Endian, Order};
+use fugue::ir::{Address, Translator};
+use fugue::ir::convention::Convention;
+
+use fuguex_state::flat::{Access, FlatState};
+use fuguex_state::paged::{self, PagedState, Segment as LoadedSegment};
+use fuguex_state::pcode::PCodeState;
+use fuguex_state::StateOps;
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::{Either, Error, LoaderMapping};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Protection {
+    read: bool,
+    write: bool,
+    execute: bool,
+}
+
+impl Default for Protection {
+    fn default() -> Self {
+        Self::READ_WRITE
+    }
+}
+
+impl Protection {
+    pub const READ: Self = Self::new(true, false, false);
+    pub const READ_WRITE: Self = Self::new(true, true, false);
+    pub const READ_EXECUTE: Self = Self::new(true, false, true);
+    pub const READ_WRITE_EXECUTE: Self = Self::new(true, true, true);
+
+    pub const fn new(read: bool, write: bool, execute: bool) -> Self {
+        Self { read, write, execute }
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.read
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.write
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.execute
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Region {
+    name: String,
+    range: Range<u64>,
+    protection: Protection,
+}
+
+impl Region {
+    pub fn new<N: AsRef<str>>(name: N, address: u64, size: usize, protection: Protection) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            range: address..address + size as u64,
+            protection,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address(&self) -> u64 {
+        self.range.start
+    }
+
+    pub fn len(&self) -> usize {
+        (self.range.end - self.range.start) as usize
+    }
+
+    pub fn range(&self) -> &Range<u64> {
+        &self.range
+    }
+
+    pub fn protection(&self) -> Protection {
+        self.protection
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        self.range.contains(&address)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Import {
+    library: String,
+    name: Option<String>,
+    ordinal: u16,
+    address: u64,
+}
+
+impl Import {
+    pub fn new<L, N>(library: L, name: Option<N>, ordinal: u16, address: u64) -> Self
+    where L: AsRef<str>,
+          N: AsRef<str> {
+        Self {
+            library: library.as_ref().to_owned(),
+            name: name.map(|name| name.as_ref().to_owned()),
+            ordinal,
+            address,
+        }
+    }
+
+    pub fn library(&self) -> &str {
+        &self.library
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn ordinal(&self) -> u16 {
+        self.ordinal
+    }
+
+    /// The address of the slot the import's resolved address is written to,
+    /// e.g., its entry in a PE import address table.
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Export {
+    name: String,
+    address: u64,
+}
+
+impl Export {
+    pub fn new<N: AsRef<str>>(name: N, address: u64) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            address,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+}
+
+/// An image laid out in memory, but not yet mapped into a state.
+#[derive(Debug, Clone)]
+pub struct Module {
+    name: String,
+    language: Option<String>,
+    endian: Endian,
+    base: u64,
+    entry: Option<u64>,
+    exports: Vec<Export>,
+    imports: Vec<Import>,
+    regions: Vec<(Region, Vec<u8>)>,
+}
+
+impl Module {
+    pub fn new<N: AsRef<str>>(name: N, endian: Endian, base: u64, regions: Vec<(Region, Vec<u8>)>) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            language: None,
+            endian,
+            base,
+            entry: None,
+            exports: Vec::default(),
+            imports: Vec::default(),
+            regions,
+        }
+    }
+
+    pub fn with_language<L: AsRef<str>>(self, language: L) -> Self {
+        Self { language: Some(language.as_ref().to_owned()), ..self }
+    }
+
+    pub fn with_entry(self, entry: Option<u64>) -> Self {
+        Self { entry, ..self }
+    }
+
+    pub fn with_exports<I>(self, exports: I) -> Self
+    where I: IntoIterator<Item = Export> {
+        Self { exports: exports.into_iter().collect(), ..self }
+    }
+
+    pub fn with_imports<I>(self, imports: I) -> Self
+    where I: IntoIterator<Item = Import> {
+        Self { imports: imports.into_iter().collect(), ..self }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The language tag of the module, if known, e.g., `x86:LE:64:default`.
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    pub fn entry(&self) -> Option<u64> {
+        self.entry
+    }
+
+    pub fn exports(&self) -> &[Export] {
+        &self.exports
+    }
+
+    pub fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter().map(|(region, _)| region)
+    }
+
+    /// The address range spanned by the module's regions.
+    pub fn extent(&self) -> Range<u64> {
+        let start = self.regions().map(|r| r.range.start).min().unwrap_or(self.base);
+        let end = self.regions().map(|r| r.range.end).max().unwrap_or(self.base);
+        start..end
+    }
+
+    /// Move the module to `base` by displacing all of its addresses; no
+    /// relocations are applied.
+    pub fn rebase(self, base: u64) -> Self {
+        let delta = base.wrapping_sub(self.base);
+        let shift = |address: u64| address.wrapping_add(delta);
+
+        Self {
+            base,
+            entry: self.entry.map(shift),
+            exports: self.exports.into_iter()
+                .map(|e| Export { address: shift(e.address), ..e })
+                .collect(),
+            imports: self.imports.into_iter()
+                .map(|i| Import { address: shift(i.address), ..i })
+                .collect(),
+            regions: self.regions.into_iter()
+                .map(|(r, bytes)| (Region::new(r.name(), shift(r.address()), r.len(), r.protection()), bytes))
+                .collect(),
+            ..self
+        }
+    }
+
+    pub(crate) fn into_parts(self) -> (Vec<(Region, Vec<u8>)>, Vec<Import>, Vec<Export>) {
+        (self.regions, self.imports, self.exports)
+    }
+}
+
+/// A loaded image that is mapped natively, i.e., without the use of an
+/// external disassembler backend to populate a `Database`.
+#[derive(Clone)]
+pub struct MappedImage<S> {
+    base: u64,
+    endian: Endian,
+    entry: Option<u64>,
+    exports: Vec<Export>,
+    imports: Vec<Import>,
+    regions: Vec<Region>,
+    state: S,
+    translator: Arc<Translator>,
+}
+
+impl<S> MappedImage<S> {
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn entry(&self) -> Option<u64> {
+        self.entry
+    }
+
+    pub fn exports(&self) -> &[Export] {
+        &self.exports
+    }
+
+    pub fn export_by_name<N: AsRef<str>>(&self, name: N) -> Option<&Export> {
+        let name = name.as_ref();
+        self.exports.iter().find(|e| e.name() == name)
+    }
+
+    pub fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn region_for(&self, address: u64) -> Option<&Region> {
+        self.regions.iter().find(|r| r.contains(address))
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut S {
+        &mut self.state
+    }
+}
+
+impl MappedImage<PagedState<u8>> {
+    /// Map each region at its address; regions with fewer bytes than their
+    /// size are zero-filled. Region protections are applied after all bytes
+    /// are written.
+    pub fn from_regions<I>(translator: Translator,
+                           endian: Endian,
+                           base: u64,
+                           entry: Option<u64>,
+                           regions: I) -> Result<Self, Error>
+    where I: IntoIterator<Item = (Region, Vec<u8>)> {
+        let space = translator.manager().default_space();
+        let mut backing = Vec::default();
+        let mut mapped = Vec::<Region>::default();
+        let mut ivt = Vec::default();
+
+        for (region, bytes) in regions.into_iter() {
+            if region.len() == 0 {
+                continue
+            }
+
+            if mapped.iter().any(|r| r.range.start < region.range.end && region.range.start < r.range.end) {
+                return Err(Error::OverlappedMapping {
+                    address: region.address(),
+                    size: region.len(),
+                })
+            }
+
+            let offset = backing.len();
+            let size = region.len();
+
+            ivt.push((translator.address(region.range.start).into()..translator.address(region.range.end).into(),
+                      LoadedSegment::new(region.name(), offset)));
+
+            backing.extend_from_slice(&bytes[..bytes.len().min(size)]);
+            backing.resize_with(offset + size, Default::default);
+
+            mapped.push(region);
+        }
+
+        let mut flat = FlatState::from_vec(space, backing);
+
+        let mut offset = 0usize;
+        for region in mapped.iter() {
+            let start = Address::from(offset as u64);
+            let protection = region.protection();
+
+            // NOTE: instructions are fetched via reads, so executable
+            // regions must remain readable to be lifted
+            if !protection.is_readable() && !protection.is_executable() {
+                flat.permissions_mut().clear_region(&start, region.len(), Access::Read);
+            }
+
+            if !protection.is_writable() {
+                flat.permissions_mut().clear_region(&start, region.len(), Access::Write);
+            }
+
+            offset += region.len();
+        }
+
+        let state = PagedState::from_parts(ivt.into_iter(), flat);
+
+        Ok(Self {
+            base,
+            endian,
+            entry,
+            exports: Vec::default(),
+            imports: Vec::default(),
+            regions: mapped,
+            state,
+            translator: Arc::new(translator),
+        })
+    }
+
+    pub fn from_module(translator: Translator, module: Module) -> Result<Self, Error> {
+        let endian = module.endian;
+        let base = module.base;
+        let entry = module.entry;
+
+        Ok(Self::from_regions(translator, endian, base, entry, module.regions)?
+            .with_imports(module.imports)
+            .with_exports(module.exports))
+    }
+
+    pub fn with_exports<I>(self, exports: I) -> Self
+    where I: IntoIterator<Item = Export> {
+        Self { exports: exports.into_iter().collect(), ..self }
+    }
+
+    pub fn with_imports<I>(self, imports: I) -> Self
+    where I: IntoIterator<Item = Import> {
+        Self { imports: imports.into_iter().collect(), ..self }
+    }
+
+    /// Write `address` into the slot of `import`, e.g., to redirect calls
+    /// through the import to a stub. The slot is written regardless of the
+    /// protection of the region containing it.
+    pub fn bind_import(&mut self, import: &Import, address: u64) -> Result<(), Error> {
+        let size = self.translator.manager().default_space().address_size();
+        let mut buf = [0u8; 8];
+
+        if size > buf.len() {
+            return Err(Error::UnsupportedFormat(format!("{}-byte import slots", size)))
+        }
+
+        if self.endian.is_big() {
+            buf.copy_from_slice(&address.to_be_bytes());
+            self.force_write(import.address(), &buf[8 - size..])
+        } else {
+            buf.copy_from_slice(&address.to_le_bytes());
+            self.force_write(import.address(), &buf[..size])
+        }
+    }
+
+    /// Bind all imports with the given name, returning the number bound.
+    pub fn bind_import_by_name<N: AsRef<str>>(&mut self, name: N, address: u64) -> Result<usize, Error> {
+        let name = name.as_ref();
+        let imports = self.imports.iter()
+            .filter(|i| i.name() == Some(name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for import in imports.iter() {
+            self.bind_import(import, address)?;
+        }
+
+        Ok(imports.len())
+    }
+
+    fn force_write(&mut self, address: u64, bytes: &[u8]) -> Result<(), Error> {
+        let address: Address = self.translator.address(address).into();
+        self.state.with_flat_mut(address, bytes.len(), |flat, address, size| {
+            let writable = flat.permissions().all_writable(&address, size);
+            if !writable {
+                flat.permissions_mut().set_region(&address, size, Access::Write);
+            }
+
+            let res = flat.set_values(address, bytes).map_err(paged::Error::Backing);
+
+            if !writable {
+                flat.permissions_mut().clear_region(&address, size, Access::Write);
+            }
+
+            res
+        })?;
+        Ok(())
+    }
+
+    pub fn pcode_state<O: Order>(self, convention: &Convention) -> MappedImage<PCodeState<u8, O>> {
+        MappedImage {
+            state: PCodeState::new(self.state, &self.translator, convention),
+            base: self.base,
+            endian: self.endian,
+            entry: self.entry,
+            exports: self.exports,
+            imports: self.imports,
+            regions: self.regions,
+            translator: self.translator,
+        }
+    }
+
+    pub fn pcode_state_with<O: Order, C: AsRef<str>>(self, convention: C) -> Either<MappedImage<PCodeState<u8, O>>, Self> {
+        let convention = convention.as_ref();
+        if let Some(convention) = self.translator.compiler_conventions().get(convention).cloned() {
+            Either::Left(self.pcode_state(&convention))
+        } else {
+            Either::Right(self)
+        }
+    }
+}
+
+impl<S> LoaderMapping<S> for MappedImage<S> {
+    fn translator(&self) -> Arc<Translator> {
+        self.translator.clone()
+    }
+
+    fn into_state(self) -> S {
+        self.state
+    }
+}
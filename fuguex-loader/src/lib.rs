@@ -1,6 +1,6 @@
 pub use either::Either;
 
-use fugue::bytes::Order;
+use fugue::bytes::{Endian, Order};
 use fugue::db::{Database, DatabaseImporter, Segment};
 use fugue::ir::{LanguageDB, Translator};
 use fugue::ir::convention::Convention;
@@ -21,10 +21,95 @@ use std::sync::Arc;
 
 use thiserror::Error;
 
+pub mod compose;
+pub use compose::{LoadedModule, MappedModules, ModuleLoader};
+
+pub mod coredump;
+pub use coredump::{CoreThread, MappedCore};
+
+pub mod image;
+pub use image::{Export, Import, MappedImage, Module, Protection, Region};
+
+pub mod pe;
+
+pub mod raw;
+pub use raw::{RawFormat, RawRegion};
+
+pub mod startup;
+pub use startup::ProcessEnvironment;
+
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("mapping of {size:#x} bytes at {address:#x} overflows the address space")]
+    AddressOverflow { address: u64, size: u64 },
     #[error("database import: {0}")]
     Import(#[from] fugue::db::Error),
+    #[error("image of {size:#x} bytes exceeds the limit of {limit:#x} bytes")]
+    ImageTooLarge { size: usize, limit: usize },
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error("language: {0}")]
+    Language(#[from] fugue::ir::error::Error),
+    #[error("malformed {format} record at line {line}")]
+    MalformedRecord { format: &'static str, line: usize },
+    #[error("overlapped mapping of {size} bytes from {address:#x}")]
+    OverlappedMapping { address: u64, size: usize },
+    #[error("parse: {0}")]
+    Parse(#[from] goblin::error::Error),
+    #[error(transparent)]
+    PCode(#[from] fuguex_state::pcode::Error),
+    #[error(transparent)]
+    Register(#[from] fuguex_state::register::Error),
+    #[error("process environment does not fit in a stack of {0} bytes")]
+    StackExhausted(usize),
+    #[error("stack of {size} bytes does not fit below {top:#x}")]
+    StackSize { top: u64, size: usize },
+    #[error(transparent)]
+    State(#[from] fuguex_state::paged::Error),
+    #[error("unsupported calling convention `{0}`")]
+    UnsupportedConvention(String),
+    #[error("unsupported format: {0}")]
+    UnsupportedFormat(String),
+    #[error("unsupported language `{0}`")]
+    UnsupportedLanguage(String),
+}
+
+fn language_parts(tag: &str) -> Result<(&str, Endian, usize, &str), Error> {
+    let parts = tag.splitn(4, ':').collect::<Vec<_>>();
+
+    if parts.len() != 4 {
+        return Err(Error::UnsupportedLanguage(tag.to_owned()))
+    }
+
+    let endian = match parts[1] {
+        "LE" => Endian::Little,
+        "BE" => Endian::Big,
+        _ => return Err(Error::UnsupportedLanguage(tag.to_owned())),
+    };
+
+    let bits = parts[2].parse::<usize>()
+        .map_err(|_| Error::UnsupportedLanguage(tag.to_owned()))?;
+
+    Ok((parts[0], endian, bits, parts[3]))
+}
+
+/// Build a translator for a language given by its tag, e.g.,
+/// `x86:LE:64:default`.
+pub fn translator_for<L>(language_db: &LanguageDB, language: L) -> Result<Translator, Error>
+where L: AsRef<str> {
+    let tag = language.as_ref();
+    let (processor, endian, bits, variant) = language_parts(tag)?;
+
+    let builder = language_db.lookup(processor, endian, bits, variant)
+        .ok_or_else(|| Error::UnsupportedLanguage(tag.to_owned()))?;
+
+    Ok(builder.build()?)
+}
+
+/// The byte order of a language given by its tag.
+pub fn endian_for<L>(language: L) -> Result<Endian, Error>
+where L: AsRef<str> {
+    language_parts(language.as_ref()).map(|(_, endian, _, _)| endian)
 }
 
 pub trait LoaderMapping<S> {
@@ -32,6 +117,18 @@ pub trait LoaderMapping<S> {
         None
     }
 
+    /// The displacement (wrapping) of the database's addresses in the
+    /// mapped state, if its image has been rebased.
+    fn symbol_offset(&self) -> u64 {
+        0
+    }
+
+    /// Decoding context variables to set before the first instruction is
+    /// lifted, e.g., `TMode` for an ARM core dump taken in Thumb mode.
+    fn context_variables(&self) -> Vec<(String, u32)> {
+        Vec::new()
+    }
+
     fn translator(&self) -> Arc<Translator>;
     fn into_state(self) -> S;
 }
@@ -39,18 +136,29 @@ pub trait LoaderMapping<S> {
 #[derive(Clone)]
 pub struct MappedDatabase<S> {
     database: Arc<Database>,
+    offset: u64,
     state: S,
     translator: Arc<Translator>,
 }
 
 impl MappedDatabase<PagedState<u8>> {
-    pub fn from_database_with<F>(database: Database, mut segment_filter: F) -> Self
+    pub fn from_database_with<F>(database: Database, segment_filter: F) -> Self
+    where F: FnMut(&Segment) -> bool {
+        Self::from_database_rebased(database, 0, segment_filter)
+    }
+
+    /// Map the segments of `database` displaced by `offset` (wrapping), as
+    /// for an image loaded away from its preferred base; no relocations
+    /// are applied.
+    pub fn from_database_rebased<F>(database: Database, offset: u64, mut segment_filter: F) -> Self
     where F: FnMut(&Segment) -> bool {
         let translator = database.default_translator();
         let space = translator.manager().default_space();
         let mut backing = Vec::default();
         let ivt = database.segments().iter().filter(|(_, v)| segment_filter(v)).map(|(k, v)| {
-            let kv = (translator.address(*k.start()).into()..translator.address(1 + *k.end()).into(),
+            let start = k.start().wrapping_add(offset);
+            let end = k.end().wrapping_add(offset);
+            let kv = (translator.address(start).into()..translator.address(1 + end).into(),
                       LoadedSegment::new(v.name(), backing.len()));
 
             backing.extend_from_slice(v.bytes());
@@ -69,6 +177,7 @@ impl MappedDatabase<PagedState<u8>> {
 
         Self {
             database: Arc::new(database),
+            offset,
             translator: Arc::new(translator),
             state,
         }
@@ -107,6 +216,7 @@ impl MappedDatabase<PagedState<u8>> {
         MappedDatabase {
             state: PCodeState::new(self.state, &self.translator, convention),
             database: self.database,
+            offset: self.offset,
             translator: self.translator,
         }
     }
@@ -117,6 +227,7 @@ impl MappedDatabase<PagedState<u8>> {
             Either::Left(MappedDatabase {
                 state: PCodeState::new(self.state, &self.translator, convention),
                 database: self.database,
+                offset: self.offset,
                 translator: self.translator,
             })
         } else {
@@ -130,6 +241,10 @@ impl<S> LoaderMapping<S> for MappedDatabase<S> {
         Some(self.database.clone())
     }
 
+    fn symbol_offset(&self) -> u64 {
+        self.offset
+    }
+
     fn translator(&self) -> Arc<Translator> {
         self.translator.clone()
     }
@@ -0,0 +1,512 @@
+use fugue::bytes::Endian;
+use fugue::ir::LanguageDB;
+
+use fuguex_state::paged::PagedState;
+
+use goblin::pe::header::{
+    COFF_MACHINE_ARM, COFF_MACHINE_ARM64, COFF_MACHINE_ARMNT, COFF_MACHINE_THUMB,
+    COFF_MACHINE_X86, COFF_MACHINE_X86_64,
+};
+use goblin::pe::section_table::{
+    SectionTable, IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE,
+};
+use goblin::pe::PE;
+
+use std::path::Path;
+
+use crate::image::{Export, Import, MappedImage, Module, Protection, Region};
+use crate::{translator_for, Error};
+
+const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+const IMAGE_REL_BASED_HIGH: u16 = 1;
+const IMAGE_REL_BASED_LOW: u16 = 2;
+const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+/// The largest `SizeOfImage` accepted by `Module::from_pe`; the header is
+/// untrusted, so larger images are rejected rather than allocated.
+pub const MAX_IMAGE_SIZE: usize = 1 << 30;
+
+fn language_for(machine: u16) -> Result<&'static str, Error> {
+    Ok(match machine {
+        COFF_MACHINE_X86 => "x86:LE:32:default",
+        COFF_MACHINE_X86_64 => "x86:LE:64:default",
+        COFF_MACHINE_ARM | COFF_MACHINE_ARMNT | COFF_MACHINE_THUMB => "ARM:LE:32:v7",
+        COFF_MACHINE_ARM64 => "AARCH64:LE:64:v8A",
+        _ => return Err(Error::UnsupportedFormat(format!("PE machine type {:#x}", machine))),
+    })
+}
+
+fn language_for_module(module: &Module) -> Result<&str, Error> {
+    module.language()
+        .ok_or_else(|| Error::UnsupportedFormat(format!("module `{}` has no language", module.name())))
+}
+
+fn protection_for(section: &SectionTable) -> Protection {
+    Protection::new(
+        section.characteristics & IMAGE_SCN_MEM_READ != 0,
+        section.characteristics & IMAGE_SCN_MEM_WRITE != 0,
+        section.characteristics & IMAGE_SCN_MEM_EXECUTE != 0,
+    )
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    if alignment <= 1 {
+        value
+    } else {
+        (value + alignment - 1) & !(alignment - 1)
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let mut buf = [0u8; 2];
+    buf.copy_from_slice(bytes.get(offset..offset + 2)?);
+    Some(u16::from_le_bytes(buf))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes.get(offset..offset + 4)?);
+    Some(u32::from_le_bytes(buf))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes.get(offset..offset + 8)?);
+    Some(u64::from_le_bytes(buf))
+}
+
+fn invalid_relocation(rva: usize) -> Error {
+    Error::UnsupportedFormat(format!("base relocation at RVA {:#x} outside of image", rva))
+}
+
+// Apply the base relocation table at `table` (RVA, size) to the image laid
+// out by RVA in `image`.
+fn relocate(image: &mut [u8], table: (usize, usize), delta: u64) -> Result<(), Error> {
+    let (start, size) = table;
+    let end = start.checked_add(size)
+        .filter(|end| *end <= image.len())
+        .ok_or_else(|| invalid_relocation(start))?;
+
+    let mut block = start;
+    while block + 8 <= end {
+        let page = read_u32(image, block).ok_or_else(|| invalid_relocation(block))? as usize;
+        let block_size = read_u32(image, block + 4).ok_or_else(|| invalid_relocation(block))? as usize;
+
+        if block_size < 8 {
+            break
+        }
+
+        let entries = block + 8..(block + block_size).min(end);
+        for entry in entries.step_by(2) {
+            let value = read_u16(image, entry).ok_or_else(|| invalid_relocation(entry))?;
+            let kind = value >> 12;
+            let rva = page + (value & 0xfff) as usize;
+
+            match kind {
+                IMAGE_REL_BASED_ABSOLUTE => (),
+                IMAGE_REL_BASED_HIGH => {
+                    let v = read_u16(image, rva).ok_or_else(|| invalid_relocation(rva))?;
+                    let v = ((v as u32) << 16).wrapping_add(delta as u32) >> 16;
+                    image[rva..rva + 2].copy_from_slice(&(v as u16).to_le_bytes());
+                },
+                IMAGE_REL_BASED_LOW => {
+                    let v = read_u16(image, rva).ok_or_else(|| invalid_relocation(rva))?;
+                    let v = v.wrapping_add(delta as u16);
+                    image[rva..rva + 2].copy_from_slice(&v.to_le_bytes());
+                },
+                IMAGE_REL_BASED_HIGHLOW => {
+                    let v = read_u32(image, rva).ok_or_else(|| invalid_relocation(rva))?;
+                    let v = v.wrapping_add(delta as u32);
+                    image[rva..rva + 4].copy_from_slice(&v.to_le_bytes());
+                },
+                IMAGE_REL_BASED_DIR64 => {
+                    let v = read_u64(image, rva).ok_or_else(|| invalid_relocation(rva))?;
+                    let v = v.wrapping_add(delta);
+                    image[rva..rva + 8].copy_from_slice(&v.to_le_bytes());
+                },
+                _ => return Err(Error::UnsupportedFormat(format!("base relocation type {}", kind))),
+            }
+        }
+
+        block += block_size;
+    }
+
+    Ok(())
+}
+
+impl Module {
+    /// The preferred image base and size of a PE image.
+    pub fn pe_extent(bytes: &[u8]) -> Result<(u64, usize), Error> {
+        let pe = PE::parse(bytes)?;
+        let optional_header = pe.header.optional_header
+            .ok_or_else(|| Error::UnsupportedFormat("PE without optional header".to_owned()))?;
+
+        Ok((pe.image_base as u64, optional_header.windows_fields.size_of_image as usize))
+    }
+
+    /// Lay out a PE image at `base`, or its preferred image base if `None`;
+    /// base relocations are applied if the image is rebased.
+    pub fn from_pe<N: AsRef<str>>(name: N, bytes: &[u8], base: Option<u64>) -> Result<Self, Error> {
+        let pe = PE::parse(bytes)?;
+
+        let optional_header = pe.header.optional_header
+            .ok_or_else(|| Error::UnsupportedFormat("PE without optional header".to_owned()))?;
+
+        let language = language_for(pe.header.coff_header.machine)?;
+
+        let windows = &optional_header.windows_fields;
+        let image_base = pe.image_base as u64;
+        let base = base.unwrap_or(image_base);
+
+        let image_size = windows.size_of_image as usize;
+        let alignment = windows.section_alignment as usize;
+
+        if image_size > MAX_IMAGE_SIZE {
+            return Err(Error::ImageTooLarge { size: image_size, limit: MAX_IMAGE_SIZE })
+        }
+
+        let headers_size = (windows.size_of_headers as usize).min(bytes.len()).min(image_size);
+
+        let mut sections = Vec::with_capacity(pe.sections.len());
+
+        for section in pe.sections.iter() {
+            let rva = section.virtual_address as usize;
+            let raw_size = section.size_of_raw_data as usize;
+            let virtual_size = if section.virtual_size == 0 {
+                raw_size
+            } else {
+                section.virtual_size as usize
+            };
+
+            let size = align_up(virtual_size.min(image_size), alignment).min(image_size.saturating_sub(rva));
+            if size == 0 {
+                continue
+            }
+
+            let offset = section.pointer_to_raw_data as usize;
+            let available = raw_size.min(size).min(bytes.len().saturating_sub(offset));
+
+            let name = section.name().unwrap_or("").to_owned();
+            sections.push((name, rva, size, protection_for(section), offset, available));
+        }
+
+        // only the headers and sections are mapped, so the image is laid out
+        // up to the end of the last section rather than to `SizeOfImage`
+        let extent = sections.iter()
+            .map(|(_, rva, size, _, _, _)| rva + size)
+            .fold(headers_size, usize::max);
+
+        let mut image = vec![0u8; extent];
+        image[..headers_size].copy_from_slice(&bytes[..headers_size]);
+
+        for (_, rva, _, _, offset, available) in sections.iter() {
+            image[*rva..rva + available].copy_from_slice(&bytes[*offset..offset + available]);
+        }
+
+        if base != image_base {
+            let table = optional_header.data_directories
+                .get_base_relocation_table()
+                .as_ref()
+                .map(|dd| (dd.virtual_address as usize, dd.size as usize))
+                .ok_or_else(|| Error::UnsupportedFormat("PE cannot be rebased without base relocations".to_owned()))?;
+
+            relocate(&mut image, table, base.wrapping_sub(image_base))?;
+        }
+
+        let mut regions = Vec::with_capacity(1 + sections.len());
+        if let Some(first) = sections.iter().map(|(_, rva, _, _, _, _)| *rva).min() {
+            let size = headers_size.min(first);
+            regions.push((Region::new("headers", base, size, Protection::READ), image[..size].to_vec()));
+        }
+
+        for (name, rva, size, protection, _, _) in sections.into_iter() {
+            regions.push((Region::new(name, base + rva as u64, size, protection), image[rva..rva + size].to_vec()));
+        }
+
+        let entry = if pe.entry != 0 { Some(base + pe.entry as u64) } else { None };
+
+        let imports = pe.imports.iter().map(|import| {
+            // NOTE: imports by ordinal have no hint/name entry
+            let name = if import.rva == 0 { None } else { Some(import.name.as_ref()) };
+            Import::new(import.dll, name, import.ordinal, base + import.offset as u64)
+        });
+
+        let exports = pe.exports.iter().filter_map(|export| {
+            if export.reexport.is_some() {
+                return None
+            }
+            export.name.map(|name| Export::new(name, base + export.rva as u64))
+        });
+
+        Ok(Module::new(name, Endian::Little, base, regions)
+            .with_language(language)
+            .with_entry(entry)
+            .with_imports(imports)
+            .with_exports(exports))
+    }
+}
+
+impl MappedImage<PagedState<u8>> {
+    /// Map a PE image at its preferred image base.
+    pub fn from_pe(bytes: &[u8], language_db: &LanguageDB) -> Result<Self, Error> {
+        Self::from_pe_with(bytes, language_db, None)
+    }
+
+    /// Map a PE image at `base`, or its preferred image base if `None`;
+    /// base relocations are applied if the image is rebased.
+    pub fn from_pe_with(bytes: &[u8], language_db: &LanguageDB, base: Option<u64>) -> Result<Self, Error> {
+        let module = Module::from_pe("pe", bytes, base)?;
+        let translator = translator_for(language_db, language_for_module(&module)?)?;
+        Self::from_module(translator, module)
+    }
+
+    pub fn from_pe_path<P>(path: P, language_db: &LanguageDB) -> Result<Self, Error>
+    where P: AsRef<Path> {
+        Self::from_pe_path_with(path, language_db, None)
+    }
+
+    pub fn from_pe_path_with<P>(path: P, language_db: &LanguageDB, base: Option<u64>) -> Result<Self, Error>
+    where P: AsRef<Path> {
+        let bytes = std::fs::read(path)?;
+        Self::from_pe_with(&bytes, language_db, base)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block(page: u32, entries: &[u16]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&page.to_le_bytes());
+        bytes.extend_from_slice(&(8 + 2 * entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            bytes.extend_from_slice(&entry.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn image_with(table: &[u8]) -> (Vec<u8>, (usize, usize)) {
+        let mut image = vec![0u8; 0x2000];
+        image[0x1000..0x1000 + table.len()].copy_from_slice(table);
+        (image, (0x1000, table.len()))
+    }
+
+    #[test]
+    fn relocate_kinds() -> Result<(), Error> {
+        let table = block(0x100, &[
+            (IMAGE_REL_BASED_HIGHLOW << 12) | 0x10,
+            (IMAGE_REL_BASED_DIR64 << 12) | 0x20,
+            (IMAGE_REL_BASED_LOW << 12) | 0x30,
+            (IMAGE_REL_BASED_HIGH << 12) | 0x40,
+            IMAGE_REL_BASED_ABSOLUTE << 12,
+        ]);
+        let (mut image, table) = image_with(&table);
+
+        image[0x110..0x114].copy_from_slice(&0x0040_1000u32.to_le_bytes());
+        image[0x120..0x128].copy_from_slice(&0x1_4000_1000u64.to_le_bytes());
+        image[0x130..0x132].copy_from_slice(&0xfff0u16.to_le_bytes());
+        image[0x140..0x142].copy_from_slice(&0x0040u16.to_le_bytes());
+
+        relocate(&mut image, table, 0x0001_0020)?;
+
+        assert_eq!(read_u32(&image, 0x110), Some(0x0041_1020));
+        assert_eq!(read_u64(&image, 0x120), Some(0x1_4001_1020));
+        assert_eq!(read_u16(&image, 0x130), Some(0x0010));
+        assert_eq!(read_u16(&image, 0x140), Some(0x0041));
+
+        Ok(())
+    }
+
+    #[test]
+    fn relocate_multiple_blocks() -> Result<(), Error> {
+        let mut table = block(0x0, &[(IMAGE_REL_BASED_HIGHLOW << 12) | 0x8]);
+        table.extend(block(0x800, &[(IMAGE_REL_BASED_HIGHLOW << 12) | 0x4]));
+        let (mut image, table) = image_with(&table);
+
+        image[0x8..0xc].copy_from_slice(&0x1000u32.to_le_bytes());
+        image[0x804..0x808].copy_from_slice(&0x2000u32.to_le_bytes());
+
+        relocate(&mut image, table, 0x100)?;
+
+        assert_eq!(read_u32(&image, 0x8), Some(0x1100));
+        assert_eq!(read_u32(&image, 0x804), Some(0x2100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn relocate_negative_delta() -> Result<(), Error> {
+        let table = block(0x0, &[(IMAGE_REL_BASED_DIR64 << 12) | 0x0]);
+        let (mut image, table) = image_with(&table);
+
+        image[..8].copy_from_slice(&0x1_4000_1000u64.to_le_bytes());
+
+        relocate(&mut image, table, 0x1_0000_0000u64.wrapping_sub(0x1_4000_0000))?;
+
+        assert_eq!(read_u64(&image, 0x0), Some(0x1_0000_1000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn relocate_malformed() {
+        let table = block(0x1ffe, &[(IMAGE_REL_BASED_HIGHLOW << 12) | 0x0]);
+        let (mut image, table) = image_with(&table);
+        assert!(relocate(&mut image, table, 0x100).is_err());
+
+        let table = block(0x0, &[(7 << 12) | 0x0]);
+        let (mut image, table) = image_with(&table);
+        assert!(relocate(&mut image, table, 0x100).is_err());
+
+        let (mut image, _) = image_with(&[]);
+        assert!(relocate(&mut image, (0x1ff0, 0x100), 0x100).is_err());
+    }
+
+    #[test]
+    fn relocate_stops_at_empty_block() -> Result<(), Error> {
+        let mut table = block(0x0, &[]);
+        table[4..8].copy_from_slice(&0u32.to_le_bytes());
+        table.extend(block(0x0, &[(IMAGE_REL_BASED_HIGHLOW << 12) | 0x0]));
+        let (mut image, table) = image_with(&table);
+
+        relocate(&mut image, table, 0x100)?;
+
+        assert_eq!(read_u32(&image, 0x0), Some(0));
+
+        Ok(())
+    }
+
+    // A PE32 (x86) or PE32+ (x86-64) image with a single `.text` section
+    // of 0x10 bytes at RVA 0x1000, laid out by hand as goblin expects it
+    fn minimal_pe(plus: bool, size_of_image: u32) -> Vec<u8> {
+        let mut bytes = vec![0u8; 0x200];
+
+        bytes[..2].copy_from_slice(b"MZ");
+        bytes[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        bytes[0x40..0x44].copy_from_slice(b"PE\0\0");
+
+        let (machine, magic, optional_size) = if plus {
+            (COFF_MACHINE_X86_64, 0x20bu16, 24 + 88 + 16 * 8)
+        } else {
+            (COFF_MACHINE_X86, 0x10bu16, 28 + 68 + 16 * 8)
+        };
+
+        // COFF header
+        let mut h = Vec::new();
+        h.extend_from_slice(&machine.to_le_bytes());
+        h.extend_from_slice(&1u16.to_le_bytes());
+        h.extend_from_slice(&[0u8; 12]);
+        h.extend_from_slice(&(optional_size as u16).to_le_bytes());
+        h.extend_from_slice(&0x0102u16.to_le_bytes());
+
+        // standard fields
+        h.extend_from_slice(&magic.to_le_bytes());
+        h.extend_from_slice(&[0u8; 2]);
+        h.extend_from_slice(&[0u8; 12]);
+        h.extend_from_slice(&0x1000u32.to_le_bytes());
+        h.extend_from_slice(&0x1000u32.to_le_bytes());
+
+        // windows fields
+        if plus {
+            h.extend_from_slice(&0x1_4000_0000u64.to_le_bytes());
+        } else {
+            h.extend_from_slice(&0u32.to_le_bytes());
+            h.extend_from_slice(&0x40_0000u32.to_le_bytes());
+        }
+        h.extend_from_slice(&0x1000u32.to_le_bytes());
+        h.extend_from_slice(&0x200u32.to_le_bytes());
+        h.extend_from_slice(&[0u8; 16]);
+        h.extend_from_slice(&size_of_image.to_le_bytes());
+        h.extend_from_slice(&0x200u32.to_le_bytes());
+        h.extend_from_slice(&[0u8; 8]);
+        h.extend_from_slice(&vec![0u8; if plus { 32 } else { 16 }]);
+        h.extend_from_slice(&0u32.to_le_bytes());
+        h.extend_from_slice(&16u32.to_le_bytes());
+        h.extend_from_slice(&[0u8; 16 * 8]);
+
+        // section table
+        h.extend_from_slice(b".text\0\0\0");
+        h.extend_from_slice(&0x10u32.to_le_bytes());
+        h.extend_from_slice(&0x1000u32.to_le_bytes());
+        h.extend_from_slice(&0x200u32.to_le_bytes());
+        h.extend_from_slice(&0x200u32.to_le_bytes());
+        h.extend_from_slice(&[0u8; 12]);
+        h.extend_from_slice(&(IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_EXECUTE).to_le_bytes());
+
+        bytes[0x44..0x44 + h.len()].copy_from_slice(&h);
+
+        let mut text = vec![0u8; 0x200];
+        text[..4].copy_from_slice(&[0x90, 0x90, 0x90, 0xc3]);
+        bytes.extend_from_slice(&text);
+
+        bytes
+    }
+
+    fn check_minimal_pe(module: Module, base: u64, language: &str) {
+        assert_eq!(module.language(), Some(language));
+        assert_eq!(module.base(), base);
+        assert_eq!(module.entry(), Some(base + 0x1000));
+        assert_eq!(module.extent(), base..base + 0x2000);
+
+        let (regions, imports, exports) = module.into_parts();
+        assert!(imports.is_empty() && exports.is_empty());
+        assert_eq!(regions.len(), 2);
+
+        let (headers, bytes) = &regions[0];
+        assert_eq!(headers.address(), base);
+        assert_eq!(headers.len(), 0x200);
+        assert_eq!(headers.protection(), Protection::READ);
+        assert_eq!(&bytes[..2], b"MZ");
+
+        let (text, bytes) = &regions[1];
+        assert_eq!(text.name(), ".text");
+        assert_eq!(text.address(), base + 0x1000);
+        assert_eq!(text.len(), 0x1000);
+        assert_eq!(text.protection(), Protection::READ_EXECUTE);
+        assert_eq!(&bytes[..4], &[0x90, 0x90, 0x90, 0xc3]);
+        assert!(bytes[0x10..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn from_pe32() -> Result<(), Error> {
+        let module = Module::from_pe("pe32", &minimal_pe(false, 0x2000), None)?;
+        check_minimal_pe(module, 0x40_0000, "x86:LE:32:default");
+        Ok(())
+    }
+
+    #[test]
+    fn from_pe32_plus() -> Result<(), Error> {
+        let module = Module::from_pe("pe32+", &minimal_pe(true, 0x2000), None)?;
+        check_minimal_pe(module, 0x1_4000_0000, "x86:LE:64:default");
+        Ok(())
+    }
+
+    #[test]
+    fn from_pe_rebase_requires_relocations() {
+        let bytes = minimal_pe(false, 0x2000);
+        assert!(Module::from_pe("pe32", &bytes, Some(0x50_0000)).is_err());
+        assert!(Module::from_pe("pe32", &bytes, Some(0x40_0000)).is_ok());
+    }
+
+    #[test]
+    fn from_pe_bounds_image_size() -> Result<(), Error> {
+        let bytes = minimal_pe(true, u32::MAX);
+        assert!(matches!(Module::from_pe("pe32+", &bytes, None),
+                         Err(Error::ImageTooLarge { size, limit: MAX_IMAGE_SIZE }) if size == u32::MAX as usize));
+
+        // a declared size beyond the last section is not laid out
+        let module = Module::from_pe("pe32", &minimal_pe(false, 0x10_0000), None)?;
+        check_minimal_pe(module, 0x40_0000, "x86:LE:32:default");
+
+        Ok(())
+    }
+
+    #[test]
+    fn align() {
+        assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+        assert_eq!(align_up(0x1000, 0x1000), 0x1000);
+        assert_eq!(align_up(0x1001, 0), 0x1001);
+    }
+}
@@ -0,0 +1,436 @@
+use fugue::bytes::Order;
+use fugue::ir::LanguageDB;
+
+use fuguex_state::paged::PagedState;
+use fuguex_state::pcode::PCodeState;
+
+use std::path::Path;
+
+use crate::image::{MappedImage, Protection, Region};
+use crate::{endian_for, translator_for, Either, Error};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RawFormat {
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+impl RawFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Binary => "binary",
+            Self::IntelHex => "Intel HEX",
+            Self::SRecord => "S-record",
+        }
+    }
+}
+
+/// A firmware image to map. For `RawFormat::Binary` inputs the bytes are
+/// mapped at `base`; for Intel HEX and S-record inputs, `base` is added to
+/// the addresses given by each record.
+#[derive(Debug, Clone)]
+pub struct RawRegion {
+    name: String,
+    bytes: Vec<u8>,
+    base: u64,
+    format: RawFormat,
+    protection: Protection,
+}
+
+impl RawRegion {
+    pub fn new<N: AsRef<str>>(name: N, format: RawFormat, bytes: Vec<u8>, base: u64, protection: Protection) -> Self {
+        Self {
+            name: name.as_ref().to_owned(),
+            bytes,
+            base,
+            format,
+            protection,
+        }
+    }
+
+    pub fn binary(bytes: Vec<u8>, base: u64, protection: Protection) -> Self {
+        Self::new("binary", RawFormat::Binary, bytes, base, protection)
+    }
+
+    pub fn intel_hex(bytes: Vec<u8>, base: u64, protection: Protection) -> Self {
+        Self::new("ihex", RawFormat::IntelHex, bytes, base, protection)
+    }
+
+    pub fn srecord(bytes: Vec<u8>, base: u64, protection: Protection) -> Self {
+        Self::new("srec", RawFormat::SRecord, bytes, base, protection)
+    }
+
+    pub fn from_path<P>(path: P, format: RawFormat, base: u64, protection: Protection) -> Result<Self, Error>
+    where P: AsRef<Path> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format.name().to_owned());
+
+        Ok(Self::new(name, format, bytes, base, protection))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    pub fn format(&self) -> RawFormat {
+        self.format
+    }
+
+    pub fn protection(&self) -> Protection {
+        self.protection
+    }
+
+    // Decode into contiguous chunks and the start address given by the
+    // input (if any).
+    fn decode(&self) -> Result<(Vec<(u64, Vec<u8>)>, Option<u64>), Error> {
+        let (chunks, entry) = match self.format {
+            RawFormat::Binary => (vec![(0, self.bytes.clone())], None),
+            RawFormat::IntelHex => decode_intel_hex(&self.bytes)?,
+            RawFormat::SRecord => decode_srecord(&self.bytes)?,
+        };
+
+        let chunks = chunks.into_iter()
+            .map(|(address, bytes)| (self.base.wrapping_add(address), bytes))
+            .collect();
+
+        Ok((chunks, entry.map(|entry| self.base.wrapping_add(entry))))
+    }
+}
+
+fn push_chunk(chunks: &mut Vec<(u64, Vec<u8>)>, address: u64, data: &[u8]) {
+    if let Some((start, bytes)) = chunks.last_mut() {
+        if *start + bytes.len() as u64 == address {
+            bytes.extend_from_slice(data);
+            return
+        }
+    }
+    chunks.push((address, data.to_vec()));
+}
+
+fn merge_chunks(mut chunks: Vec<(u64, Vec<u8>)>) -> Vec<(u64, Vec<u8>)> {
+    chunks.sort_by_key(|(address, _)| *address);
+
+    let mut merged = Vec::<(u64, Vec<u8>)>::with_capacity(chunks.len());
+    for (address, bytes) in chunks.into_iter() {
+        push_chunk(&mut merged, address, &bytes);
+    }
+    merged
+}
+
+fn decode_record(line: &str) -> Option<Vec<u8>> {
+    if line.len() % 2 != 0 {
+        return None
+    }
+
+    (0..line.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(line.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn decode_intel_hex(bytes: &[u8]) -> Result<(Vec<(u64, Vec<u8>)>, Option<u64>), Error> {
+    let input = String::from_utf8_lossy(bytes);
+
+    let mut chunks = Vec::default();
+    let mut entry = None;
+    let mut upper = 0u64;
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+
+        let malformed = || Error::MalformedRecord { format: "Intel HEX", line: index + 1 };
+
+        let record = line.strip_prefix(':')
+            .and_then(decode_record)
+            .ok_or_else(malformed)?;
+
+        if record.len() < 5 || record.len() != 5 + record[0] as usize {
+            return Err(malformed())
+        }
+
+        if record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0 {
+            return Err(malformed())
+        }
+
+        let offset = u16::from_be_bytes([record[1], record[2]]) as u64;
+        let data = &record[4..record.len() - 1];
+
+        match record[3] {
+            0x00 => push_chunk(&mut chunks, upper + offset, data),
+            0x01 => break,
+            0x02 if data.len() == 2 => {
+                upper = (u16::from_be_bytes([data[0], data[1]]) as u64) << 4;
+            },
+            0x03 if data.len() == 4 => {
+                let cs = u16::from_be_bytes([data[0], data[1]]) as u64;
+                let ip = u16::from_be_bytes([data[2], data[3]]) as u64;
+                entry = Some((cs << 4) + ip);
+            },
+            0x04 if data.len() == 2 => {
+                upper = (u16::from_be_bytes([data[0], data[1]]) as u64) << 16;
+            },
+            0x05 if data.len() == 4 => {
+                entry = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as u64);
+            },
+            _ => return Err(malformed()),
+        }
+    }
+
+    Ok((merge_chunks(chunks), entry))
+}
+
+fn decode_srecord(bytes: &[u8]) -> Result<(Vec<(u64, Vec<u8>)>, Option<u64>), Error> {
+    let input = String::from_utf8_lossy(bytes);
+
+    let mut chunks = Vec::default();
+    let mut entry = None;
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+
+        let malformed = || Error::MalformedRecord { format: "S-record", line: index + 1 };
+
+        let kind = line.strip_prefix('S')
+            .and_then(|line| line.chars().next())
+            .ok_or_else(malformed)?;
+
+        let record = line.get(2..)
+            .and_then(decode_record)
+            .ok_or_else(malformed)?;
+
+        if record.is_empty() || record.len() != 1 + record[0] as usize {
+            return Err(malformed())
+        }
+
+        if record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0xff {
+            return Err(malformed())
+        }
+
+        let address_size = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(malformed()),
+        };
+
+        if record.len() < 2 + address_size {
+            return Err(malformed())
+        }
+
+        let address = record[1..1 + address_size]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let data = &record[1 + address_size..record.len() - 1];
+
+        match kind {
+            '1' | '2' | '3' => push_chunk(&mut chunks, address, data),
+            '7' | '8' | '9' => { entry = Some(address); },
+            _ => (), // header and record counts
+        }
+    }
+
+    Ok((merge_chunks(chunks), entry))
+}
+
+impl MappedImage<PagedState<u8>> {
+    /// Map raw firmware images for the language given by its tag, e.g.,
+    /// `ARM:LE:32:Cortex`. If `entry` is `None`, the start address given by
+    /// the first Intel HEX or S-record input that provides one is used.
+    pub fn from_raw_regions<L, I>(language_db: &LanguageDB, language: L, regions: I, entry: Option<u64>) -> Result<Self, Error>
+    where L: AsRef<str>,
+          I: IntoIterator<Item = RawRegion> {
+        let language = language.as_ref();
+        let translator = translator_for(language_db, language)?;
+        let endian = endian_for(language)?;
+
+        let mut mapped = Vec::default();
+        let mut base = None;
+        let mut start = None;
+
+        for region in regions.into_iter() {
+            let (chunks, region_entry) = region.decode()?;
+
+            start = start.or(region_entry);
+
+            let count = chunks.len();
+            for (index, (address, bytes)) in chunks.into_iter().enumerate() {
+                let name = if count == 1 {
+                    region.name().to_owned()
+                } else {
+                    format!("{}.{}", region.name(), index)
+                };
+
+                base = Some(base.map_or(address, |base: u64| base.min(address)));
+                mapped.push((Region::new(name, address, bytes.len(), region.protection()), bytes));
+            }
+        }
+
+        MappedImage::from_regions(translator, endian, base.unwrap_or(0), entry.or(start), mapped)
+    }
+}
+
+impl<O: Order> MappedImage<PCodeState<u8, O>> {
+    /// Map raw firmware images as `MappedImage::from_raw_regions`, and
+    /// build a `PCodeState` for the calling convention given by its name,
+    /// e.g., `default`.
+    pub fn from_raw<L, C, I>(language_db: &LanguageDB, language: L, convention: C, regions: I, entry: Option<u64>) -> Result<Self, Error>
+    where L: AsRef<str>,
+          C: AsRef<str>,
+          I: IntoIterator<Item = RawRegion> {
+        let convention = convention.as_ref();
+        let image = MappedImage::from_raw_regions(language_db, language, regions, entry)?;
+
+        match image.pcode_state_with(convention) {
+            Either::Left(image) => Ok(image),
+            Either::Right(_) => Err(Error::UnsupportedConvention(convention.to_owned())),
+        }
+    }
+
+    pub fn from_raw_paths<L, C, P, I>(language_db: &LanguageDB, language: L, convention: C, regions: I, entry: Option<u64>) -> Result<Self, Error>
+    where L: AsRef<str>,
+          C: AsRef<str>,
+          P: AsRef<Path>,
+          I: IntoIterator<Item = (P, RawFormat, u64, Protection)> {
+        let regions = regions.into_iter()
+            .map(|(path, format, base, protection)| RawRegion::from_path(path, format, base, protection))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_raw(language_db, language, convention, regions, entry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ihex(kind: u8, offset: u16, data: &[u8]) -> String {
+        let mut record = vec![data.len() as u8];
+        record.extend_from_slice(&offset.to_be_bytes());
+        record.push(kind);
+        record.extend_from_slice(data);
+        record.push(record.iter().fold(0u8, |acc, b| acc.wrapping_sub(*b)));
+        record.iter().fold(String::from(":"), |acc, b| acc + &format!("{:02X}", b))
+    }
+
+    fn srec(kind: char, address: &[u8], data: &[u8]) -> String {
+        let mut record = vec![(address.len() + data.len() + 1) as u8];
+        record.extend_from_slice(address);
+        record.extend_from_slice(data);
+        record.push(!record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)));
+        record.iter().fold(format!("S{}", kind), |acc, b| acc + &format!("{:02X}", b))
+    }
+
+    fn malformed_line(result: Result<(Vec<(u64, Vec<u8>)>, Option<u64>), Error>) -> Option<usize> {
+        match result {
+            Err(Error::MalformedRecord { line, .. }) => Some(line),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn intel_hex_records() -> Result<(), Error> {
+        assert_eq!(ihex(0x00, 0, &[1, 2, 3, 4]), ":0400000001020304F2");
+
+        let input = [
+            ":020000040001F9".to_owned(),
+            ":0400000001020304F2".to_owned(),
+            ihex(0x00, 4, &[5, 6]),
+            ihex(0x00, 0x10, &[7]),
+            ":0400000500001000E7".to_owned(),
+            ":00000001FF".to_owned(),
+            ihex(0x00, 0x20, &[8]),
+        ].join("\r\n");
+
+        let (chunks, entry) = decode_intel_hex(input.as_bytes())?;
+
+        assert_eq!(chunks, vec![(0x10000, vec![1, 2, 3, 4, 5, 6]), (0x10010, vec![7])]);
+        assert_eq!(entry, Some(0x1000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn intel_hex_segments() -> Result<(), Error> {
+        let input = [
+            ihex(0x02, 0, &[0x10, 0x00]),
+            ihex(0x00, 0x10, &[1]),
+            ihex(0x03, 0, &[0x10, 0x00, 0x00, 0x20]),
+        ].join("\n");
+
+        let (chunks, entry) = decode_intel_hex(input.as_bytes())?;
+
+        assert_eq!(chunks, vec![(0x10010, vec![1])]);
+        assert_eq!(entry, Some(0x10020));
+
+        Ok(())
+    }
+
+    #[test]
+    fn intel_hex_malformed() {
+        // bad checksum
+        assert_eq!(malformed_line(decode_intel_hex(b":0400000001020304F3")), Some(1));
+        // length disagrees with count
+        assert_eq!(malformed_line(decode_intel_hex(b"\n:0500000001020304F1")), Some(2));
+        // missing start code, odd digits, non-hex digits
+        assert_eq!(malformed_line(decode_intel_hex(b"0400000001020304F2")), Some(1));
+        assert_eq!(malformed_line(decode_intel_hex(b":0400000001020304F")), Some(1));
+        assert_eq!(malformed_line(decode_intel_hex(b":04000000010203G4F2")), Some(1));
+        // extended address with the wrong length, unknown record type
+        assert_eq!(malformed_line(decode_intel_hex(ihex(0x04, 0, &[1]).as_bytes())), Some(1));
+        assert_eq!(malformed_line(decode_intel_hex(ihex(0x06, 0, &[]).as_bytes())), Some(1));
+    }
+
+    #[test]
+    fn srecord_records() -> Result<(), Error> {
+        assert_eq!(srec('1', &[0, 0], &[1, 2, 3, 4]), "S107000001020304EE");
+
+        let input = [
+            srec('0', &[0, 0], b"hdr"),
+            "S107000001020304EE".to_owned(),
+            srec('2', &[0x00, 0x00, 0x04], &[5]),
+            srec('3', &[0x00, 0x01, 0x00, 0x00], &[6, 7]),
+            srec('5', &[0, 3], &[]),
+            srec('7', &[0x00, 0x00, 0x10, 0x00], &[]),
+        ].join("\n");
+
+        let (chunks, entry) = decode_srecord(input.as_bytes())?;
+
+        assert_eq!(chunks, vec![(0x0, vec![1, 2, 3, 4, 5]), (0x10000, vec![6, 7])]);
+        assert_eq!(entry, Some(0x1000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn srecord_malformed() {
+        // bad checksum
+        assert_eq!(malformed_line(decode_srecord(b"S107000001020304EF")), Some(1));
+        // count disagrees with length
+        assert_eq!(malformed_line(decode_srecord(b"S108000001020304EE")), Some(1));
+        // unknown type, missing prefix
+        assert_eq!(malformed_line(decode_srecord(srec('4', &[0, 0], &[]).as_bytes())), Some(1));
+        assert_eq!(malformed_line(decode_srecord(b"\nX107000001020304EE")), Some(2));
+        // shorter than its address
+        assert_eq!(malformed_line(decode_srecord(srec('3', &[0, 0], &[]).as_bytes())), Some(1));
+    }
+
+    #[test]
+    fn chunks_merge() {
+        let chunks = vec![(0x10, vec![3]), (0x0, vec![1]), (0x1, vec![2])];
+        assert_eq!(merge_chunks(chunks), vec![(0x0, vec![1, 2]), (0x10, vec![3])]);
+    }
+}
@@ -0,0 +1,567 @@
+use fugue::bytes::Order;
+use fugue::ir::Address;
+
+use fuguex_state::pcode::PCodeState;
+use fuguex_state::StateOps;
+
+use crate::image::MappedImage;
+use crate::Error;
+
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_BASE: u64 = 7;
+pub const AT_FLAGS: u64 = 8;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_UID: u64 = 11;
+pub const AT_EUID: u64 = 12;
+pub const AT_GID: u64 = 13;
+pub const AT_EGID: u64 = 14;
+pub const AT_PLATFORM: u64 = 15;
+pub const AT_HWCAP: u64 = 16;
+pub const AT_CLKTCK: u64 = 17;
+pub const AT_SECURE: u64 = 23;
+pub const AT_RANDOM: u64 = 25;
+pub const AT_EXECFN: u64 = 31;
+
+const PT_LOAD: u32 = 1;
+const PT_PHDR: u32 = 6;
+
+pub const DEFAULT_STACK_SIZE: usize = 0x100000;
+pub const DEFAULT_STACK_TOP_32: u64 = 0xc000_0000;
+pub const DEFAULT_STACK_TOP_64: u64 = 0x7fff_ffff_f000;
+
+/// Builds the initial process stack in the layout expected by the System V
+/// ABI; from the stack pointer upwards: argc, argv, envp, auxv, followed by
+/// the strings and `AT_RANDOM` bytes referred to by them.
+#[derive(Debug, Clone)]
+pub struct ProcessEnvironment {
+    arguments: Vec<Vec<u8>>,
+    environment: Vec<Vec<u8>>,
+    auxiliary: Vec<(u64, u64)>,
+    platform: Option<Vec<u8>>,
+    random: [u8; 16],
+    entry: Option<u64>,
+    stack_top: Option<u64>,
+    stack_size: usize,
+    alignment: u64,
+}
+
+impl Default for ProcessEnvironment {
+    fn default() -> Self {
+        Self {
+            arguments: Vec::default(),
+            environment: Vec::default(),
+            auxiliary: Vec::default(),
+            platform: None,
+            random: *b"fuguex-at-random",
+            entry: None,
+            stack_top: None,
+            stack_size: DEFAULT_STACK_SIZE,
+            alignment: 16,
+        }
+    }
+}
+
+impl ProcessEnvironment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the entry point of `image` as the initial program counter and
+    /// `AT_ENTRY`; if `image` is an ELF whose headers are mapped at its
+    /// base, `AT_PHDR`, `AT_PHENT` and `AT_PHNUM` are derived from them.
+    pub fn for_image<S>(image: &MappedImage<S>) -> Self
+    where S: StateOps<Value = u8> {
+        let mut environment = Self::default();
+        environment.entry = image.entry();
+
+        let state = image.state();
+        let read = |address: u64, bytes: &mut [u8]| state.get_values(Address::from(address), bytes).is_ok();
+
+        if let Some((address, entry_size, count)) = program_headers(image.base(), read) {
+            environment.program_headers(address, entry_size, count);
+        }
+
+        environment
+    }
+
+    pub fn arg<A: AsRef<[u8]>>(&mut self, argument: A) -> &mut Self {
+        self.arguments.push(argument.as_ref().to_vec());
+        self
+    }
+
+    pub fn args<I, A>(&mut self, arguments: I) -> &mut Self
+    where I: IntoIterator<Item = A>,
+          A: AsRef<[u8]> {
+        for argument in arguments.into_iter() {
+            self.arg(argument);
+        }
+        self
+    }
+
+    pub fn env<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where K: AsRef<[u8]>,
+          V: AsRef<[u8]> {
+        let mut variable = key.as_ref().to_vec();
+        variable.push(b'=');
+        variable.extend_from_slice(value.as_ref());
+        self.environment.push(variable);
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, variables: I) -> &mut Self
+    where I: IntoIterator<Item = (K, V)>,
+          K: AsRef<[u8]>,
+          V: AsRef<[u8]> {
+        for (key, value) in variables.into_iter() {
+            self.env(key, value);
+        }
+        self
+    }
+
+    /// Add (or replace) an auxiliary vector entry; `AT_RANDOM`,
+    /// `AT_PLATFORM` and `AT_EXECFN` are always derived from the other
+    /// settings.
+    pub fn auxv(&mut self, key: u64, value: u64) -> &mut Self {
+        if let Some(entry) = self.auxiliary.iter_mut().find(|(k, _)| *k == key) {
+            entry.1 = value;
+        } else {
+            self.auxiliary.push((key, value));
+        }
+        self
+    }
+
+    pub fn program_headers(&mut self, address: u64, entry_size: u64, count: u64) -> &mut Self {
+        self.auxv(AT_PHDR, address)
+            .auxv(AT_PHENT, entry_size)
+            .auxv(AT_PHNUM, count)
+    }
+
+    pub fn platform<P: AsRef<[u8]>>(&mut self, platform: P) -> &mut Self {
+        self.platform = Some(platform.as_ref().to_vec());
+        self
+    }
+
+    pub fn random(&mut self, bytes: [u8; 16]) -> &mut Self {
+        self.random = bytes;
+        self
+    }
+
+    pub fn entry(&mut self, entry: u64) -> &mut Self {
+        self.entry = Some(entry);
+        self
+    }
+
+    /// Map the stack below `top`; by default it is placed below
+    /// `DEFAULT_STACK_TOP_32` or `DEFAULT_STACK_TOP_64`.
+    pub fn stack(&mut self, top: u64, size: usize) -> &mut Self {
+        self.stack_top = Some(top);
+        self.stack_size = size;
+        self
+    }
+
+    /// The alignment of the initial stack pointer; 16 bytes by default.
+    pub fn alignment(&mut self, alignment: u64) -> &mut Self {
+        self.alignment = alignment.max(1);
+        self
+    }
+
+    fn auxiliary_vector(&self, random: u64, platform: Option<u64>, execfn: Option<u64>) -> Vec<(u64, u64)> {
+        let mut auxv = vec![
+            (AT_PAGESZ, 4096),
+            (AT_CLKTCK, 100),
+            (AT_FLAGS, 0),
+            (AT_UID, 0),
+            (AT_EUID, 0),
+            (AT_GID, 0),
+            (AT_EGID, 0),
+            (AT_SECURE, 0),
+        ];
+
+        if let Some(entry) = self.entry {
+            auxv.push((AT_ENTRY, entry));
+        }
+
+        for (key, value) in self.auxiliary.iter() {
+            if let Some(entry) = auxv.iter_mut().find(|(k, _)| k == key) {
+                entry.1 = *value;
+            } else {
+                auxv.push((*key, *value));
+            }
+        }
+
+        auxv.retain(|(k, _)| *k != AT_RANDOM && *k != AT_PLATFORM && *k != AT_EXECFN);
+
+        auxv.push((AT_RANDOM, random));
+        if let Some(platform) = platform {
+            auxv.push((AT_PLATFORM, platform));
+        }
+        if let Some(execfn) = execfn {
+            auxv.push((AT_EXECFN, execfn));
+        }
+
+        auxv.push((AT_NULL, 0));
+        auxv
+    }
+
+    // Lay out the process environment below `top`; returns the initial
+    // stack pointer and the bytes from it up to `top`
+    fn layout(&self, top: u64, pointer_size: usize, big_endian: bool) -> Result<(u64, Vec<u8>), Error> {
+        let mask = if pointer_size >= 8 { u64::MAX } else { (1u64 << (pointer_size * 8)) - 1 };
+
+        let bottom = top.checked_sub(self.stack_size as u64)
+            .ok_or(Error::StackSize { top, size: self.stack_size })?;
+
+        // strings and random bytes are placed at the top of the stack, and
+        // are collected in reverse
+        let mut strings = Vec::<u8>::new();
+        let mut cursor = top;
+
+        let mut push_bytes = |bytes: &[u8], nul: bool| -> Result<u64, Error> {
+            let size = bytes.len() as u64 + if nul { 1 } else { 0 };
+            cursor = cursor.checked_sub(size)
+                .filter(|cursor| *cursor >= bottom)
+                .ok_or(Error::StackExhausted(self.stack_size))?;
+            if nul {
+                strings.push(0);
+            }
+            strings.extend(bytes.iter().rev());
+            Ok(cursor)
+        };
+
+        let execfn = self.arguments.first()
+            .map(|argument| push_bytes(argument, true))
+            .transpose()?;
+
+        let environment = self.environment.iter().rev()
+            .map(|variable| push_bytes(variable, true))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>();
+
+        let arguments = self.arguments.iter().rev()
+            .map(|argument| push_bytes(argument, true))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>();
+
+        let platform = self.platform.as_ref()
+            .map(|platform| push_bytes(platform, true))
+            .transpose()?;
+
+        let random = push_bytes(&self.random, false)?;
+
+        let auxv = self.auxiliary_vector(random, platform, execfn);
+
+        // argc + argv + NULL + envp + NULL + auxv pairs
+        let words = 1 + arguments.len() + 1 + environment.len() + 1 + 2 * auxv.len();
+
+        let stack_pointer = cursor.checked_sub((words * pointer_size) as u64)
+            .map(|address| address / self.alignment * self.alignment)
+            .filter(|address| *address >= bottom)
+            .ok_or(Error::StackExhausted(self.stack_size))?;
+
+        let values = std::iter::once(arguments.len() as u64)
+            .chain(arguments.iter().copied())
+            .chain(std::iter::once(0))
+            .chain(environment.iter().copied())
+            .chain(std::iter::once(0))
+            .chain(auxv.iter().flat_map(|(k, v)| [*k, *v]));
+
+        let mut bytes = Vec::with_capacity((top - stack_pointer) as usize);
+        for value in values {
+            let value = value & mask;
+            if big_endian {
+                bytes.extend_from_slice(&value.to_be_bytes()[8 - pointer_size..]);
+            } else {
+                bytes.extend_from_slice(&value.to_le_bytes()[..pointer_size]);
+            }
+        }
+
+        // padding between the vectors and the strings
+        bytes.resize((cursor - stack_pointer) as usize, 0);
+        bytes.extend(strings.iter().rev());
+
+        Ok((stack_pointer, bytes))
+    }
+
+    /// Map the stack into `state`, lay out the process environment and set
+    /// the stack pointer (and program counter, if an entry point is known);
+    /// returns the initial stack pointer.
+    pub fn build<O: Order>(&self, state: &mut PCodeState<u8, O>) -> Result<u64, Error> {
+        let space = state.memory_space();
+        let at = |address: u64| Address::new(&*space, address);
+
+        let pointer_size = space.address_size();
+
+        let top = self.stack_top.unwrap_or(if pointer_size >= 8 {
+            DEFAULT_STACK_TOP_64
+        } else {
+            DEFAULT_STACK_TOP_32
+        });
+
+        let (stack_pointer, bytes) = self.layout(top, pointer_size, O::ENDIAN.is_big())?;
+
+        let bottom = top - self.stack_size as u64;
+        state.memory_mut().static_mapping("[stack]", at(bottom), self.stack_size)?;
+        state.set_values(at(stack_pointer), &bytes)?;
+
+        state.set_stack_pointer_value(at(stack_pointer))?;
+
+        if let Some(entry) = self.entry {
+            state.set_program_counter_value(at(entry))?;
+        }
+
+        Ok(stack_pointer)
+    }
+}
+
+// Read an unsigned integer of `size` bytes
+fn read_uint<R>(read: &R, address: u64, size: usize, big_endian: bool) -> Option<u64>
+where R: Fn(u64, &mut [u8]) -> bool {
+    let mut buf = [0u8; 8];
+    let bytes = &mut buf[..size];
+    if !read(address, bytes) {
+        return None
+    }
+
+    Some(if big_endian {
+        bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+    } else {
+        bytes.iter().rev().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+    })
+}
+
+// The address, entry size and count of the program headers of an ELF
+// image whose headers are mapped at `base`
+fn program_headers<R>(base: u64, read: R) -> Option<(u64, u64, u64)>
+where R: Fn(u64, &mut [u8]) -> bool {
+    let mut ident = [0u8; 6];
+    if !read(base, &mut ident) || &ident[..4] != b"\x7fELF" {
+        return None
+    }
+
+    let is_64 = match ident[4] {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+
+    let big_endian = match ident[5] {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+
+    let word = if is_64 { 8 } else { 4 };
+    let (phoff, phentsize, phnum) = if is_64 { (0x20, 0x36, 0x38) } else { (0x1c, 0x2a, 0x2c) };
+
+    let offset = read_uint(&read, base + phoff, word, big_endian)?;
+    let entry_size = read_uint(&read, base + phentsize, 2, big_endian)?;
+    let count = read_uint(&read, base + phnum, 2, big_endian)?;
+
+    let table = base.checked_add(offset)?;
+
+    // the address of the table is given by PT_PHDR, relative to the lowest
+    // PT_LOAD, which is mapped at `base`; without it, the table is assumed
+    // to be mapped with the headers
+    let mut phdr = None;
+    let mut lowest = None::<u64>;
+
+    for i in 0..count {
+        let header = table.checked_add(i * entry_size)?;
+        let kind = read_uint(&read, header, 4, big_endian)? as u32;
+        let vaddr = read_uint(&read, header + 2 * word as u64, word, big_endian)?;
+
+        match kind {
+            PT_PHDR => phdr = Some(vaddr),
+            PT_LOAD => lowest = Some(lowest.map_or(vaddr, |lowest| lowest.min(vaddr))),
+            _ => (),
+        }
+    }
+
+    let address = match (phdr, lowest) {
+        (Some(phdr), Some(lowest)) => base.wrapping_add(phdr.wrapping_sub(lowest & !0xfff)),
+        _ => table,
+    };
+
+    Some((address, entry_size, count))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn word(bytes: &[u8], index: usize, size: usize, big_endian: bool) -> u64 {
+        let bytes = &bytes[index * size..(index + 1) * size];
+        if big_endian {
+            bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+        } else {
+            bytes.iter().rev().fold(0u64, |acc, b| (acc << 8) | *b as u64)
+        }
+    }
+
+    fn string(bytes: &[u8], top: u64, address: u64) -> &[u8] {
+        let start = bytes.len() - (top - address) as usize;
+        let end = start + bytes[start..].iter().position(|b| *b == 0).unwrap();
+        &bytes[start..end]
+    }
+
+    fn environment() -> ProcessEnvironment {
+        let mut environment = ProcessEnvironment::new();
+        environment
+            .args(["/bin/true", "-v"])
+            .env("HOME", "/root")
+            .platform("x86_64")
+            .entry(0x40_1000)
+            .program_headers(0x40_0040, 0x38, 9);
+        environment
+    }
+
+    fn check_layout(pointer_size: usize, big_endian: bool) -> Result<(), Error> {
+        let top = 0x7fff_f000;
+        let (stack_pointer, bytes) = environment().layout(top, pointer_size, big_endian)?;
+
+        assert_eq!(stack_pointer % 16, 0);
+        assert_eq!(stack_pointer + bytes.len() as u64, top);
+
+        let word = |index| word(&bytes, index, pointer_size, big_endian);
+
+        // argc, argv
+        assert_eq!(word(0), 2);
+        assert_eq!(string(&bytes, top, word(1)), b"/bin/true");
+        assert_eq!(string(&bytes, top, word(2)), b"-v");
+        assert_eq!(word(3), 0);
+
+        // envp
+        assert_eq!(string(&bytes, top, word(4)), b"HOME=/root");
+        assert_eq!(word(5), 0);
+
+        // auxv
+        let auxv = (6..).step_by(2)
+            .map(|i| (word(i), word(i + 1)))
+            .take_while(|(k, _)| *k != AT_NULL)
+            .collect::<Vec<_>>();
+
+        let value = |key| auxv.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+
+        assert_eq!(value(AT_PAGESZ), Some(4096));
+        assert_eq!(value(AT_ENTRY), Some(0x40_1000));
+        assert_eq!(value(AT_PHDR), Some(0x40_0040));
+        assert_eq!(value(AT_PHENT), Some(0x38));
+        assert_eq!(value(AT_PHNUM), Some(9));
+        assert_eq!(string(&bytes, top, value(AT_PLATFORM).unwrap()), b"x86_64");
+        assert_eq!(string(&bytes, top, value(AT_EXECFN).unwrap()), b"/bin/true");
+
+        let random = value(AT_RANDOM).unwrap();
+        let start = bytes.len() - (top - random) as usize;
+        assert_eq!(&bytes[start..start + 16], b"fuguex-at-random");
+
+        Ok(())
+    }
+
+    #[test]
+    fn layout_64() -> Result<(), Error> {
+        check_layout(8, false)
+    }
+
+    #[test]
+    fn layout_32_big_endian() -> Result<(), Error> {
+        check_layout(4, true)
+    }
+
+    #[test]
+    fn layout_alignment() -> Result<(), Error> {
+        let mut environment = environment();
+
+        environment.alignment(64);
+        let (stack_pointer, _) = environment.layout(0x7fff_f000, 8, false)?;
+        assert_eq!(stack_pointer % 64, 0);
+
+        // no alignment is treated as byte alignment
+        environment.alignment(0);
+        assert!(environment.layout(0x7fff_f003, 8, false).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn layout_exhausted() {
+        let mut environment = environment();
+
+        environment.stack(0x1000, 0x40);
+        assert!(matches!(environment.layout(0x1000, 8, false), Err(Error::StackExhausted(0x40))));
+
+        environment.stack(0x1000, 0x2000);
+        assert!(matches!(environment.layout(0x1000, 8, false), Err(Error::StackSize { .. })));
+    }
+
+    // An ELF header with `PT_LOAD` at `vaddr` and a `PT_PHDR` for the
+    // program header table at offset 0x40
+    fn elf_headers(is_64: bool, big_endian: bool, vaddr: u64) -> Vec<u8> {
+        let mut bytes = vec![0u8; 0x200];
+        bytes[..4].copy_from_slice(b"\x7fELF");
+        bytes[4] = if is_64 { 2 } else { 1 };
+        bytes[5] = if big_endian { 2 } else { 1 };
+
+        let mut put = |offset: usize, size: usize, value: u64| {
+            let value = if big_endian {
+                value.to_be_bytes()[8 - size..].to_vec()
+            } else {
+                value.to_le_bytes()[..size].to_vec()
+            };
+            bytes[offset..offset + size].copy_from_slice(&value);
+        };
+
+        let (word, entry_size) = if is_64 { (8, 0x38) } else { (4, 0x20) };
+        if is_64 {
+            put(0x20, 8, 0x40);
+            put(0x36, 2, entry_size);
+            put(0x38, 2, 2);
+        } else {
+            put(0x1c, 4, 0x40);
+            put(0x2a, 2, entry_size);
+            put(0x2c, 2, 2);
+        }
+
+        let phdr = 0x40;
+        put(phdr, 4, PT_PHDR as u64);
+        put(phdr + 2 * word, word, vaddr + 0x40);
+
+        let load = 0x40 + entry_size as usize;
+        put(load, 4, PT_LOAD as u64);
+        put(load + 2 * word, word, vaddr);
+
+        bytes
+    }
+
+    fn reader(base: u64, bytes: Vec<u8>) -> impl Fn(u64, &mut [u8]) -> bool {
+        move |address, buf| {
+            address.checked_sub(base)
+                .map(|offset| offset as usize)
+                .and_then(|offset| bytes.get(offset..offset + buf.len()))
+                .map(|bytes| buf.copy_from_slice(bytes))
+                .is_some()
+        }
+    }
+
+    #[test]
+    fn elf_program_headers() {
+        // an executable mapped at its link address
+        let read = reader(0x40_0000, elf_headers(true, false, 0x40_0000));
+        assert_eq!(program_headers(0x40_0000, read), Some((0x40_0040, 0x38, 2)));
+
+        // a position-independent executable mapped at a bias
+        let read = reader(0x5555_0000, elf_headers(false, true, 0));
+        assert_eq!(program_headers(0x5555_0000, read), Some((0x5555_0040, 0x20, 2)));
+
+        // not an ELF
+        let read = reader(0x1000, vec![0u8; 0x100]);
+        assert_eq!(program_headers(0x1000, read), None);
+    }
+}
@@ -65,8 +65,25 @@ impl<I> Machine<I> where I: Interpreter {
             OrOutcome::Halt(outcome) => return Ok(StepOutcome::Halt(outcome)),
         }
 
+        // Delay slots execute as part of the instruction, but are reported
+        // as instructions in their own right when it is entered; a branch
+        // back to its start from within it does not enter it again
+        if location.position() == 0 {
+            if let Some(delay_slot) = self.step_state.delay_slot() {
+                match self.interpreter.delay_slot(&delay_slot, &self.step_state)? {
+                    OrOutcome::Branch(location) => return self.step(location),
+                    OrOutcome::Halt(outcome) => return Ok(StepOutcome::Halt(outcome)),
+                    OrOutcome::Continue(_) => (),
+                }
+            }
+        }
+
         while let Some(op) = self.step_state.current() {
             match self.interpreter.operation(&self.step_state.location(), op)? {
+                OrOutcome::Branch(location) if *location.address() == self.step_state.address() => {
+                    self.step_state = self.step_state.clone().with_location(&location);
+                    continue
+                },
                 OrOutcome::Branch(location) => return self.step(location),
                 OrOutcome::Halt(outcome) => return Ok(StepOutcome::Halt(outcome)),
                 OrOutcome::Continue(_) => (),
@@ -286,6 +303,10 @@ impl<I> Machine<I> where I: Interpreter {
                     }
                 },
                 Ok(action) => {
+                    if let Some(outcome) = self.interpreter.halted() {
+                        return Ok(StepOutcome::Halt(outcome))
+                    }
+
                     match action {
                         Outcome::Halt(outcome) => {
                             return Ok(StepOutcome::Halt(outcome))
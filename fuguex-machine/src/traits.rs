@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use fugue::ir::{AddressSpace, IntoAddress};
+use fugue::ir::{AddressSpace, AddressValue, IntoAddress};
 use fugue::ir::il::Location;
 use fugue::ir::il::pcode::{Operand, PCodeOp};
 use fugue::ir::space::AddressSpaceId;
@@ -104,5 +104,21 @@ pub trait Interpreter {
         Ok(().into())
     }
 
+    /// Called after each operation executes; returns the outcome to halt
+    /// with, if the operation requested one, e.g., through a hook on one
+    /// of its operands.
+    fn halted(&mut self) -> Option<Self::Outcome> {
+        None
+    }
+
+    /// Called with the address of the delay slot instruction of `step`
+    /// when `step` is entered, before it is executed; as the delay slot's
+    /// p-code is part of `step`'s, this should only report it, e.g., to
+    /// hooks.
+    #[allow(unused)]
+    fn delay_slot(&mut self, address: &AddressValue, step: &StepState) -> Result<OrOutcome<(), Self::Outcome>, Self::Error> {
+        Ok(().into())
+    }
+
     fn interpreter_space(&self) -> Arc<AddressSpace>;
 }
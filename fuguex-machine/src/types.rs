@@ -103,6 +103,7 @@ pub enum BranchOutcome {
 pub struct StepState {
     pcode: Arc<PCode>,
     position: usize,
+    delay_slot: Option<usize>,
 }
 
 impl From<PCode> for StepState {
@@ -110,6 +111,7 @@ impl From<PCode> for StepState {
         Self {
             pcode: Arc::new(pcode),
             position: 0,
+            delay_slot: None,
         }
     }
 }
@@ -138,7 +140,28 @@ impl StepState {
         self.pcode.operations().get(self.position)
     }
 
+    /// The minimum length in bytes of the delay slot instructions
+    /// following this instruction, as declared by SLEIGH's `delayslot`;
+    /// their p-code is included in its own.
+    pub fn delay_slots(&self) -> usize {
+        self.pcode.delay_slots()
+    }
+
+    /// Set the offset of the first delay slot instruction from this
+    /// instruction; as the lifted length covers both, it is found by the
+    /// interpreter.
+    pub fn with_delay_slot(self, offset: usize) -> Self {
+        Self { delay_slot: Some(offset), ..self }
+    }
+
+    /// The address of the first delay slot instruction, if any.
+    pub fn delay_slot(&self) -> Option<AddressValue> {
+        self.delay_slot.map(|offset| self.address() + offset)
+    }
+
+    /// The address following this instruction and its delay slots.
     pub fn fallthrough(&self) -> AddressValue {
+        // the lifted length already includes the delay slots
         self.address() + self.pcode.length()
     }
 
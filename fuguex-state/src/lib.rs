@@ -4,6 +4,10 @@ pub mod paged;
 pub mod pcode;
 pub mod register;
 pub mod unique;
+pub mod vfs;
 
 pub mod traits;
 pub use self::traits::*;
+
+#[cfg(test)]
+mod testing;
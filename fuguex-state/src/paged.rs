@@ -91,10 +91,12 @@ pub enum MappingRef<'a, T: StateValue> {
     Static(&'a FlatState<T>),
 }
 
-#[derive(Debug, Clone)]
+/// A mutable view of a mapping, e.g., to allocate from a dynamic mapping;
+/// unlike `MappingRef`, it cannot be cloned.
+#[derive(Debug)]
 pub enum MappingMut<'a, T: StateValue> {
-    Dynamic(&'a ChunkState<T>),
-    Static(&'a FlatState<T>),
+    Dynamic(&'a mut ChunkState<T>),
+    Static(&'a mut FlatState<T>),
 }
 
 impl<T: StateValue> Segment<T> {
@@ -10,7 +10,7 @@ use fugue::ir::{Address, AddressSpace, Translator};
 use thiserror::Error;
 
 use crate::paged::{self, PagedState};
-use crate::register::{self, RegisterState};
+use crate::register::{self, ParameterLocation, RegisterState};
 use crate::unique::{self, UniqueState};
 
 use crate::traits::{State, StateOps, StateValue};
@@ -32,6 +32,10 @@ pub enum Error {
     Temporary(unique::Error),
     #[error("unsupported addess size of `{0}` bytes")]
     UnsupportedAddressSize(usize),
+    #[error("calling convention has no location for argument {0}")]
+    UnsupportedArgument(usize),
+    #[error("calling convention has no location for a return value of {0} bytes")]
+    UnsupportedReturnValue(usize),
 }
 
 #[derive(Debug, Clone)]
@@ -273,6 +277,139 @@ impl<O: Order> PCodeState<u8, O> {
 
         Ok(())
     }
+
+    fn parameter_operand(&self, location: &ParameterLocation) -> Result<Operand, Error> {
+        Ok(match location {
+            ParameterLocation::Register(operand) => operand.clone(),
+            ParameterLocation::RegisterPair(_, low) => low.clone(),
+            ParameterLocation::Relative(stack_pointer, offset) => {
+                let address = self.get_address(stack_pointer)? + *offset;
+                Operand::Address {
+                    value: address,
+                    size: self.memory_space_ref().address_size(),
+                }
+            },
+        })
+    }
+
+    fn get_value(&self, operand: &Operand) -> Result<u64, Error> {
+        self.with_operand_values(operand, |values| {
+            let mut buf = [0u8; 8];
+            let size = values.len().min(8);
+            if O::ENDIAN.is_big() {
+                buf[8 - size..].copy_from_slice(&values[values.len() - size..]);
+                u64::from_be_bytes(buf)
+            } else {
+                buf[..size].copy_from_slice(&values[..size]);
+                u64::from_le_bytes(buf)
+            }
+        })
+    }
+
+    fn set_value(&mut self, operand: &Operand, value: u128) -> Result<(), Error> {
+        self.with_operand_values_mut(operand, |values| {
+            let size = values.len().min(16);
+            let len = values.len();
+            for v in values.iter_mut() {
+                *v = 0;
+            }
+            if O::ENDIAN.is_big() {
+                values[len - size..].copy_from_slice(&value.to_be_bytes()[16 - size..]);
+            } else {
+                values[..size].copy_from_slice(&value.to_le_bytes()[..size]);
+            }
+        })
+    }
+
+    /// The `index`th integer or pointer argument, assuming the state is at
+    /// the entry of a function using the default prototype.
+    pub fn argument(&self, index: usize) -> Result<u64, Error> {
+        let location = self.registers.parameters()
+            .argument(index)
+            .ok_or(Error::UnsupportedArgument(index))?;
+        let operand = self.parameter_operand(&location)?;
+        self.get_value(&operand)
+    }
+
+    pub fn set_argument(&mut self, index: usize, value: u64) -> Result<(), Error> {
+        let location = self.registers.parameters()
+            .argument(index)
+            .ok_or(Error::UnsupportedArgument(index))?;
+        let operand = self.parameter_operand(&location)?;
+        self.set_value(&operand, value as u128)
+    }
+
+    /// A cursor over the variadic arguments of a function, starting from
+    /// the `first`th argument.
+    pub fn variadic_arguments(&self, first: usize) -> VariadicArguments {
+        VariadicArguments { index: first }
+    }
+
+    pub fn return_value(&self) -> Result<u64, Error> {
+        let parameters = self.registers.parameters();
+        let location = parameters.returns()
+            .first()
+            .ok_or(Error::UnsupportedReturnValue(0))?;
+        let operand = self.parameter_operand(location)?;
+        self.get_value(&operand)
+    }
+
+    /// Set a pointer-sized return value.
+    pub fn set_return_value(&mut self, value: u64) -> Result<(), Error> {
+        let size = self.memory_space_ref().address_size();
+        self.set_return_value_wide(value as u128, size)
+    }
+
+    /// Set a return value of `size` bytes; values wider than the first
+    /// return register are split over a register pair if the convention
+    /// defines one.
+    pub fn set_return_value_wide(&mut self, value: u128, size: usize) -> Result<(), Error> {
+        let parameters = self.registers.parameters();
+        let returns = parameters.returns();
+
+        if size <= MAX_POINTER_SIZE {
+            let register = returns.iter().find_map(|location| match location {
+                ParameterLocation::Register(operand) if operand.size() >= size => Some(operand),
+                _ => None,
+            });
+
+            if let Some(operand) = register {
+                return self.set_value(operand, value)
+            }
+        }
+
+        let pair = returns.iter().find_map(|location| match location {
+            ParameterLocation::RegisterPair(high, low) if high.size() + low.size() >= size => Some((high, low)),
+            _ => None,
+        });
+
+        if let Some((high, low)) = pair {
+            let shift = (low.size() * 8) as u32;
+            self.set_value(low, value)?;
+            return self.set_value(high, value.checked_shr(shift).unwrap_or(0))
+        }
+
+        Err(Error::UnsupportedReturnValue(size))
+    }
+}
+
+/// Reads successive integer or pointer arguments following the default
+/// prototype's argument locations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VariadicArguments {
+    index: usize,
+}
+
+impl VariadicArguments {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn next_argument<O: Order>(&mut self, state: &PCodeState<u8, O>) -> Result<u64, Error> {
+        let value = state.argument(self.index)?;
+        self.index += 1;
+        Ok(value)
+    }
 }
 
 impl<V: StateValue, O: Order> State for PCodeState<V, O> {
@@ -339,3 +476,140 @@ impl<V: StateValue, O: Order> StateOps for PCodeState<V, O> {
         self.memory.len()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use fugue::bytes::{BE, LE};
+
+    use crate::testing::{self, STACK, STACK_SIZE};
+
+    const SP: u64 = STACK + STACK_SIZE as u64 / 2;
+
+    fn register<V: FromStateValues<u8>, O: Order>(state: &PCodeState<u8, O>, name: &str) -> V {
+        let register = state.registers().register_by_name(name).unwrap();
+        state.registers().get_register(&register).unwrap()
+    }
+
+    fn set_register<V: IntoStateValues<u8>, O: Order>(state: &mut PCodeState<u8, O>, name: &str, value: V) {
+        let register = state.registers().register_by_name(name).unwrap();
+        state.registers_mut().set_register(&register, value).unwrap()
+    }
+
+    fn stack<V: ByteCast, O: Order>(state: &PCodeState<u8, O>, offset: u64) -> V {
+        let mut buf = vec![0u8; V::SIZEOF];
+        state.get_values(Address::from(SP + offset), &mut buf).unwrap();
+        V::from_bytes::<O>(&buf)
+    }
+
+    #[test]
+    fn arguments_registers_then_stack() {
+        let mut state = testing::pcode_state::<LE>("x86:LE:64:default", "gcc");
+
+        for i in 0..8 {
+            state.set_argument(i, 0x1000 + i as u64).unwrap();
+        }
+
+        // floating-point arguments (XMM0..) are skipped
+        let registers = ["RDI", "RSI", "RDX", "RCX", "R8", "R9"];
+        for (i, name) in registers.iter().enumerate() {
+            assert_eq!(register::<u64, _>(&state, name), 0x1000 + i as u64, "{}", name);
+        }
+
+        // then the stack, above the return address
+        assert_eq!(stack::<u64, _>(&state, 8), 0x1006);
+        assert_eq!(stack::<u64, _>(&state, 16), 0x1007);
+
+        set_register(&mut state, "RCX", 0xdead_beef_u64);
+        assert_eq!(state.argument(3).unwrap(), 0xdead_beef);
+        assert_eq!(state.argument(7).unwrap(), 0x1007);
+    }
+
+    #[test]
+    fn arguments_stack_only() {
+        let mut state = testing::pcode_state::<LE>("x86:LE:32:default", "gcc");
+
+        state.set_argument(0, 0x1_1122_3344).unwrap();
+        state.set_argument(1, 0x5566_7788).unwrap();
+
+        // pointer sized slots; wider values are truncated
+        assert_eq!(stack::<u32, _>(&state, 4), 0x1122_3344);
+        assert_eq!(stack::<u32, _>(&state, 8), 0x5566_7788);
+        assert_eq!(state.argument(0).unwrap(), 0x1122_3344);
+        assert_eq!(state.argument(1).unwrap(), 0x5566_7788);
+    }
+
+    #[test]
+    fn arguments_big_endian() {
+        let mut state = testing::pcode_state::<BE>("MIPS:BE:32:default", "default");
+
+        for i in 0..6 {
+            state.set_argument(i, 0x1122_3300 + i as u64).unwrap();
+        }
+
+        // f12 and f14 are skipped; the fifth argument follows the 16 bytes
+        // reserved for the register arguments
+        for (i, name) in ["a0", "a1", "a2", "a3"].iter().enumerate() {
+            assert_eq!(register::<u32, _>(&state, name), 0x1122_3300 + i as u32, "{}", name);
+        }
+        assert_eq!(stack::<u32, _>(&state, 16), 0x1122_3304);
+        assert_eq!(stack::<u32, _>(&state, 20), 0x1122_3305);
+        assert_eq!(state.argument(5).unwrap(), 0x1122_3305);
+    }
+
+    #[test]
+    fn variadic_arguments() {
+        let mut state = testing::pcode_state::<LE>("x86:LE:64:default", "gcc");
+
+        for i in 0..8 {
+            state.set_argument(i, i as u64 * 3).unwrap();
+        }
+
+        let mut arguments = state.variadic_arguments(4);
+        let values = (0..4)
+            .map(|_| arguments.next_argument(&state).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(values, [12, 15, 18, 21]);
+        assert_eq!(arguments.index(), 8);
+    }
+
+    #[test]
+    fn return_values() {
+        let mut state = testing::pcode_state::<LE>("x86:LE:64:default", "gcc");
+
+        state.set_return_value(0x1122_3344_5566_7788).unwrap();
+        assert_eq!(register::<u64, _>(&state, "RAX"), 0x1122_3344_5566_7788);
+        assert_eq!(state.return_value().unwrap(), 0x1122_3344_5566_7788);
+
+        // narrower values clear the rest of the register
+        state.set_return_value_wide(0xff, 1).unwrap();
+        assert_eq!(register::<u64, _>(&state, "RAX"), 0xff);
+    }
+
+    #[test]
+    fn return_value_pair_little_endian() {
+        let mut state = testing::pcode_state::<LE>("x86:LE:32:default", "gcc");
+
+        state.set_return_value_wide(0x1122_3344_5566_7788, 8).unwrap();
+        assert_eq!(register::<u32, _>(&state, "EDX"), 0x1122_3344);
+        assert_eq!(register::<u32, _>(&state, "EAX"), 0x5566_7788);
+        assert_eq!(state.return_value().unwrap(), 0x5566_7788);
+
+        assert!(matches!(state.set_return_value_wide(0, 16), Err(Error::UnsupportedReturnValue(16))));
+    }
+
+    #[test]
+    fn return_value_pair_big_endian() {
+        let mut state = testing::pcode_state::<BE>("MIPS:BE:32:default", "default");
+
+        state.set_return_value_wide(0x1122_3344_5566_7788, 8).unwrap();
+        assert_eq!(register::<u32, _>(&state, "v0"), 0x1122_3344);
+        assert_eq!(register::<u32, _>(&state, "v1"), 0x5566_7788);
+
+        state.set_return_value(0xdead_beef).unwrap();
+        assert_eq!(register::<u32, _>(&state, "v0"), 0xdead_beef);
+        assert_eq!(state.return_value().unwrap(), 0xdead_beef);
+    }
+}
@@ -3,7 +3,7 @@ use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
 use fugue::bytes::Order;
-use fugue::ir::convention::{Convention, ReturnAddress};
+use fugue::ir::convention::{Convention, PrototypeOperand, ReturnAddress};
 use fugue::ir::il::pcode::{Operand, Register};
 use fugue::ir::register::RegisterNames;
 use fugue::ir::{Address, Translator};
@@ -35,12 +35,133 @@ impl ReturnLocation {
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ParameterLocation {
+    Register(Operand),
+    // most significant part first
+    RegisterPair(Operand, Operand),
+    Relative(Operand, u64),
+}
+
+// The extra pop of a prototype declared with `extrapop="unknown"`, i.e.,
+// whose callee pops a number of bytes that depends on its arguments
+const UNKNOWN_EXTRA_POP: u64 = 0x8000;
+
+/// The integer and pointer parameter locations of a convention's default
+/// prototype; floating-point locations are not included.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ParameterLocations {
+    registers: Vec<Operand>,
+    stack: Option<(Operand, u64, u64)>,
+    returns: Vec<ParameterLocation>,
+    extra_pop: Option<u64>,
+    stack_shift: u64,
+}
+
+impl ParameterLocations {
+    pub fn from_convention(translator: &Translator, convention: &Convention) -> Self {
+        let prototype = convention.default_prototype();
+        let stack_pointer = Operand::from_varnode(translator, *convention.stack_pointer().varnode());
+        let pointer_size = translator.manager().default_space().address_size() as u64;
+
+        let is_float = |meta_type: Option<&str>| meta_type == Some("float");
+
+        let mut registers = Vec::new();
+        let mut stack = None;
+
+        for entry in prototype.inputs().iter().filter(|e| !is_float(e.meta_type())) {
+            match entry.operand() {
+                PrototypeOperand::Register { varnode, .. } => {
+                    registers.push(Operand::from_varnode(translator, *varnode));
+                },
+                PrototypeOperand::StackRelative(offset) if stack.is_none() => {
+                    let slot = entry.alignment().max(pointer_size);
+                    stack = Some((stack_pointer.clone(), *offset, slot));
+                },
+                _ => (),
+            }
+        }
+
+        let returns = prototype.outputs().iter()
+            .filter(|e| !is_float(e.meta_type()))
+            .filter_map(|entry| match entry.operand() {
+                PrototypeOperand::Register { varnode, .. } => {
+                    Some(ParameterLocation::Register(Operand::from_varnode(translator, *varnode)))
+                },
+                PrototypeOperand::RegisterJoin { first_varnode, second_varnode, .. } => {
+                    Some(ParameterLocation::RegisterPair(
+                        Operand::from_varnode(translator, *first_varnode),
+                        Operand::from_varnode(translator, *second_varnode),
+                    ))
+                },
+                PrototypeOperand::StackRelative(offset) => {
+                    Some(ParameterLocation::Relative(stack_pointer.clone(), *offset))
+                },
+            })
+            .collect();
+
+        let extra_pop = Some(prototype.extra_pop()).filter(|pop| *pop != UNKNOWN_EXTRA_POP);
+        let stack_shift = prototype.stack_shift();
+
+        Self { registers, stack, returns, extra_pop, stack_shift }
+    }
+
+    /// The location of the `index`th integer or pointer argument on entry
+    /// to a function; arguments are passed in registers, then on the stack.
+    pub fn argument(&self, index: usize) -> Option<ParameterLocation> {
+        if let Some(register) = self.registers.get(index) {
+            Some(ParameterLocation::Register(register.clone()))
+        } else {
+            let (stack_pointer, offset, slot) = self.stack.as_ref()?;
+            let index = (index - self.registers.len()) as u64;
+            Some(ParameterLocation::Relative(stack_pointer.clone(), offset + index * slot))
+        }
+    }
+
+    /// The size in bytes of the stack slots used by the first `arguments`
+    /// arguments.
+    pub fn stack_arguments_size(&self, arguments: usize) -> u64 {
+        self.stack.as_ref()
+            .map(|(_, _, slot)| arguments.saturating_sub(self.registers.len()) as u64 * slot)
+            .unwrap_or(0)
+    }
+
+    /// `true` if the callee pops its stack arguments, e.g., for stdcall;
+    /// that is, if it pops more than the return address on return.
+    pub fn callee_cleanup(&self) -> bool {
+        self.extra_pop.map_or(true, |pop| pop > self.stack_shift)
+    }
+
+    /// The size in bytes of the return address on the stack on entry to a
+    /// function; zero if it is passed in a register.
+    pub fn stack_shift(&self) -> u64 {
+        self.stack_shift
+    }
+
+    /// The bytes popped from the stack on return from a function taking
+    /// `arguments` integer or pointer arguments; unless the convention
+    /// fixes it, the callee pops the return address and its stack
+    /// arguments.
+    pub fn return_pop(&self, arguments: usize) -> u64 {
+        self.extra_pop.unwrap_or_else(|| self.stack_shift + self.stack_arguments_size(arguments))
+    }
+
+    pub fn argument_registers(&self) -> &[Operand] {
+        &self.registers
+    }
+
+    pub fn returns(&self) -> &[ParameterLocation] {
+        &self.returns
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RegisterState<T: StateValue, O: Order> {
     program_counter: Arc<Operand>,
     stack_pointer: Arc<Operand>,
     register_names: Arc<RegisterNames>,
     return_location: Arc<ReturnLocation>,
+    parameters: Arc<ParameterLocations>,
     inner: FlatState<T>,
     marker: PhantomData<O>,
 }
@@ -103,6 +224,7 @@ impl<V: StateValue, O: Order> State for RegisterState<V, O> {
             stack_pointer: self.stack_pointer.clone(),
             program_counter: self.program_counter.clone(),
             return_location: self.return_location.clone(),
+            parameters: self.parameters.clone(),
             register_names: self.register_names.clone(),
             marker: PhantomData,
         }
@@ -159,6 +281,7 @@ impl<T: StateValue, O: Order> RegisterState<T, O> {
         let program_counter = Arc::new(Operand::from_varnode(translator, *translator.program_counter()));
         let stack_pointer = Arc::new(Operand::from_varnode(translator, *convention.stack_pointer().varnode()));
         let return_location = Arc::new(ReturnLocation::from_convention(translator, convention));
+        let parameters = Arc::new(ParameterLocations::from_convention(translator, convention));
 
         let space = translator.manager().register_space();
         let register_names = translator.registers().clone();
@@ -171,6 +294,7 @@ impl<T: StateValue, O: Order> RegisterState<T, O> {
             program_counter,
             stack_pointer,
             return_location,
+            parameters,
             register_names,
             marker: PhantomData,
         }
@@ -188,6 +312,10 @@ impl<T: StateValue, O: Order> RegisterState<T, O> {
         self.return_location.clone()
     }
 
+    pub fn parameters(&self) -> Arc<ParameterLocations> {
+        self.parameters.clone()
+    }
+
     pub fn get_register_values(&self, register: &Register, values: &mut [T]) -> Result<(), Error> {
         let view = self.view_values(register.offset(), register.size())?;
         values.clone_from_slice(view);
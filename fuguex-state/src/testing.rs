@@ -0,0 +1,49 @@
+// Fixtures for tests that need a language; these load the SLEIGH
+// processor specifications found in `FUGUE_PROCESSORS` or, by default,
+// `data/processors` in the workspace.
+
+use std::path::PathBuf;
+
+use fugue::bytes::{Endian, Order};
+use fugue::ir::{Address, LanguageDB, Translator};
+
+use crate::paged::PagedState;
+use crate::pcode::PCodeState;
+
+pub(crate) const STACK: u64 = 0x8000;
+pub(crate) const STACK_SIZE: usize = 0x1000;
+
+pub(crate) fn translator(language: &str) -> Translator {
+    let path = std::env::var_os("FUGUE_PROCESSORS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../data/processors")));
+
+    let language_db = LanguageDB::from_directory_with(&path, true)
+        .unwrap_or_else(|e| panic!("processor specifications at {}: {}", path.display(), e));
+
+    let parts = language.splitn(4, ':').collect::<Vec<_>>();
+    let endian = if parts[1] == "BE" { Endian::Big } else { Endian::Little };
+    let bits = parts[2].parse::<usize>().unwrap();
+
+    language_db.lookup(parts[0], endian, bits, parts[3])
+        .unwrap_or_else(|| panic!("language {} not found", language))
+        .build()
+        .unwrap()
+}
+
+/// A state for `language` using `convention`, with a stack mapped at
+/// `STACK`; the stack pointer is set to its middle.
+pub(crate) fn pcode_state<O: Order>(language: &str, convention: &str) -> PCodeState<u8, O> {
+    let translator = translator(language);
+    let convention = translator.compiler_conventions()
+        .get(convention)
+        .unwrap_or_else(|| panic!("language {} has no convention {}", language, convention))
+        .clone();
+
+    let mut memory = PagedState::new(std::iter::empty(), translator.manager().default_space(), 0);
+    memory.static_mapping("stack", STACK, STACK_SIZE).unwrap();
+
+    let mut state = PCodeState::new(memory, &translator, &convention);
+    state.set_stack_pointer_value(Address::from(STACK + STACK_SIZE as u64 / 2)).unwrap();
+    state
+}
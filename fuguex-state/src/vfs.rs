@@ -0,0 +1,620 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::traits::State;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+/// The default limit on the size of a file or of captured output.
+pub const DEFAULT_MAX_FILE_SIZE: usize = 64 << 20;
+
+#[derive(Debug, Clone, Error)]
+pub enum Error {
+    #[error("bad file descriptor {0}")]
+    BadDescriptor(u64),
+    #[error("file `{0}` already exists")]
+    Exists(String),
+    #[error("invalid seek to offset {0}")]
+    InvalidSeek(i64),
+    #[error("file descriptor {0} would exceed the maximum file size")]
+    FileTooLarge(u64),
+    #[error("file `{0}` not found")]
+    NotFound(String),
+    #[error("file descriptor {0} not open for reading")]
+    NotReadable(u64),
+    #[error("file descriptor {0} not seekable")]
+    NotSeekable(u64),
+    #[error("file descriptor {0} not open for writing")]
+    NotWritable(u64),
+}
+
+impl Error {
+    /// The corresponding Linux error number.
+    pub fn errno(&self) -> u64 {
+        match self {
+            Self::BadDescriptor(_) | Self::NotReadable(_) | Self::NotWritable(_) => 9, // EBADF
+            Self::Exists(_) => 17, // EEXIST
+            Self::FileTooLarge(_) => 27, // EFBIG
+            Self::InvalidSeek(_) => 22, // EINVAL
+            Self::NotFound(_) => 2, // ENOENT
+            Self::NotSeekable(_) => 29, // ESPIPE
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Whence {
+    Start,
+    Current,
+    End,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    exclusive: bool,
+    truncate: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(mut self, read: bool) -> Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    pub fn create(mut self, create: bool) -> Self {
+        self.create = create;
+        self
+    }
+
+    /// Fail if the file exists; only has an effect with `create`.
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    File {
+        path: String,
+        offset: usize,
+        options: OpenOptions,
+    },
+}
+
+/// A sandboxed, in-memory filesystem and file-descriptor table.
+///
+/// Each file descriptor refers to an open file description, a
+/// `Descriptor`, which holds the file offset and access mode; as on Linux,
+/// descriptors duplicated with `dup` or `dup2` share it. File contents are
+/// shared between forks until written. Standard input is served from a
+/// fixed buffer and standard output and error are captured. Paths are normalised and relative paths are resolved
+/// against `/`. Writes that would grow a file, or captured output, beyond
+/// `max_file_size` bytes fail with `FileTooLarge`.
+#[derive(Debug, Clone)]
+pub struct FileSystem {
+    files: BTreeMap<String, Arc<Vec<u8>>>,
+    descriptors: BTreeMap<u64, u64>,
+    descriptions: BTreeMap<u64, Descriptor>,
+    stdin: Arc<Vec<u8>>,
+    stdin_offset: usize,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    max_file_size: usize,
+}
+
+impl Default for FileSystem {
+    fn default() -> Self {
+        let descriptions = vec![
+            (STDIN, Descriptor::Stdin),
+            (STDOUT, Descriptor::Stdout),
+            (STDERR, Descriptor::Stderr),
+        ];
+
+        Self {
+            files: BTreeMap::default(),
+            descriptors: descriptions.iter().map(|(fd, _)| (*fd, *fd)).collect(),
+            descriptions: descriptions.into_iter().collect(),
+            stdin: Arc::default(),
+            stdin_offset: 0,
+            stdout: Vec::default(),
+            stderr: Vec::default(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+}
+
+impl AsRef<Self> for FileSystem {
+    #[inline(always)]
+    fn as_ref(&self) -> &Self {
+        self
+    }
+}
+
+impl AsMut<Self> for FileSystem {
+    #[inline(always)]
+    fn as_mut(&mut self) -> &mut Self {
+        self
+    }
+}
+
+fn normalise(path: &str) -> String {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => { parts.pop(); },
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+impl FileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file<P: AsRef<str>>(mut self, path: P, bytes: Vec<u8>) -> Self {
+        self.add_file(path, bytes);
+        self
+    }
+
+    pub fn with_stdin(mut self, bytes: Vec<u8>) -> Self {
+        self.set_stdin(bytes);
+        self
+    }
+
+    pub fn with_max_file_size(mut self, bytes: usize) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    pub fn max_file_size(&self) -> usize {
+        self.max_file_size
+    }
+
+    pub fn add_file<P: AsRef<str>>(&mut self, path: P, bytes: Vec<u8>) {
+        self.files.insert(normalise(path.as_ref()), Arc::new(bytes));
+    }
+
+    pub fn remove_file<P: AsRef<str>>(&mut self, path: P) -> Result<(), Error> {
+        let path = normalise(path.as_ref());
+        self.files.remove(&path).map(|_| ()).ok_or(Error::NotFound(path))
+    }
+
+    pub fn set_stdin(&mut self, bytes: Vec<u8>) {
+        self.stdin = Arc::new(bytes);
+        self.stdin_offset = 0;
+    }
+
+    pub fn exists<P: AsRef<str>>(&self, path: P) -> bool {
+        self.files.contains_key(&normalise(path.as_ref()))
+    }
+
+    pub fn file<P: AsRef<str>>(&self, path: P) -> Option<&[u8]> {
+        self.files.get(&normalise(path.as_ref())).map(|bytes| &***bytes)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.files.iter().map(|(path, bytes)| (&**path, &***bytes))
+    }
+
+    /// The open file description that `fd` refers to.
+    pub fn descriptor(&self, fd: u64) -> Option<&Descriptor> {
+        self.descriptors.get(&fd).and_then(|id| self.descriptions.get(id))
+    }
+
+    pub fn descriptors(&self) -> impl Iterator<Item = (u64, &Descriptor)> {
+        self.descriptors.iter().map(move |(fd, id)| (*fd, &self.descriptions[id]))
+    }
+
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    pub fn take_stdout(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.stdout)
+    }
+
+    pub fn take_stderr(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.stderr)
+    }
+
+    fn next_fd(&self, from: u64) -> u64 {
+        (from..).find(|fd| !self.descriptors.contains_key(fd)).unwrap()
+    }
+
+    // Close `fd`, and the description it refers to if no other descriptor
+    // refers to it
+    fn release(&mut self, fd: u64) -> Result<(), Error> {
+        let id = self.descriptors.remove(&fd).ok_or(Error::BadDescriptor(fd))?;
+        if !self.descriptors.values().any(|other| *other == id) {
+            self.descriptions.remove(&id);
+        }
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<str>>(&mut self, path: P, options: OpenOptions) -> Result<u64, Error> {
+        let path = normalise(path.as_ref());
+
+        if let Some(bytes) = self.files.get_mut(&path) {
+            if options.create && options.exclusive {
+                return Err(Error::Exists(path))
+            }
+            if options.truncate && options.write {
+                *bytes = Arc::default();
+            }
+        } else if options.create {
+            self.files.insert(path.clone(), Arc::default());
+        } else {
+            return Err(Error::NotFound(path))
+        }
+
+        let id = self.descriptions.keys().next_back().map_or(0, |id| id + 1);
+        self.descriptions.insert(id, Descriptor::File { path, offset: 0, options });
+
+        let fd = self.next_fd(0);
+        self.descriptors.insert(fd, id);
+
+        Ok(fd)
+    }
+
+    pub fn close(&mut self, fd: u64) -> Result<(), Error> {
+        self.release(fd)
+    }
+
+    /// Duplicate `fd` onto the lowest free descriptor; both refer to the
+    /// same description, and so share a file offset.
+    pub fn dup(&mut self, fd: u64) -> Result<u64, Error> {
+        let id = *self.descriptors.get(&fd).ok_or(Error::BadDescriptor(fd))?;
+        let new_fd = self.next_fd(0);
+        self.descriptors.insert(new_fd, id);
+        Ok(new_fd)
+    }
+
+    /// Duplicate `fd` onto `new_fd`, closing `new_fd` if it is open.
+    pub fn dup2(&mut self, fd: u64, new_fd: u64) -> Result<u64, Error> {
+        let id = *self.descriptors.get(&fd).ok_or(Error::BadDescriptor(fd))?;
+        if fd != new_fd {
+            let _ = self.release(new_fd);
+            self.descriptors.insert(new_fd, id);
+        }
+        Ok(new_fd)
+    }
+
+    pub fn read(&mut self, fd: u64, size: usize) -> Result<Vec<u8>, Error> {
+        let id = *self.descriptors.get(&fd).ok_or(Error::BadDescriptor(fd))?;
+        match self.descriptions.get_mut(&id).ok_or(Error::BadDescriptor(fd))? {
+            Descriptor::Stdin => {
+                let start = self.stdin_offset.min(self.stdin.len());
+                let end = start.saturating_add(size).min(self.stdin.len());
+                self.stdin_offset = end;
+                Ok(self.stdin[start..end].to_vec())
+            },
+            Descriptor::File { path, offset, options } => {
+                if !options.read {
+                    return Err(Error::NotReadable(fd))
+                }
+                let bytes = self.files.get(path).ok_or_else(|| Error::NotFound(path.clone()))?;
+                let start = (*offset).min(bytes.len());
+                let end = start.saturating_add(size).min(bytes.len());
+                // the offset may be past the end of the file
+                *offset += end - start;
+                Ok(bytes[start..end].to_vec())
+            },
+            _ => Err(Error::NotReadable(fd)),
+        }
+    }
+
+    /// Return the last `size` bytes read from `fd` to its input, e.g., if
+    /// they could not be delivered.
+    pub fn unread(&mut self, fd: u64, size: usize) -> Result<(), Error> {
+        let id = *self.descriptors.get(&fd).ok_or(Error::BadDescriptor(fd))?;
+        match self.descriptions.get_mut(&id).ok_or(Error::BadDescriptor(fd))? {
+            Descriptor::Stdin => {
+                self.stdin_offset = self.stdin_offset.saturating_sub(size);
+                Ok(())
+            },
+            Descriptor::File { offset, options, .. } if options.read => {
+                *offset = offset.saturating_sub(size);
+                Ok(())
+            },
+            _ => Err(Error::NotReadable(fd)),
+        }
+    }
+
+    pub fn write(&mut self, fd: u64, data: &[u8]) -> Result<usize, Error> {
+        let limit = self.max_file_size;
+        let check = |length: usize| length.checked_add(data.len())
+            .filter(|end| *end <= limit)
+            .ok_or(Error::FileTooLarge(fd));
+
+        let id = *self.descriptors.get(&fd).ok_or(Error::BadDescriptor(fd))?;
+        match self.descriptions.get_mut(&id).ok_or(Error::BadDescriptor(fd))? {
+            Descriptor::Stdout => {
+                check(self.stdout.len())?;
+                self.stdout.extend_from_slice(data)
+            },
+            Descriptor::Stderr => {
+                check(self.stderr.len())?;
+                self.stderr.extend_from_slice(data)
+            },
+            Descriptor::File { path, offset, options } => {
+                if !options.write {
+                    return Err(Error::NotWritable(fd))
+                }
+                let bytes = Arc::make_mut(
+                    self.files.get_mut(path).ok_or_else(|| Error::NotFound(path.clone()))?
+                );
+                if options.append {
+                    *offset = bytes.len();
+                }
+                let end = check(*offset)?;
+                if bytes.len() < end {
+                    bytes.resize(end, 0);
+                }
+                bytes[*offset..end].copy_from_slice(data);
+                *offset = end;
+            },
+            Descriptor::Stdin => return Err(Error::NotWritable(fd)),
+        }
+        Ok(data.len())
+    }
+
+    pub fn seek(&mut self, fd: u64, offset: i64, whence: Whence) -> Result<u64, Error> {
+        let id = *self.descriptors.get(&fd).ok_or(Error::BadDescriptor(fd))?;
+        match self.descriptions.get_mut(&id).ok_or(Error::BadDescriptor(fd))? {
+            Descriptor::File { path, offset: current, .. } => {
+                let size = self.files.get(path).map(|bytes| bytes.len()).unwrap_or(0) as i64;
+                let position = match whence {
+                    Whence::Start => offset,
+                    Whence::Current => (*current as i64).saturating_add(offset),
+                    Whence::End => size.saturating_add(offset),
+                };
+                if position < 0 {
+                    return Err(Error::InvalidSeek(position))
+                }
+                *current = position as usize;
+                Ok(position as u64)
+            },
+            _ => Err(Error::NotSeekable(fd)),
+        }
+    }
+}
+
+impl State for FileSystem {
+    type Error = Error;
+
+    fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    fn restore(&mut self, other: &Self) {
+        self.files = other.files.clone();
+        self.descriptors.clone_from(&other.descriptors);
+        self.descriptions.clone_from(&other.descriptions);
+        self.stdin = other.stdin.clone();
+        self.stdin_offset = other.stdin_offset;
+        self.stdout.clone_from(&other.stdout);
+        self.stderr.clone_from(&other.stderr);
+        self.max_file_size = other.max_file_size;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_bounded() {
+        let mut files = FileSystem::new()
+            .with_file("/out", Vec::new())
+            .with_max_file_size(8);
+
+        let fd = files.open("/out", OpenOptions::new().write(true)).unwrap();
+
+        assert_eq!(files.write(fd, b"12345678").unwrap(), 8);
+        assert_eq!(files.write(fd, b"9").unwrap_err().errno(), 27);
+
+        files.seek(fd, i64::MAX, Whence::Start).unwrap();
+        assert!(matches!(files.write(fd, b"x"), Err(Error::FileTooLarge(_))));
+        assert_eq!(files.file("/out"), Some(&b"12345678"[..]));
+
+        assert_eq!(files.write(STDOUT, b"12345678").unwrap(), 8);
+        assert!(files.write(STDOUT, b"9").is_err());
+        assert_eq!(files.stdout(), b"12345678");
+    }
+
+    fn read_only() -> OpenOptions {
+        OpenOptions::new().read(true)
+    }
+
+    #[test]
+    fn dup_shares_offset() {
+        let mut files = FileSystem::new().with_file("/file", b"abcdef".to_vec());
+
+        let fd = files.open("/file", read_only()).unwrap();
+        let copy = files.dup(fd).unwrap();
+        assert_eq!(copy, fd + 1);
+
+        assert_eq!(files.read(fd, 2).unwrap(), b"ab");
+        assert_eq!(files.read(copy, 2).unwrap(), b"cd");
+
+        assert_eq!(files.seek(copy, 1, Whence::Start).unwrap(), 1);
+        assert_eq!(files.read(fd, 2).unwrap(), b"bc");
+
+        // the description stays open until its last descriptor is closed
+        files.close(fd).unwrap();
+        assert_eq!(files.read(copy, 2).unwrap(), b"de");
+        assert!(matches!(files.read(fd, 1), Err(Error::BadDescriptor(_))));
+
+        // descriptors opened separately do not share offsets
+        let other = files.open("/file", read_only()).unwrap();
+        assert_eq!(other, fd);
+        assert_eq!(files.read(other, 2).unwrap(), b"ab");
+        assert_eq!(files.read(copy, 2).unwrap(), b"f");
+    }
+
+    #[test]
+    fn dup2_replaces() {
+        let mut files = FileSystem::new().with_file("/out", Vec::new());
+
+        let fd = files.open("/out", OpenOptions::new().write(true)).unwrap();
+        assert_eq!(files.dup2(fd, STDOUT).unwrap(), STDOUT);
+
+        files.write(STDOUT, b"abc").unwrap();
+        files.write(fd, b"def").unwrap();
+        assert_eq!(files.file("/out"), Some(&b"abcdef"[..]));
+        assert!(files.stdout().is_empty());
+
+        // onto itself, nothing changes
+        assert_eq!(files.dup2(fd, fd).unwrap(), fd);
+        files.close(fd).unwrap();
+        files.write(STDOUT, b"g").unwrap();
+        assert_eq!(files.file("/out"), Some(&b"abcdefg"[..]));
+
+        assert!(matches!(files.dup2(fd, STDERR), Err(Error::BadDescriptor(_))));
+        assert_eq!(files.descriptor(STDERR), Some(&Descriptor::Stderr));
+
+        // onto a closed descriptor
+        assert_eq!(files.dup2(STDERR, 10).unwrap(), 10);
+        files.write(10, b"error").unwrap();
+        assert_eq!(files.stderr(), b"error");
+        assert_eq!(files.descriptors().map(|(fd, _)| fd).collect::<Vec<_>>(), [STDIN, STDOUT, STDERR, 10]);
+    }
+
+    #[test]
+    fn open_options() {
+        let mut files = FileSystem::new().with_file("/dir/file", b"abc".to_vec());
+
+        assert!(matches!(files.open("/missing", read_only()), Err(Error::NotFound(_))));
+        assert!(matches!(files.open("/dir/file", OpenOptions::new().create(true).exclusive(true)), Err(Error::Exists(_))));
+
+        // paths are normalised
+        let fd = files.open("dir/./x/../file", read_only()).unwrap();
+        assert_eq!(files.read(fd, 8).unwrap(), b"abc");
+        assert!(matches!(files.write(fd, b"x"), Err(Error::NotWritable(_))));
+
+        let fd = files.open("/dir/file", OpenOptions::new().write(true).append(true)).unwrap();
+        files.seek(fd, 0, Whence::Start).unwrap();
+        files.write(fd, b"de").unwrap();
+        assert_eq!(files.file("/dir/file"), Some(&b"abcde"[..]));
+        assert!(matches!(files.read(fd, 1), Err(Error::NotReadable(_))));
+
+        let fd = files.open("/dir/file", OpenOptions::new().write(true).truncate(true)).unwrap();
+        assert_eq!(files.file("/dir/file"), Some(&b""[..]));
+        files.write(fd, b"x").unwrap();
+
+        files.open("/new", OpenOptions::new().create(true)).unwrap();
+        assert!(files.exists("/new"));
+        assert_eq!(files.files().map(|(path, _)| path).collect::<Vec<_>>(), ["/dir/file", "/new"]);
+    }
+
+    #[test]
+    fn stdin() {
+        let mut files = FileSystem::new().with_stdin(b"input".to_vec());
+
+        assert_eq!(files.read(STDIN, 2).unwrap(), b"in");
+        files.unread(STDIN, 1).unwrap();
+        assert_eq!(files.read(STDIN, 8).unwrap(), b"nput");
+        assert_eq!(files.read(STDIN, 8).unwrap(), b"");
+
+        // shared with its duplicates
+        files.set_stdin(b"again".to_vec());
+        let copy = files.dup(STDIN).unwrap();
+        assert_eq!(files.read(copy, 2).unwrap(), b"ag");
+        assert_eq!(files.read(STDIN, 2).unwrap(), b"ai");
+
+        assert!(matches!(files.write(STDIN, b"x"), Err(Error::NotWritable(_))));
+        assert!(matches!(files.seek(STDIN, 0, Whence::Start), Err(Error::NotSeekable(_))));
+        assert!(matches!(files.read(STDOUT, 1), Err(Error::NotReadable(_))));
+        assert!(matches!(files.unread(STDOUT, 1), Err(Error::NotReadable(_))));
+    }
+
+    #[test]
+    fn seek() {
+        let mut files = FileSystem::new().with_file("/file", b"abcdef".to_vec());
+        let fd = files.open("/file", OpenOptions::new().read(true).write(true)).unwrap();
+
+        assert_eq!(files.seek(fd, 2, Whence::Start).unwrap(), 2);
+        assert_eq!(files.seek(fd, 1, Whence::Current).unwrap(), 3);
+        assert_eq!(files.read(fd, 1).unwrap(), b"d");
+        assert_eq!(files.seek(fd, -1, Whence::End).unwrap(), 5);
+        assert_eq!(files.read(fd, 8).unwrap(), b"f");
+
+        assert!(matches!(files.seek(fd, -7, Whence::End), Err(Error::InvalidSeek(-1))));
+        assert_eq!(files.seek(fd, 0, Whence::Current).unwrap(), 6);
+
+        // reading past the end is empty; writing there fills the gap
+        assert_eq!(files.seek(fd, 8, Whence::Start).unwrap(), 8);
+        assert_eq!(files.read(fd, 1).unwrap(), b"");
+        files.write(fd, b"x").unwrap();
+        assert_eq!(files.file("/file"), Some(&b"abcdef\0\0x"[..]));
+    }
+
+    #[test]
+    fn fork_restore() {
+        let mut files = FileSystem::new()
+            .with_file("/file", b"abcdef".to_vec())
+            .with_stdin(b"input".to_vec());
+
+        let fd = files.open("/file", OpenOptions::new().read(true).write(true)).unwrap();
+        let copy = files.dup(fd).unwrap();
+        assert_eq!(files.read(fd, 1).unwrap(), b"a");
+
+        let snapshot = files.fork();
+
+        files.write(copy, b"XY").unwrap();
+        files.read(STDIN, 2).unwrap();
+        files.write(STDOUT, b"out").unwrap();
+        files.close(fd).unwrap();
+        files.remove_file("/file").unwrap();
+
+        // the fork is unaffected, and has its own offsets
+        let mut fork = snapshot.fork();
+        assert_eq!(fork.read(copy, 2).unwrap(), b"bc");
+        assert_eq!(snapshot.file("/file"), Some(&b"abcdef"[..]));
+
+        files.restore(&snapshot);
+        assert_eq!(files.file("/file"), Some(&b"abcdef"[..]));
+        assert_eq!(files.read(fd, 1).unwrap(), b"b");
+        assert_eq!(files.read(copy, 1).unwrap(), b"c");
+        assert_eq!(files.read(STDIN, 2).unwrap(), b"in");
+        assert!(files.stdout().is_empty());
+    }
+}